@@ -1,12 +0,0 @@
-#!/usr/bin/sh
-
-sudo iptables -P INPUT ACCEPT
-sudo iptables -P OUTPUT ACCEPT
-sudo iptables -P FORWARD ACCEPT
-sudo iptables -F
-sudo iptables -X
-sudo ip6tables -P INPUT ACCEPT
-sudo ip6tables -P OUTPUT ACCEPT
-sudo ip6tables -P FORWARD ACCEPT
-sudo ip6tables -F
-sudo ip6tables -X
@@ -0,0 +1,94 @@
+//! A fake VPN client that doesn't connect anywhere. Used to test the connect flow.
+
+use super::{StopSignal, VpnClient, VpnStatus};
+use crate::error::{CliError, CliResult};
+use linkage_firewall::FirewallException;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Pretends to connect and records which methods were called.
+pub(crate) struct FakeClient {
+    exceptions: Vec<FirewallException>,
+    /// The interface that comes up after starting. Waiting for it fails if this is `None`.
+    interface_name: Option<String>,
+    status: VpnStatus,
+    /// The number of times waiting for the interface fails before it comes up.
+    pub failing_attempts: u32,
+    /// The DNS servers that are pushed by the pretended VPN server.
+    pub dns_servers: Vec<IpAddr>,
+    /// The names of the methods that were called, in order.
+    pub calls: Vec<&'static str>,
+}
+
+impl FakeClient {
+    /// Returns a new instance of FakeClient that requires the supplied exceptions and brings up
+    /// the supplied interface.
+    pub fn new(exceptions: Vec<FirewallException>, interface_name: Option<&str>) -> FakeClient {
+        FakeClient {
+            exceptions,
+            interface_name: interface_name.map(String::from),
+            status: VpnStatus::Stopped,
+            failing_attempts: 0,
+            dns_servers: Vec::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    /// Pretends that the client exited on its own with the supplied exit code.
+    pub fn exit(&mut self, code: Option<i32>) {
+        self.status = VpnStatus::Exited(code);
+    }
+}
+
+impl VpnClient for FakeClient {
+    fn get_exceptions(&self) -> CliResult<Vec<FirewallException>> {
+        Ok(self.exceptions.clone())
+    }
+
+    fn get_tunnel_interface(&self) -> Option<String> {
+        self.interface_name.clone()
+    }
+
+    fn start(&mut self) -> CliResult<()> {
+        self.calls.push("start");
+        self.status = VpnStatus::Connecting;
+        Ok(())
+    }
+
+    fn wait_for_interface(
+        &mut self,
+        _timeout: Duration,
+        stop_signal: &mut StopSignal,
+    ) -> CliResult<String> {
+        self.calls.push("wait_for_interface");
+        if let Some(signal) = stop_signal()? {
+            self.stop()?;
+            return Err(CliError::Interrupted(signal));
+        }
+        // Like the real clients, the client is stopped if connecting fails
+        let interface_name = match self.interface_name.clone() {
+            Some(interface_name) if self.failing_attempts == 0 => interface_name,
+            _ => {
+                self.failing_attempts = self.failing_attempts.saturating_sub(1);
+                self.status = VpnStatus::Stopped;
+                return Err(CliError::InterfaceParseError);
+            }
+        };
+        self.status = VpnStatus::Connected(interface_name.clone());
+        Ok(interface_name)
+    }
+
+    fn stop(&mut self) -> CliResult<()> {
+        self.calls.push("stop");
+        self.status = VpnStatus::Stopped;
+        Ok(())
+    }
+
+    fn get_status(&mut self) -> CliResult<VpnStatus> {
+        Ok(self.status.clone())
+    }
+
+    fn get_dns_servers(&self) -> Vec<IpAddr> {
+        self.dns_servers.clone()
+    }
+}
@@ -0,0 +1,328 @@
+//! A client for the management interface of OpenVPN. Linkage uses it to receive the real-time
+//! state notifications of OpenVPN instead of parsing its log output. The log messages are only
+//! used for the options that the VPN server pushed, because they aren't reported otherwise.
+
+use crate::error::{CliError, CliResult};
+use std::io::{ErrorKind, Read, Write};
+use std::net::IpAddr;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// The prefix of real-time state notifications.
+const STATE_NOTIFICATION_PREFIX: &str = ">STATE:";
+/// The prefix of real-time log messages.
+const LOG_NOTIFICATION_PREFIX: &str = ">LOG:";
+/// The start of the options in the log message of the reply to a push request.
+const PUSH_REPLY_PREFIX: &str = "PUSH_REPLY,";
+
+/// A state of OpenVPN as reported by the management interface.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum OpenVpnState {
+    /// Connecting to the VPN server, this includes waiting for replies, the authentication and
+    /// getting the configuration.
+    Connecting,
+    /// Assigning the address to the tunnel interface.
+    AssignIp,
+    /// Adding the routes to the tunnel interface.
+    AddRoutes,
+    /// The connection is established. Contains the local IPv4 and IPv6 address of the tunnel
+    /// interface, if there are any.
+    Connected {
+        address_v4: Option<IpAddr>,
+        address_v6: Option<IpAddr>,
+    },
+    /// The connection was lost or restarted and is about to be established again.
+    Reconnecting,
+    /// OpenVPN is shutting down.
+    Exiting,
+    /// Any other state, e.g. `RESOLVE` or `TCP_CONNECT`.
+    Other(String),
+}
+
+impl OpenVpnState {
+    /// Parses a real-time state notification, e.g.
+    /// `>STATE:1607791360,CONNECTED,SUCCESS,10.8.0.2,1.1.1.1,1194,,,fd00::2`. Returns `None` if the
+    /// line isn't a state notification.
+    pub fn from_notification(line: &str) -> Option<OpenVpnState> {
+        let fields: Vec<&str> = line.strip_prefix(STATE_NOTIFICATION_PREFIX)?.split(',').collect();
+        // The first field is the time of the notification
+        let state = *fields.get(1)?;
+        let address = |i: usize| fields.get(i).and_then(|a| a.parse().ok());
+
+        Some(match state {
+            "CONNECTING" | "WAIT" | "AUTH" | "GET_CONFIG" => OpenVpnState::Connecting,
+            "ASSIGN_IP" => OpenVpnState::AssignIp,
+            "ADD_ROUTES" => OpenVpnState::AddRoutes,
+            "CONNECTED" => OpenVpnState::Connected {
+                address_v4: address(3),
+                address_v6: address(8),
+            },
+            "RECONNECTING" => OpenVpnState::Reconnecting,
+            "EXITING" => OpenVpnState::Exiting,
+            _ => OpenVpnState::Other(state.to_string()),
+        })
+    }
+}
+
+/// Parses the DNS servers of the `dhcp-option DNS` and `dhcp-option DNS6` options of a log
+/// message of a push reply, e.g. `>LOG:1607791360,,PUSH: Received control message:
+/// 'PUSH_REPLY,dhcp-option DNS 10.8.0.1,route-gateway 10.8.0.1'`. Returns `None` if the line isn't
+/// a push reply.
+fn parse_pushed_dns_servers(line: &str) -> Option<Vec<IpAddr>> {
+    let message = line.strip_prefix(LOG_NOTIFICATION_PREFIX)?;
+    let options = &message[message.find(PUSH_REPLY_PREFIX)? + PUSH_REPLY_PREFIX.len()..];
+    // The message ends with the quote around the reply
+    let options = options.split('\'').next().unwrap_or_default();
+
+    Some(options
+        .split(',')
+        .filter_map(|option| {
+            let words: Vec<&str> = option.split_whitespace().collect();
+            match words.as_slice() {
+                ["dhcp-option", "DNS", address] | ["dhcp-option", "DNS6", address] => {
+                    address.parse().ok()
+                }
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// A connection to the management interface of OpenVPN on a unix socket.
+pub(crate) struct ManagementInterface {
+    stream: UnixStream,
+    /// Data that was received, but doesn't form a complete line yet.
+    buffer: Vec<u8>,
+    /// The DNS servers of the last push reply. OpenVPN logs the reply before the connection is
+    /// established, so they are up to date once the `Connected` state is received.
+    dns_servers: Vec<IpAddr>,
+}
+
+impl ManagementInterface {
+    /// Connects to the management interface on the supplied unix socket.
+    pub fn connect(path: &Path) -> CliResult<ManagementInterface> {
+        Ok(ManagementInterface {
+            stream: UnixStream::connect(path)?,
+            buffer: Vec::new(),
+            dns_servers: Vec::new(),
+        })
+    }
+
+    /// Sends the supplied command to OpenVPN. The reply is received like any other line.
+    pub fn send_command(&mut self, command: &str) -> CliResult<()> {
+        self.stream.write_all(format!("{}\n", command).as_bytes())?;
+        Ok(())
+    }
+
+    /// Enables the real-time state notifications and log messages and releases the hold, so
+    /// OpenVPN starts to connect. OpenVPN has to be started with `--management-hold`, so no
+    /// notification is missed.
+    pub fn start(&mut self) -> CliResult<()> {
+        self.send_command("state on")?;
+        self.send_command("log on")?;
+        self.send_command("hold release")
+    }
+
+    /// Returns the DNS servers that the VPN server pushed with the last push reply that was
+    /// received.
+    pub fn dns_servers(&self) -> &[IpAddr] {
+        &self.dns_servers
+    }
+
+    /// Sets how long reading may block before failing with `TimedOut` or `WouldBlock`. `None`
+    /// blocks indefinitely.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> CliResult<()> {
+        self.stream.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Blocks until the next state notification is received and returns it. Other lines are
+    /// skipped.
+    pub fn next_state(&mut self) -> CliResult<OpenVpnState> {
+        loop {
+            let line = self.read_line()?;
+            if let Some(state) = OpenVpnState::from_notification(&line) {
+                return Ok(state);
+            }
+        }
+    }
+
+    /// Returns the state notifications that were received so far, without blocking.
+    pub fn pending_states(&mut self) -> CliResult<Vec<OpenVpnState>> {
+        self.stream.set_nonblocking(true)?;
+        let mut states = Vec::new();
+        let result = loop {
+            match self.read_line() {
+                Ok(line) => states.extend(OpenVpnState::from_notification(&line)),
+                Err(CliError::Io(e)) if e.kind() == ErrorKind::WouldBlock => break Ok(states),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+
+        result
+    }
+
+    /// Reads a single line without the line break. Incomplete lines are kept in the buffer, so
+    /// nothing gets lost if the stream is non-blocking.
+    fn read_line(&mut self) -> CliResult<String> {
+        loop {
+            if let Some(position) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=position).collect();
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                if let Some(dns_servers) = parse_pushed_dns_servers(&line) {
+                    self.dns_servers = dns_servers;
+                }
+                return Ok(line);
+            }
+
+            let mut chunk = [0; 1024];
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(CliError::Management(String::from("the connection was closed")));
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::thread;
+
+    #[test]
+    fn test_openvpn_state_from_notification() {
+        assert_eq!(
+            Some(OpenVpnState::Connecting),
+            OpenVpnState::from_notification(">STATE:1607791360,CONNECTING,,,,,,")
+        );
+        assert_eq!(
+            Some(OpenVpnState::Connecting),
+            OpenVpnState::from_notification(">STATE:1607791360,GET_CONFIG,,,,,,")
+        );
+        assert_eq!(
+            Some(OpenVpnState::AssignIp),
+            OpenVpnState::from_notification(">STATE:1607791360,ASSIGN_IP,,10.8.0.2,,,,")
+        );
+        assert_eq!(
+            Some(OpenVpnState::Connected {
+                address_v4: Some("10.8.0.2".parse().unwrap()),
+                address_v6: None,
+            }),
+            OpenVpnState::from_notification(
+                ">STATE:1607791360,CONNECTED,SUCCESS,10.8.0.2,1.1.1.1,1194,,"
+            )
+        );
+        assert_eq!(
+            Some(OpenVpnState::Connected {
+                address_v4: None,
+                address_v6: Some("fd00::2".parse().unwrap()),
+            }),
+            OpenVpnState::from_notification(
+                ">STATE:1607791360,CONNECTED,SUCCESS,,1.1.1.1,1194,192.168.1.2,50000,fd00::2"
+            )
+        );
+        assert_eq!(
+            Some(OpenVpnState::Reconnecting),
+            OpenVpnState::from_notification(">STATE:1607791360,RECONNECTING,ping-restart,,,,,")
+        );
+        assert_eq!(
+            Some(OpenVpnState::Exiting),
+            OpenVpnState::from_notification(">STATE:1607791360,EXITING,SIGTERM,,,,,")
+        );
+        assert_eq!(
+            Some(OpenVpnState::Other(String::from("RESOLVE"))),
+            OpenVpnState::from_notification(">STATE:1607791360,RESOLVE,,,,,,")
+        );
+
+        assert_eq!(None, OpenVpnState::from_notification(">INFO:OpenVPN Management Interface"));
+        assert_eq!(None, OpenVpnState::from_notification("SUCCESS: hold release succeeded"));
+        assert_eq!(None, OpenVpnState::from_notification(">STATE:"));
+    }
+
+    #[test]
+    fn test_parse_pushed_dns_servers() -> CliResult<()> {
+        assert_eq!(
+            Some(vec!["10.8.0.1".parse::<IpAddr>()?, "fd00::1".parse()?]),
+            parse_pushed_dns_servers(
+                ">LOG:1607791360,,PUSH: Received control message: 'PUSH_REPLY,redirect-gateway \
+                 def1,dhcp-option DNS 10.8.0.1,dhcp-option DOMAIN vpn,dhcp-option DNS6 fd00::1,\
+                 route-gateway 10.8.0.1,peer-id 0'"
+            )
+        );
+        // A reply without DNS servers replaces the ones of a previous reply
+        assert_eq!(
+            Some(Vec::new()),
+            parse_pushed_dns_servers(
+                ">LOG:1607791360,,PUSH: Received control message: 'PUSH_REPLY,ping 10'"
+            )
+        );
+
+        assert_eq!(None, parse_pushed_dns_servers(">LOG:1607791360,,Initialization Sequence"));
+        assert_eq!(None, parse_pushed_dns_servers(">STATE:1607791360,CONNECTING,,,,,,"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_management_interface() -> CliResult<()> {
+        let path = env::temp_dir().join(format!("linkage-test-management-{}.sock", process::id()));
+        let listener = UnixListener::bind(&path)?;
+
+        // Pretends to be OpenVPN
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b">INFO:OpenVPN Management Interface Version 5\n").unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut commands = Vec::new();
+            for _ in 0..3 {
+                let mut command = String::new();
+                reader.read_line(&mut command).unwrap();
+                commands.push(command);
+            }
+            stream.write_all(b"SUCCESS: real-time state notification set to ON\n").unwrap();
+            stream.write_all(b">LOG:1607791360,,PUSH: Received control message: 'PUSH_REPLY,")
+                .unwrap();
+            stream.write_all(b"dhcp-option DNS 10.8.0.1,ifconfig 10.8.0.2 255.255.255.0'\n")
+                .unwrap();
+            stream.write_all(b">STATE:1607791360,CONNECTING,,,,,,\n>STATE:1607791361,").unwrap();
+            stream.write_all(b"CONNECTED,SUCCESS,10.8.0.2,1.1.1.1,1194,,\n").unwrap();
+            stream.write_all(b">STATE:1607791362,RECONNECTING,ping-restart,,,,,\n").unwrap();
+            stream.write_all(b">STATE:1607791362,").unwrap();
+
+            (commands, stream)
+        });
+
+        let mut management = ManagementInterface::connect(&path)?;
+        management.start()?;
+        assert_eq!(OpenVpnState::Connecting, management.next_state()?);
+        assert_eq!(["10.8.0.1".parse::<IpAddr>()?], management.dns_servers());
+        // The notification was split across two writes
+        assert_eq!(OpenVpnState::Connected {
+            address_v4: Some("10.8.0.2".parse()?),
+            address_v6: None,
+        }, management.next_state()?);
+
+        let (commands, stream) = server.join().unwrap();
+        assert_eq!(vec![
+            String::from("state on\n"), String::from("log on\n"), String::from("hold release\n"),
+        ], commands);
+        // The incomplete notification is kept
+        assert_eq!(vec![OpenVpnState::Reconnecting], management.pending_states()?);
+        assert!(management.pending_states()?.is_empty());
+
+        drop(stream);
+        assert!(matches!(management.next_state(), Err(CliError::Management(_))));
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! The VPN clients that Linkage can use to connect to VPN servers.
+
+#[cfg(test)]
+pub(crate) mod fake;
+pub(crate) mod management;
+pub(crate) mod openvpn;
+pub(crate) mod wireguard;
+
+use crate::error::{CliError, CliResult};
+use linkage_firewall::FirewallException;
+use openvpn::OpenVpnClient;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use wireguard::{has_interface_section, WireGuardClient};
+
+/// Returns the stop signal that was received, so a client can give up connecting.
+pub(crate) type StopSignal<'a> = dyn FnMut() -> CliResult<Option<i32>> + 'a;
+
+/// The status of a VPN client.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum VpnStatus {
+    /// The client wasn't started yet or was stopped.
+    Stopped,
+    /// The client was started, but the tunnel interface isn't up yet.
+    Connecting,
+    /// The tunnel interface with the name is up.
+    Connected(String),
+    /// The client exited on its own with the exit code, if there is one.
+    Exited(Option<i32>),
+}
+
+/// Exposes the methods that are needed to connect to a VPN server using a specific VPN client.
+pub(crate) trait VpnClient {
+    /// Returns the exceptions that the firewall needs so the client can reach the VPN servers of
+    /// its configuration.
+    fn get_exceptions(&self) -> CliResult<Vec<FirewallException>>;
+    /// Returns the name of the tunnel interface if it's already known before starting the client.
+    fn get_tunnel_interface(&self) -> Option<String>;
+    /// Starts connecting to the VPN server. Called after the firewall was set up.
+    fn start(&mut self) -> CliResult<()>;
+    /// Blocks until the tunnel interface is up and returns its name. Fails if the interface isn't
+    /// up within the supplied timeout. While waiting, `stop_signal` is called regularly and the
+    /// client is stopped with `CliError::Interrupted` once it returns a signal.
+    fn wait_for_interface(
+        &mut self,
+        timeout: Duration,
+        stop_signal: &mut StopSignal,
+    ) -> CliResult<String>;
+    /// Closes the connection to the VPN server. Does nothing if the client isn't running.
+    fn stop(&mut self) -> CliResult<()>;
+    /// Returns the current status of the client.
+    fn get_status(&mut self) -> CliResult<VpnStatus>;
+    /// Returns the DNS servers that the VPN server pushed for the current connection, which
+    /// Linkage applies to the resolver of the system.
+    fn get_dns_servers(&self) -> Vec<IpAddr>;
+}
+
+/// Returns the addresses of the supplied host of a VPN server, which is either an IP address or a
+/// host name. Host names are resolved using the resolver of the system, so this has to happen
+/// before the firewall is set up.
+fn resolve_host(host: &str, port: u16) -> CliResult<Vec<IpAddr>> {
+    if let Ok(address) = host.parse() {
+        return Ok(vec![address]);
+    }
+
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    let resolved = (host, port).to_socket_addrs()
+        .map_err(|_| CliError::ResolveHost(host.to_string()))?;
+    for address in resolved {
+        if !addresses.contains(&address.ip()) {
+            addresses.push(address.ip());
+        }
+    }
+    if addresses.is_empty() {
+        return Err(CliError::ResolveHost(host.to_string()));
+    }
+
+    Ok(addresses)
+}
+
+/// The VPN clients that are supported.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum VpnType {
+    OpenVpn,
+    WireGuard,
+}
+
+impl VpnType {
+    /// Returns the type that matches the supplied configuration file. `.conf` files are WireGuard
+    /// configurations if they have an `[Interface]` section, since OpenVPN uses the extension as
+    /// well. Everything else, including files that can't be read, is an OpenVPN configuration.
+    pub fn from_path(path: &Path) -> VpnType {
+        if path.extension().and_then(|e| e.to_str()) == Some("conf")
+            && fs::read_to_string(path).is_ok_and(|content| has_interface_section(&content))
+        {
+            VpnType::WireGuard
+        } else {
+            VpnType::OpenVpn
+        }
+    }
+
+    /// Returns a new client of this type that uses the supplied configuration file.
+    pub fn new_client(&self, config_file_path: &str) -> Box<dyn VpnClient> {
+        match self {
+            VpnType::OpenVpn => Box::new(OpenVpnClient::new(config_file_path)),
+            VpnType::WireGuard => Box::new(WireGuardClient::new(config_file_path)),
+        }
+    }
+}
+
+impl FromStr for VpnType {
+    type Err = CliError;
+
+    /// Converts the supplied string into VpnType.
+    /// # Values
+    /// - OpenVpn: `openvpn`
+    /// - WireGuard: `wireguard`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openvpn" => Ok(VpnType::OpenVpn),
+            "wireguard" => Ok(VpnType::WireGuard),
+            _ => Err(CliError::UnknownVpnType(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_vpn_type_from_path() -> CliResult<()> {
+        let directory = env::temp_dir().join(format!("linkage-vpn-type-test-{}", process::id()));
+        fs::create_dir_all(&directory)?;
+        let wireguard_path = directory.join("wg0.conf");
+        fs::write(&wireguard_path, "[Interface]\nPrivateKey = key\n")?;
+        let openvpn_path = directory.join("client.conf");
+        fs::write(&openvpn_path, "client\nremote 185.65.135.1 1194\n")?;
+
+        assert_eq!(VpnType::WireGuard, VpnType::from_path(&wireguard_path));
+        // OpenVPN uses `.conf` on Linux as well
+        assert_eq!(VpnType::OpenVpn, VpnType::from_path(&openvpn_path));
+        assert_eq!(VpnType::OpenVpn, VpnType::from_path(&directory.join("missing.conf")));
+        assert_eq!(VpnType::OpenVpn, VpnType::from_path(Path::new("config.ovpn")));
+        assert_eq!(VpnType::OpenVpn, VpnType::from_path(Path::new("config")));
+
+        fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_vpn_type_from_str() {
+        assert_eq!(VpnType::OpenVpn, VpnType::from_str("openvpn").unwrap());
+        assert_eq!(VpnType::WireGuard, VpnType::from_str("wireguard").unwrap());
+        assert!(matches!(VpnType::from_str("ipsec"), Err(CliError::UnknownVpnType(_))));
+    }
+
+    #[test]
+    fn test_vpn_type_new_client() -> CliResult<()> {
+        // Nothing is started when creating a client
+        assert_eq!(VpnStatus::Stopped, VpnType::OpenVpn.new_client("a.ovpn").get_status()?);
+        assert_eq!(VpnStatus::Stopped, VpnType::WireGuard.new_client("wg0.conf").get_status()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_host() -> CliResult<()> {
+        assert_eq!(vec!["185.65.135.1".parse::<IpAddr>()?], resolve_host("185.65.135.1", 1194)?);
+        assert_eq!(vec!["2a03:1b20::1".parse::<IpAddr>()?], resolve_host("2a03:1b20::1", 1194)?);
+
+        // Host names are resolved to all of their addresses
+        let addresses = resolve_host("localhost", 1194)?;
+        assert!(addresses.iter().all(|a| a.is_loopback()));
+        assert!(addresses.contains(&"127.0.0.1".parse()?));
+
+        // The reserved top level domain `.invalid` never resolves
+        assert!(matches!(resolve_host("vpn.invalid", 1194), Err(CliError::ResolveHost(_))));
+
+        Ok(())
+    }
+}
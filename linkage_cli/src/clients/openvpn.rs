@@ -0,0 +1,543 @@
+//! Support for OpenVPN using `.ovpn` configuration files.
+
+use super::management::{ManagementInterface, OpenVpnState};
+use super::{resolve_host, StopSignal, VpnClient, VpnStatus};
+use crate::consts;
+use crate::error::{CliError, CliResult};
+use crate::interfaces::get_interface_by_address;
+use linkage_firewall::{
+    FirewallException, FirewallExceptionProtocol, FirewallExceptionProtocolError,
+};
+use ovpnfile::{self, ConfigDirective as OvpnConfigDirective};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The name of the OpenVPN binary.
+const OPENVPN_BINARY_NAME: &str = "openvpn";
+/// The port that OpenVPN uses if neither the `remote` nor the `port` directive contains one.
+const DEFAULT_PORT: u16 = 1194;
+/// The protocol that OpenVPN uses if neither the `remote` nor the `proto` directive contains one.
+const DEFAULT_PROTOCOL: &str = "udp";
+/// How often connecting to the management interface is attempted after starting `openvpn`.
+const MANAGEMENT_CONNECT_ATTEMPTS: u32 = 50;
+/// The time between the attempts to connect to the management interface.
+const MANAGEMENT_CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+/// How long reading a state notification blocks before checking for a stop signal.
+const STOP_SIGNAL_INTERVAL: Duration = Duration::from_millis(100);
+/// The verbosity of `openvpn`, which is the lowest one that logs the pushed options.
+const OPENVPN_VERBOSITY: &str = "3";
+/// How many lines of the output of `openvpn` are kept to explain why connecting failed.
+const OUTPUT_LINES: usize = 10;
+
+/// Connects using `openvpn`. Its state is followed using the management interface on a unix
+/// socket.
+pub(crate) struct OpenVpnClient {
+    config_file_path: String,
+    /// The unix socket of the management interface.
+    management_socket_path: PathBuf,
+    /// The running `openvpn` process.
+    child: Option<Child>,
+    /// The connection to the management interface of the running `openvpn` process.
+    management: Option<ManagementInterface>,
+    /// The name of the tunnel interface while it's up.
+    interface_name: Option<String>,
+    /// The last lines of the output of `openvpn`.
+    output: Arc<Mutex<VecDeque<String>>>,
+    /// The thread that reads the output of `openvpn`.
+    output_thread: Option<JoinHandle<()>>,
+}
+
+impl OpenVpnClient {
+    /// Returns a new instance of OpenVpnClient that uses the supplied configuration file.
+    pub fn new(config_file_path: &str) -> OpenVpnClient {
+        OpenVpnClient {
+            config_file_path: config_file_path.to_string(),
+            management_socket_path: PathBuf::from(consts::OPENVPN_MANAGEMENT_SOCKET_PATH),
+            child: None,
+            management: None,
+            interface_name: None,
+            output: Arc::new(Mutex::new(VecDeque::new())),
+            output_thread: None,
+        }
+    }
+
+    /// Connects to the management interface of the started `openvpn` process. OpenVPN creates the
+    /// socket shortly after starting, so this is retried for a few seconds.
+    fn connect_management(&mut self) -> CliResult<ManagementInterface> {
+        for _ in 0..MANAGEMENT_CONNECT_ATTEMPTS {
+            if let Some(status) = self.child.as_mut().map(Child::try_wait).transpose()?.flatten() {
+                return Err(CliError::Management(format!(
+                    "openvpn exited with code {:?} before the management interface was available",
+                    status.code(),
+                )));
+            }
+            match ManagementInterface::connect(&self.management_socket_path) {
+                Ok(management) => return Ok(management),
+                // The socket doesn't exist yet or isn't listening yet
+                Err(CliError::Io(e)) if matches!(
+                    e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused
+                ) => thread::sleep(MANAGEMENT_CONNECT_INTERVAL),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(CliError::Management(String::from("the management interface isn't available")))
+    }
+
+    /// Follows the state notifications until the connection is established and returns the
+    /// interface that has the address of the tunnel. Fails with `TimedOut` after the deadline and
+    /// with `Interrupted` once a stop signal was received.
+    fn follow_until_connected(
+        &mut self,
+        deadline: Instant,
+        stop_signal: &mut StopSignal,
+    ) -> CliResult<String> {
+        loop {
+            if let Some(signal) = stop_signal()? {
+                return Err(CliError::Interrupted(signal));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(io::Error::from(ErrorKind::TimedOut).into());
+            }
+
+            let management = self.management.as_mut().ok_or(CliError::InterfaceParseError)?;
+            management.set_timeout(Some(remaining.min(STOP_SIGNAL_INTERVAL)))?;
+            // Incomplete lines stay buffered, so reading again after a timeout loses nothing
+            let state = match management.next_state() {
+                Ok(state) => state,
+                Err(CliError::Io(e)) if is_read_timeout(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(interface_name) = self.handle_state(state)? {
+                self.management.as_mut().unwrap().set_timeout(None)?;
+                return Ok(interface_name);
+            }
+        }
+    }
+
+    /// Stops `openvpn` after connecting failed and returns an error with the supplied reason and
+    /// the last lines of its output.
+    fn fail(&mut self, reason: String) -> CliError {
+        // The output is only complete once openvpn exited. Stopping can't fail in a way that
+        // would be more helpful than the reason.
+        let _ = self.stop();
+        if let Some(output_thread) = self.output_thread.take() {
+            let _ = output_thread.join();
+        }
+
+        let output = match self.output.lock() {
+            Ok(output) => output.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        };
+        CliError::OpenVpn { reason, output }
+    }
+
+    /// Updates the interface name with the supplied state. Returns the name once the connection is
+    /// established.
+    fn handle_state(&mut self, state: OpenVpnState) -> CliResult<Option<String>> {
+        match state {
+            OpenVpnState::Connected { address_v4, address_v6 } => {
+                let interface_name = get_interface_name(address_v4.or(address_v6))?;
+                self.interface_name = Some(interface_name.clone());
+                Ok(Some(interface_name))
+            }
+            OpenVpnState::Exiting => {
+                Err(CliError::Management(String::from("openvpn is exiting")))
+            }
+            // The tunnel is down until the connection is established again
+            OpenVpnState::Reconnecting => {
+                self.interface_name = None;
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl VpnClient for OpenVpnClient {
+    /// Returns an exception for every `remote` of the configuration file.
+    fn get_exceptions(&self) -> CliResult<Vec<FirewallException>> {
+        parse_configuration_file(File::open(&self.config_file_path)?)
+    }
+
+    /// OpenVPN picks the name of the tunnel interface when connecting.
+    fn get_tunnel_interface(&self) -> Option<String> {
+        None
+    }
+
+    /// Starts `openvpn` with the management interface and holds it until the state notifications
+    /// are enabled, so none of them is missed.
+    fn start(&mut self) -> CliResult<()> {
+        if let Some(parent) = self.management_socket_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // A socket of a previous session would be found before the new one is created
+        match fs::remove_file(&self.management_socket_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let mut child = Command::new(OPENVPN_BINARY_NAME)
+            .arg("--config")
+            .arg(&self.config_file_path)
+            .arg("--management")
+            .arg(&self.management_socket_path)
+            .arg("unix")
+            .arg("--management-hold")
+            // The pushed options are only logged from this verbosity on
+            .arg("--verb")
+            .arg(OPENVPN_VERBOSITY)
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        // The output has to be read continuously, otherwise openvpn blocks once the pipe is full
+        self.output = Arc::new(Mutex::new(VecDeque::new()));
+        if let Some(stdout) = child.stdout.take() {
+            let output = self.output.clone();
+            self.output_thread = Some(thread::spawn(move || collect_output(stdout, &output)));
+        }
+        self.child = Some(child);
+
+        let management = self.connect_management().and_then(|mut management| {
+            management.start()?;
+            Ok(management)
+        });
+        match management {
+            Ok(management) => self.management = Some(management),
+            Err(e) => return Err(self.fail(e.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Follows the state notifications until the connection is established and returns the
+    /// interface that has the address of the tunnel. `openvpn` is stopped if that fails, e.g.
+    /// because it exited or didn't connect in time.
+    fn wait_for_interface(
+        &mut self,
+        timeout: Duration,
+        stop_signal: &mut StopSignal,
+    ) -> CliResult<String> {
+        match self.follow_until_connected(Instant::now() + timeout, stop_signal) {
+            Ok(interface_name) => Ok(interface_name),
+            // Linkage is asked to exit, so the output of openvpn doesn't matter
+            Err(CliError::Interrupted(signal)) => {
+                self.stop()?;
+                Err(CliError::Interrupted(signal))
+            }
+            Err(CliError::Io(e)) if is_read_timeout(&e) => {
+                Err(self.fail(format!(
+                    "the connection wasn't established within {} seconds", timeout.as_secs()
+                )))
+            }
+            Err(e) => Err(self.fail(e.to_string())),
+        }
+    }
+
+    /// Sends `SIGTERM` to `openvpn` and waits until it exited.
+    fn stop(&mut self) -> CliResult<()> {
+        self.management = None;
+        if let Some(mut child) = self.child.take() {
+            // The process ID might already be reused if it exited on its own
+            if child.try_wait()?.is_none() {
+                unsafe {
+                    libc::kill(child.id() as i32, libc::SIGTERM);
+                }
+            }
+            child.wait()?;
+        }
+        self.interface_name = None;
+
+        Ok(())
+    }
+
+    /// Returns the status using the state notifications that were received since the last call.
+    fn get_status(&mut self) -> CliResult<VpnStatus> {
+        let child = match self.child.as_mut() {
+            Some(child) => child,
+            None => return Ok(VpnStatus::Stopped),
+        };
+        if let Some(status) = child.try_wait()? {
+            return Ok(VpnStatus::Exited(status.code()));
+        }
+
+        let states = match self.management.as_mut() {
+            Some(management) => management.pending_states()?,
+            None => Vec::new(),
+        };
+        for state in states {
+            // OpenVPN exits on its own afterwards, which is reported once the process is gone
+            if state != OpenVpnState::Exiting {
+                self.handle_state(state)?;
+            }
+        }
+
+        Ok(match &self.interface_name {
+            Some(interface_name) => VpnStatus::Connected(interface_name.clone()),
+            None => VpnStatus::Connecting,
+        })
+    }
+
+    /// Returns the DNS servers of the `dhcp-option DNS` options that the VPN server pushed.
+    fn get_dns_servers(&self) -> Vec<IpAddr> {
+        match &self.management {
+            Some(management) => management.dns_servers().to_vec(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Keeps the last lines that are read from the supplied output of `openvpn` until it's closed.
+fn collect_output<R: Read>(reader: R, output: &Mutex<VecDeque<String>>) {
+    for line in BufReader::new(reader).split(b'\n') {
+        let line = match line {
+            Ok(line) => String::from_utf8_lossy(&line).trim_end().to_string(),
+            Err(_) => break,
+        };
+        if let Ok(mut output) = output.lock() {
+            if output.len() == OUTPUT_LINES {
+                output.pop_front();
+            }
+            output.push_back(line);
+        }
+    }
+}
+
+/// Returns the name of the interface that has the supplied tunnel address. OpenVPN doesn't report
+/// the name of the device in its state notifications, but the address identifies it reliably,
+/// regardless of the `dev` directive.
+fn get_interface_name(address: Option<IpAddr>) -> CliResult<String> {
+    address
+        .map(get_interface_by_address)
+        .transpose()?
+        .flatten()
+        .ok_or(CliError::InterfaceParseError)
+}
+
+/// Returns whether the supplied error means that reading from the management interface timed out.
+/// Signals interrupt reads with a timeout even though their handlers restart system calls.
+fn is_read_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+    )
+}
+
+/// Parses the supplied configuration file using ovpnfile and returns an exception for every
+/// address of every `remote`. Remotes without a port or protocol use the ones of the `port` and
+/// `proto` directives, which default to 1194 and UDP like in OpenVPN. Host names are resolved now,
+/// since OpenVPN resolves them again after the firewall is up.
+fn parse_configuration_file<R: Read>(f: R) -> CliResult<Vec<FirewallException>> {
+    let parsed_file = ovpnfile::parse(f).map_err(|_| CliError::OvpnFile)?;
+
+    // Get the default settings
+    let mut default_port = DEFAULT_PORT.to_string();
+    let mut default_protocol = DEFAULT_PROTOCOL.to_string();
+    for d in parsed_file.directives() {
+        match d {
+            OvpnConfigDirective::Port { port } | OvpnConfigDirective::Rport { port } => {
+                default_port = port;
+            }
+            OvpnConfigDirective::Proto { p } => default_protocol = p,
+            _ => {}
+        }
+    }
+
+    // Create the firewall exceptions
+    let mut exceptions: Vec<FirewallException> = Vec::new();
+    for d in parsed_file.directives() {
+        if let OvpnConfigDirective::Remote { host, port, proto } = d {
+            let port: u16 = port.as_deref().unwrap_or(&default_port).parse()?;
+            let protocol = parse_protocol(proto.as_deref().unwrap_or(&default_protocol))?;
+            for address in resolve_host(&host, port)? {
+                exceptions.push(FirewallException::new(address, port, protocol));
+            }
+        }
+    }
+
+    Ok(exceptions)
+}
+
+/// Parses the supplied OpenVPN protocol. OpenVPN restricts the address family with a `4` or `6`
+/// suffix and TCP clients may be marked with `-client`, e.g. `udp6` or `tcp4-client`.
+fn parse_protocol(protocol: &str) -> CliResult<FirewallExceptionProtocol> {
+    let protocol = protocol.trim_end_matches("-client").trim_end_matches(&['4', '6'][..]);
+    match protocol {
+        "udp" | "tcp" => Ok(protocol.parse()?),
+        _ => Err(FirewallExceptionProtocolError.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+
+    #[test]
+    fn test_parse_configuration_file() -> CliResult<()> {
+        let exceptions = parse_configuration_file("client
+dev tun
+proto udp
+remote 185.65.135.1 1194
+remote 185.65.135.2 443 tcp
+".as_bytes())?;
+
+        assert_eq!(vec![
+            FirewallException::new("185.65.135.1".parse()?, 1194, FirewallExceptionProtocol::UDP),
+            FirewallException::new("185.65.135.2".parse()?, 443, FirewallExceptionProtocol::TCP),
+        ], exceptions);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_configuration_file_defaults() -> CliResult<()> {
+        // Without `port` and `proto`, OpenVPN uses 1194 and UDP
+        let exceptions = parse_configuration_file("client
+remote 185.65.135.1
+remote 185.65.135.2 443
+remote 185.65.135.3 443 tcp-client
+".as_bytes())?;
+        assert_eq!(vec![
+            FirewallException::new("185.65.135.1".parse()?, 1194, FirewallExceptionProtocol::UDP),
+            FirewallException::new("185.65.135.2".parse()?, 443, FirewallExceptionProtocol::UDP),
+            FirewallException::new("185.65.135.3".parse()?, 443, FirewallExceptionProtocol::TCP),
+        ], exceptions);
+
+        // The defaults can be changed, host names are resolved to all of their addresses
+        let exceptions = parse_configuration_file("client
+port 443
+proto tcp6
+remote localhost
+".as_bytes())?;
+        let v4 = FirewallException::new("127.0.0.1".parse()?, 443, FirewallExceptionProtocol::TCP);
+        let v6 = FirewallException::new("::1".parse()?, 443, FirewallExceptionProtocol::TCP);
+        assert!(exceptions.contains(&v4));
+        assert!(exceptions.iter().all(|e| *e == v4 || *e == v6));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_configuration_file_errors() {
+        assert!(matches!(
+            parse_configuration_file("remote 185.65.135.1 port".as_bytes()),
+            Err(CliError::ParseInt(_))
+        ));
+        assert!(matches!(
+            parse_configuration_file("remote 185.65.135.1 1194 sctp".as_bytes()),
+            Err(CliError::FirewallExceptionProtocol(_))
+        ));
+        assert!(matches!(
+            parse_configuration_file("remote vpn.invalid".as_bytes()),
+            Err(CliError::ResolveHost(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_protocol() -> CliResult<()> {
+        for protocol in ["udp", "udp4", "udp6"].iter() {
+            assert_eq!(FirewallExceptionProtocol::UDP, parse_protocol(protocol)?);
+        }
+        for protocol in ["tcp", "tcp6", "tcp-client", "tcp4-client"].iter() {
+            assert_eq!(FirewallExceptionProtocol::TCP, parse_protocol(protocol)?);
+        }
+        assert!(parse_protocol("icmp").is_err());
+        assert!(parse_protocol("tcp-server6").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() -> CliResult<()> {
+        let mut client = OpenVpnClient::new("config.ovpn");
+        assert_eq!(VpnStatus::Stopped, client.get_status()?);
+        // Stopping a client that isn't running does nothing
+        client.stop()?;
+        assert_eq!(VpnStatus::Stopped, client.get_status()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_output() {
+        let output = Mutex::new(VecDeque::new());
+        let lines: Vec<String> = (0..15).map(|i| format!("line {}", i)).collect();
+        collect_output(lines.join("\n").as_bytes(), &output);
+
+        // Only the last lines are kept
+        assert_eq!(lines[5..], *output.lock().unwrap().iter().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_get_interface_by_address() -> CliResult<()> {
+        assert_eq!(Some(String::from("lo")), get_interface_by_address("127.0.0.1".parse()?)?);
+        // TEST-NET-1 is reserved for documentation
+        assert_eq!(None, get_interface_by_address("192.0.2.1".parse()?)?);
+        assert!(matches!(get_interface_name(None), Err(CliError::InterfaceParseError)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_follow_until_connected() -> CliResult<()> {
+        let path = env::temp_dir().join(format!("linkage-test-follow-{}.sock", process::id()));
+        let listener = UnixListener::bind(&path)?;
+        let mut client = OpenVpnClient::new("config.ovpn");
+        client.management = Some(ManagementInterface::connect(&path)?);
+        let (mut stream, _) = listener.accept()?;
+        stream.write_all(b">STATE:1607791361,CONNECTED,SUCCESS,")?;
+
+        // A stop signal ends waiting early
+        let mut checks = 0;
+        let result = client.follow_until_connected(
+            Instant::now() + Duration::from_secs(10),
+            &mut || {
+                checks += 1;
+                Ok(Some(libc::SIGINT).filter(|_| checks == 3))
+            },
+        );
+        assert!(matches!(result, Err(CliError::Interrupted(libc::SIGINT))));
+
+        // The incomplete notification isn't lost while checking for stop signals
+        stream.write_all(b"127.0.0.1,1.1.1.1,1194,,\n")?;
+        assert_eq!("lo", client.follow_until_connected(
+            Instant::now() + Duration::from_secs(10), &mut || Ok(None),
+        )?);
+        let result = client.follow_until_connected(
+            Instant::now() + Duration::from_millis(10), &mut || Ok(None),
+        );
+        assert!(matches!(result, Err(CliError::Io(e)) if e.kind() == ErrorKind::TimedOut));
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_state() -> CliResult<()> {
+        let mut client = OpenVpnClient::new("config.ovpn");
+        assert_eq!(None, client.handle_state(OpenVpnState::Connecting)?);
+        assert_eq!(Some(String::from("lo")), client.handle_state(OpenVpnState::Connected {
+            address_v4: Some("127.0.0.1".parse()?),
+            address_v6: None,
+        })?);
+        assert_eq!(Some(String::from("lo")), client.interface_name);
+
+        assert_eq!(None, client.handle_state(OpenVpnState::Reconnecting)?);
+        assert_eq!(None, client.interface_name);
+        assert!(matches!(client.handle_state(OpenVpnState::Exiting), Err(CliError::Management(_))));
+
+        Ok(())
+    }
+}
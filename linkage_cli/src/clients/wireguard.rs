@@ -0,0 +1,406 @@
+//! Support for WireGuard using `wg-quick` style configuration files.
+
+use super::{resolve_host, StopSignal, VpnClient, VpnStatus};
+use crate::command::{CommandRunner, SystemCommandRunner};
+use crate::error::{CliError, CliResult};
+use linkage_firewall::{FirewallException, FirewallExceptionProtocol};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+/// The name of the tool that brings WireGuard interfaces up and down.
+const WG_QUICK_BINARY_NAME: &str = "wg-quick";
+/// The name of the tool that shows the state of WireGuard interfaces.
+const WG_BINARY_NAME: &str = "wg";
+/// The name of the tool that shows the network interfaces.
+const IP_BINARY_NAME: &str = "ip";
+
+/// Connects by bringing a WireGuard interface up using `wg-quick`.
+pub(crate) struct WireGuardClient {
+    config_file_path: String,
+    /// The name of the interface while it's up.
+    interface_name: Option<String>,
+    /// Runs `wg-quick` and the commands that check the state of the interface.
+    runner: Box<dyn CommandRunner>,
+}
+
+impl WireGuardClient {
+    /// Returns a new instance of WireGuardClient that uses the supplied configuration file.
+    pub fn new(config_file_path: &str) -> WireGuardClient {
+        WireGuardClient::with_runner(config_file_path, Box::new(SystemCommandRunner))
+    }
+
+    /// Returns a new instance of WireGuardClient that uses the supplied configuration file and runs
+    /// its commands using the supplied runner.
+    pub fn with_runner(config_file_path: &str, runner: Box<dyn CommandRunner>) -> WireGuardClient {
+        WireGuardClient {
+            config_file_path: config_file_path.to_string(),
+            interface_name: None,
+            runner,
+        }
+    }
+
+    /// Runs `wg-quick` with the supplied action for the configuration file. `wg-quick` takes
+    /// arguments that look like an interface name, e.g. `wg0.conf`, as the name of a
+    /// configuration in `/etc/wireguard`, so the path always gets a directory.
+    fn run_wg_quick(&self, action: &str) -> CliResult<()> {
+        let config_file_path = if self.config_file_path.contains('/') {
+            self.config_file_path.clone()
+        } else {
+            format!("./{}", self.config_file_path)
+        };
+        let output = self.runner.run(WG_QUICK_BINARY_NAME, &[action, &config_file_path])?;
+        if !output.success() {
+            return Err(CliError::WgQuick(output.code));
+        }
+
+        Ok(())
+    }
+}
+
+impl VpnClient for WireGuardClient {
+    /// Returns an exception for every address of the `Endpoint` of every `[Peer]`.
+    fn get_exceptions(&self) -> CliResult<Vec<FirewallException>> {
+        parse_configuration_file(&fs::read_to_string(&self.config_file_path)?)
+    }
+
+    /// The tunnel interface is named after the configuration file.
+    fn get_tunnel_interface(&self) -> Option<String> {
+        get_interface_name(&self.config_file_path).ok()
+    }
+
+    /// Brings the interface up using `wg-quick`.
+    fn start(&mut self) -> CliResult<()> {
+        self.run_wg_quick("up")?;
+        self.interface_name = Some(get_interface_name(&self.config_file_path)?);
+
+        Ok(())
+    }
+
+    /// The interface is already up once `wg-quick` returns, so this doesn't block.
+    fn wait_for_interface(
+        &mut self,
+        _timeout: Duration,
+        _stop_signal: &mut StopSignal,
+    ) -> CliResult<String> {
+        self.interface_name.clone().ok_or(CliError::InterfaceParseError)
+    }
+
+    /// Brings the interface down using `wg-quick`.
+    fn stop(&mut self) -> CliResult<()> {
+        if self.interface_name.take().is_some() {
+            self.run_wg_quick("down")?;
+        }
+
+        Ok(())
+    }
+
+    /// The client is stopped if the interface doesn't exist anymore, e.g. because it was brought
+    /// down by someone else. It's connecting until the first handshake with one of the peers.
+    fn get_status(&mut self) -> CliResult<VpnStatus> {
+        let interface_name = match &self.interface_name {
+            Some(interface_name) => interface_name.clone(),
+            None => return Ok(VpnStatus::Stopped),
+        };
+
+        let link = self.runner.run(IP_BINARY_NAME, &["link", "show", "dev", &interface_name])?;
+        if !link.success() {
+            // There's nothing left that `wg-quick down` could remove
+            self.interface_name = None;
+            return Ok(VpnStatus::Stopped);
+        }
+
+        let handshakes = self.runner.run(
+            WG_BINARY_NAME, &["show", &interface_name, "latest-handshakes"],
+        )?;
+        if !handshakes.success() {
+            return Err(CliError::Wg(handshakes.code));
+        }
+        Ok(if has_handshake(&handshakes.stdout) {
+            VpnStatus::Connected(interface_name)
+        } else {
+            VpnStatus::Connecting
+        })
+    }
+
+    /// `wg-quick` applies the `DNS` of the configuration itself, so there's nothing to apply.
+    fn get_dns_servers(&self) -> Vec<IpAddr> {
+        Vec::new()
+    }
+}
+
+/// Parses the supplied WireGuard configuration and returns an exception for every address of the
+/// `Endpoint` of every `[Peer]`. Host names are resolved now, since `wg-quick` resolves them again
+/// after the firewall is up. WireGuard always uses UDP.
+fn parse_configuration_file(content: &str) -> CliResult<Vec<FirewallException>> {
+    let mut exceptions = Vec::new();
+    let mut section = String::new();
+
+    for line in content.lines() {
+        // Everything after a `#` is a comment
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| CliError::WireGuardConfig(format!("invalid line {}", line)))?;
+        // Keys are case-insensitive for wg-quick
+        if section.eq_ignore_ascii_case("Peer") && key.trim().eq_ignore_ascii_case("Endpoint") {
+            let (host, port) = parse_endpoint(value.trim())?;
+            for address in resolve_host(host, port)? {
+                exceptions.push(FirewallException::new(
+                    address, port, FirewallExceptionProtocol::UDP,
+                ));
+            }
+        }
+    }
+
+    Ok(exceptions)
+}
+
+/// Returns whether the supplied configuration has an `[Interface]` section, which every
+/// configuration of `wg-quick` needs.
+pub(super) fn has_interface_section(content: &str) -> bool {
+    content.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .any(|line| line.eq_ignore_ascii_case("[Interface]"))
+}
+
+/// Splits the supplied endpoint into the host and the port. IPv6 addresses have to be enclosed in
+/// brackets, e.g. `[2001:db8::1]:51820`.
+fn parse_endpoint(endpoint: &str) -> CliResult<(&str, u16)> {
+    let invalid = || CliError::WireGuardConfig(
+        format!("the endpoint {} isn't a host with a port", endpoint)
+    );
+
+    let (host, port) = match endpoint.strip_prefix('[') {
+        Some(rest) => rest.split_once("]:").ok_or_else(invalid)?,
+        None => endpoint.rsplit_once(':').ok_or_else(invalid)?,
+    };
+    // Without brackets, the colons of an IPv6 address would be ambiguous
+    if host.is_empty() || (!endpoint.starts_with('[') && host.contains(':')) {
+        return Err(invalid());
+    }
+
+    Ok((host, port.parse().map_err(|_| invalid())?))
+}
+
+/// Returns whether any peer in the supplied output of `wg show <interface> latest-handshakes`
+/// completed a handshake. Peers without a handshake have a timestamp of 0. Without peers, there's
+/// nothing to wait for.
+fn has_handshake(latest_handshakes: &str) -> bool {
+    let timestamps: Vec<u64> = latest_handshakes.lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter_map(|timestamp| timestamp.parse().ok())
+        .collect();
+
+    timestamps.is_empty() || timestamps.iter().any(|t| *t > 0)
+}
+
+/// Returns the name of the interface that `wg-quick` creates for the supplied configuration file,
+/// which is the name of the file without the extension.
+fn get_interface_name(config_file_path: &str) -> CliResult<String> {
+    Path::new(config_file_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(String::from)
+        .ok_or_else(|| CliError::WireGuardConfig(format!("invalid path {}", config_file_path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::FakeCommandRunner;
+
+    #[test]
+    fn test_parse_configuration_file() -> CliResult<()> {
+        let exceptions = parse_configuration_file("
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+Address = 10.64.0.2/32, fc00:bbbb:bbbb:bb01::2/128
+DNS = 10.64.0.1
+# Endpoint = 9.9.9.9:1
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 0.0.0.0/0, ::/0
+Endpoint = 185.65.135.1:51820 # Comment
+
+[peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+endpoint=[2a03:1b20:1:f011::a01f]:3233
+")?;
+
+        assert_eq!(vec![
+            FirewallException::new("185.65.135.1".parse()?, 51820, FirewallExceptionProtocol::UDP),
+            FirewallException::new(
+                "2a03:1b20:1:f011::a01f".parse()?, 3233, FirewallExceptionProtocol::UDP,
+            ),
+        ], exceptions);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_configuration_file_host_name() -> CliResult<()> {
+        // Every address of the host name gets its own exception
+        let exceptions = parse_configuration_file("[Peer]\nEndpoint = localhost:51820\n")?;
+        let v4 = FirewallException::new(
+            "127.0.0.1".parse()?, 51820, FirewallExceptionProtocol::UDP,
+        );
+        let v6 = FirewallException::new("::1".parse()?, 51820, FirewallExceptionProtocol::UDP);
+        assert!(exceptions.contains(&v4));
+        assert!(exceptions.iter().all(|e| *e == v4 || *e == v6));
+
+        assert!(matches!(
+            parse_configuration_file("[Peer]\nEndpoint = vpn.invalid:51820\n"),
+            Err(CliError::ResolveHost(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_configuration_file_errors() {
+        // Peers without an endpoint don't need an exception
+        assert!(parse_configuration_file("[Peer]\nPublicKey = abc\n").unwrap().is_empty());
+
+        assert!(matches!(
+            parse_configuration_file("[Peer]\nEndpoint = 1.1.1.1\n"),
+            Err(CliError::WireGuardConfig(_))
+        ));
+        assert!(matches!(
+            parse_configuration_file("[Peer]\nEndpoint\n"),
+            Err(CliError::WireGuardConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_has_interface_section() {
+        assert!(has_interface_section("# wg0\n[Interface]\nPrivateKey = key\n"));
+        assert!(has_interface_section(" [interface] # comment\n"));
+        assert!(!has_interface_section("client\nremote 185.65.135.1 1194\n"));
+        assert!(!has_interface_section("# [Interface]\n[Peer]\n"));
+    }
+
+    #[test]
+    fn test_parse_endpoint() -> CliResult<()> {
+        assert_eq!(("185.65.135.1", 51820), parse_endpoint("185.65.135.1:51820")?);
+        assert_eq!(("vpn.example.com", 51820), parse_endpoint("vpn.example.com:51820")?);
+        assert_eq!(
+            ("2a03:1b20:1:f011::a01f", 3233), parse_endpoint("[2a03:1b20:1:f011::a01f]:3233")?
+        );
+
+        for endpoint in [
+            "vpn.example.com", "2a03:1b20::1:3233", "[2a03:1b20::1]", "[2a03:1b20::1]3233",
+            ":51820", "vpn.example.com:port", "vpn.example.com:70000",
+        ].iter() {
+            assert!(matches!(parse_endpoint(endpoint), Err(CliError::WireGuardConfig(_))));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_handshake() {
+        assert!(has_handshake("key1=\t0\nkey2=\t1700000000\n"));
+        assert!(!has_handshake("key1=\t0\nkey2=\t0\n"));
+        // Without peers, there's nothing to wait for
+        assert!(has_handshake(""));
+    }
+
+    #[test]
+    fn test_get_interface_name() -> CliResult<()> {
+        assert_eq!("wg0", get_interface_name("/etc/wireguard/wg0.conf")?);
+        assert_eq!("mullvad-se4", get_interface_name("mullvad-se4.conf")?);
+        assert!(get_interface_name("").is_err());
+        assert_eq!(
+            Some(String::from("wg0")),
+            WireGuardClient::new("/etc/wireguard/wg0.conf").get_tunnel_interface()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() -> CliResult<()> {
+        let mut client = WireGuardClient::new("wg0.conf");
+        assert_eq!(VpnStatus::Stopped, client.get_status()?);
+        assert!(client.wait_for_interface(Duration::from_secs(1), &mut || Ok(None)).is_err());
+        // Stopping a client that isn't running does nothing
+        client.stop()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_with_runner() -> CliResult<()> {
+        let runner = FakeCommandRunner::default();
+        let mut client = WireGuardClient::with_runner("wg0.conf", Box::new(runner.clone()));
+        runner.set_output("wg-quick up ./wg0.conf", 0, "");
+        runner.set_output("ip link show dev wg0", 0, "");
+        runner.set_output("wg show wg0 latest-handshakes", 0, "key=\t0\n");
+
+        client.start()?;
+        assert_eq!("wg0", client.wait_for_interface(Duration::from_secs(1), &mut || Ok(None))?);
+
+        // Connecting until the first handshake
+        assert_eq!(VpnStatus::Connecting, client.get_status()?);
+        runner.set_output("wg show wg0 latest-handshakes", 0, "key=\t1700000000\n");
+        assert_eq!(VpnStatus::Connected(String::from("wg0")), client.get_status()?);
+
+        // The interface was removed by someone else, so there's nothing left to bring down
+        runner.set_output("ip link show dev wg0", 1, "");
+        assert_eq!(VpnStatus::Stopped, client.get_status()?);
+        client.stop()?;
+
+        assert_eq!(vec![
+            "wg-quick up ./wg0.conf",
+            "ip link show dev wg0",
+            "wg show wg0 latest-handshakes",
+            "ip link show dev wg0",
+            "wg show wg0 latest-handshakes",
+            "ip link show dev wg0",
+        ], runner.calls());
+
+        // Failing to read the handshakes is an error
+        client.start()?;
+        runner.set_output("ip link show dev wg0", 0, "");
+        runner.set_output("wg show wg0 latest-handshakes", 1, "");
+        assert!(matches!(client.get_status(), Err(CliError::Wg(Some(1)))));
+
+        // wg-quick failing to bring the interface up or down is reported too
+        runner.set_output("wg-quick down ./wg0.conf", 1, "");
+        assert!(matches!(client.stop(), Err(CliError::WgQuick(Some(1)))));
+        runner.set_output("wg-quick up ./wg0.conf", 1, "");
+        assert!(matches!(client.start(), Err(CliError::WgQuick(Some(1)))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_wg_quick_paths() -> CliResult<()> {
+        let runner = FakeCommandRunner::default();
+        runner.set_output("wg-quick up ./wg0.conf", 0, "");
+        runner.set_output("wg-quick up configs/wg0.conf", 0, "");
+        runner.set_output("wg-quick up /etc/wireguard/wg0.conf", 0, "");
+
+        // A file name without a directory would be taken as an interface name
+        for path in ["wg0.conf", "configs/wg0.conf", "/etc/wireguard/wg0.conf"] {
+            WireGuardClient::with_runner(path, Box::new(runner.clone())).run_wg_quick("up")?;
+        }
+        assert_eq!(vec![
+            "wg-quick up ./wg0.conf",
+            "wg-quick up configs/wg0.conf",
+            "wg-quick up /etc/wireguard/wg0.conf",
+        ], runner.calls());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! Runs external commands. The commands are run through a trait, so tests can fake their output.
+
+use crate::error::CliResult;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::collections::HashMap;
+#[cfg(test)]
+use std::io;
+use std::process::{Command, Stdio};
+#[cfg(test)]
+use std::rc::Rc;
+
+/// The result of a command that ran to completion.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct CommandOutput {
+    /// The exit code of the command, `None` if it was terminated by a signal.
+    pub code: Option<i32>,
+    /// Everything the command wrote to stdout.
+    pub stdout: String,
+}
+
+impl CommandOutput {
+    /// Returns whether the command exited with code 0.
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs a program with arguments and collects its output.
+pub(crate) trait CommandRunner {
+    /// Runs the supplied program with the supplied arguments and waits until it exits. Fails if the
+    /// program cannot be started.
+    fn run(&self, program: &str, args: &[&str]) -> CliResult<CommandOutput>;
+}
+
+/// Runs the programs using std::process::Command. Their stderr is passed through.
+#[derive(Debug, Default)]
+pub(crate) struct SystemCommandRunner;
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> CliResult<CommandOutput> {
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        Ok(CommandOutput {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        })
+    }
+}
+
+/// Returns the configured output for every command instead of running it and records the commands.
+/// Clones share their outputs and calls, so a test can keep a clone to inspect them.
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub(crate) struct FakeCommandRunner {
+    /// The outputs by the command line, i.e. the program and its arguments joined by spaces.
+    outputs: Rc<RefCell<HashMap<String, CommandOutput>>>,
+    /// The command lines that were run, in order.
+    calls: Rc<RefCell<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl FakeCommandRunner {
+    /// Makes the supplied command line exit with the supplied code and stdout from now on.
+    pub fn set_output(&self, command_line: &str, code: i32, stdout: &str) {
+        self.outputs.borrow_mut().insert(command_line.to_string(), CommandOutput {
+            code: Some(code),
+            stdout: stdout.to_string(),
+        });
+    }
+
+    /// Returns the command lines that were run, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for FakeCommandRunner {
+    /// Fails like a missing program if there's no output for the command line.
+    fn run(&self, program: &str, args: &[&str]) -> CliResult<CommandOutput> {
+        let command_line = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.calls.borrow_mut().push(command_line.clone());
+
+        self.outputs.borrow().get(&command_line).cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, command_line).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_command_runner() -> CliResult<()> {
+        let output = SystemCommandRunner.run("sh", &["-c", "echo linkage; exit 3"])?;
+        assert_eq!(CommandOutput { code: Some(3), stdout: String::from("linkage\n") }, output);
+        assert!(!output.success());
+        assert!(SystemCommandRunner.run("linkage-missing-binary", &[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_command_runner() -> CliResult<()> {
+        let runner = FakeCommandRunner::default();
+        runner.set_output("wg show wg0 latest-handshakes", 0, "key\t0\n");
+
+        // Clones share the outputs and calls
+        let output = runner.clone().run("wg", &["show", "wg0", "latest-handshakes"])?;
+        assert!(output.success());
+        assert_eq!("key\t0\n", output.stdout);
+        assert!(runner.run("ip", &["link"]).is_err());
+        assert_eq!(vec!["wg show wg0 latest-handshakes", "ip link"], runner.calls());
+
+        Ok(())
+    }
+}
@@ -7,4 +7,34 @@ pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 /// The author of the application.
 pub const APP_AUTHOR: &str = "BitJerkers not incorporated";
 /// Short description of the application.
-pub const APP_ABOUT: &str = "An open-source VPN manager.";
\ No newline at end of file
+pub const APP_ABOUT: &str = "An open-source VPN manager.";
+/// The file that holds the state of the firewall while connected. It lives in `/run`, so it's gone
+/// after a reboot, just like the firewall rules themselves.
+pub const STATE_FILE_PATH: &str = "/run/linkage/state.json";
+/// The settings file that is used if no other one is supplied. It's optional.
+pub const SETTINGS_FILE_PATH: &str = "/etc/linkage/linkage.toml";
+/// The unix socket of the OpenVPN management interface, which is used to follow the state of the
+/// connection.
+pub const OPENVPN_MANAGEMENT_SOCKET_PATH: &str = "/run/linkage/openvpn.sock";
+/// The seconds to wait for the tunnel interface if no other timeout is configured.
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+/// The seconds between the periodic checks of the connection.
+pub const SUPERVISOR_INTERVAL: u64 = 5;
+/// How often reconnecting is attempted after the VPN client exited, if nothing else is configured.
+pub const DEFAULT_RECONNECT_RETRIES: u32 = 5;
+/// The seconds before the first attempt to reconnect, if nothing else is configured. The delay is
+/// doubled after every failed attempt.
+pub const DEFAULT_RECONNECT_DELAY: u64 = 2;
+/// The longest delay in seconds between two attempts to reconnect.
+pub const MAX_RECONNECT_DELAY: u64 = 60;
+/// The seconds between the leak tests while connected, if nothing else is configured.
+pub const DEFAULT_LEAK_CHECK_INTERVAL: u64 = 300;
+/// The number of requests of the DNS leak test, if nothing else is configured.
+pub const DEFAULT_DNS_TEST_REQUESTS: u32 = 100;
+/// The resolver configuration that is rewritten to use the DNS servers of the VPN server.
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+/// Where the original resolver configuration is kept while connected. It's restored by `reset`
+/// and before connecting even without a state file, which doesn't survive a reboot.
+pub const RESOLV_CONF_BACKUP_PATH: &str = "/etc/resolv.conf.linkage-backup";
+/// The service used for the leak tests, if nothing else is configured.
+pub const DEFAULT_LEAK_PROVIDER: &str = "ipleak";
@@ -0,0 +1,279 @@
+//! Applies the DNS servers that the VPN server pushed to the resolver of the system, so DNS
+//! requests go through the tunnel without external scripts like `update-resolv-conf`.
+
+use crate::consts;
+use crate::error::{CliError, CliResult};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// The name of the `resolvconf` binary, which is provided by resolvconf, openresolv or
+/// systemd-resolved.
+const RESOLVCONF_BINARY_NAME: &str = "resolvconf";
+
+/// How the DNS servers of the VPN server are applied, as configured by the user.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DnsMode {
+    /// Uses `resolvconf` if it's installed and rewrites `/etc/resolv.conf` otherwise.
+    Auto,
+    /// Rewrites `/etc/resolv.conf`.
+    File,
+    /// Registers the DNS servers for the tunnel interface using `resolvconf`.
+    Resolvconf,
+    /// Leaves the resolver configuration alone, e.g. because a script of the VPN client handles
+    /// it.
+    Off,
+}
+
+impl DnsMode {
+    /// Returns the method that is used in this mode, or `None` if the DNS servers aren't applied.
+    pub fn get_method(&self) -> Option<DnsMethod> {
+        match self {
+            DnsMode::Auto if which::which(RESOLVCONF_BINARY_NAME).is_ok() => {
+                Some(DnsMethod::Resolvconf)
+            }
+            DnsMode::Auto | DnsMode::File => Some(DnsMethod::File),
+            DnsMode::Resolvconf => Some(DnsMethod::Resolvconf),
+            DnsMode::Off => None,
+        }
+    }
+}
+
+impl FromStr for DnsMode {
+    type Err = CliError;
+
+    /// Converts the supplied string into DnsMode.
+    /// # Values
+    /// - Auto: `auto`
+    /// - File: `file`
+    /// - Resolvconf: `resolvconf`
+    /// - Off: `off`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(DnsMode::Auto),
+            "file" => Ok(DnsMode::File),
+            "resolvconf" => Ok(DnsMode::Resolvconf),
+            "off" => Ok(DnsMode::Off),
+            _ => Err(CliError::UnknownDnsMode(s.to_string())),
+        }
+    }
+}
+
+/// How the DNS servers are applied.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum DnsMethod {
+    /// `/etc/resolv.conf` is moved to a backup next to it and replaced.
+    File,
+    /// The DNS servers are registered for the tunnel interface using `resolvconf -a`.
+    Resolvconf,
+}
+
+/// The DNS configuration that has to be restored when disconnecting. It's part of the state file,
+/// so it's restored after a crash as well.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub(crate) enum DnsState {
+    /// The `resolv.conf` at the path was replaced and the original was moved to the backup path.
+    File { path: PathBuf, backup_path: PathBuf },
+    /// The DNS servers were registered for the interface using `resolvconf`.
+    Resolvconf { interface_name: String },
+}
+
+/// Applies DNS servers to the resolver of the system and restores the original configuration.
+pub(crate) struct DnsManager {
+    method: DnsMethod,
+    resolv_conf_path: PathBuf,
+    /// Where the original `resolv.conf` is kept. It's in the same directory, so it's moved
+    /// atomically and survives a reboot, unlike the state file. See `restore_resolv_conf_backup`.
+    backup_path: PathBuf,
+}
+
+impl DnsManager {
+    /// Returns a new instance of DnsManager that uses the supplied method on the system.
+    pub fn new(method: DnsMethod) -> DnsManager {
+        DnsManager::with_paths(
+            method,
+            Path::new(consts::RESOLV_CONF_PATH),
+            Path::new(consts::RESOLV_CONF_BACKUP_PATH),
+        )
+    }
+
+    /// Returns a new instance of DnsManager that rewrites the `resolv.conf` at the supplied path.
+    pub(crate) fn with_paths(
+        method: DnsMethod,
+        resolv_conf_path: &Path,
+        backup_path: &Path,
+    ) -> DnsManager {
+        DnsManager {
+            method,
+            resolv_conf_path: resolv_conf_path.to_path_buf(),
+            backup_path: backup_path.to_path_buf(),
+        }
+    }
+
+    /// Applies the supplied DNS servers of the tunnel with the supplied name. Returns what has to
+    /// be restored afterwards.
+    pub fn apply(&self, interface_name: &str, servers: &[IpAddr]) -> CliResult<DnsState> {
+        let content = format_resolv_conf(interface_name, servers);
+        match self.method {
+            DnsMethod::File => {
+                self.write_resolv_conf(&content)?;
+                Ok(DnsState::File {
+                    path: self.resolv_conf_path.clone(),
+                    backup_path: self.backup_path.clone(),
+                })
+            }
+            DnsMethod::Resolvconf => {
+                run_resolvconf(&["-a", interface_name], Some(&content))?;
+                Ok(DnsState::Resolvconf {
+                    interface_name: interface_name.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Replaces `resolv.conf` with the supplied content. The original is only moved to the backup
+    /// if there isn't one yet, so applying twice doesn't lose it. A symlink, e.g. to the stub
+    /// resolver of systemd-resolved, is kept as it is.
+    fn write_resolv_conf(&self, content: &str) -> CliResult<()> {
+        if fs::symlink_metadata(&self.backup_path).is_err() {
+            match fs::rename(&self.resolv_conf_path, &self.backup_path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        // The file is written next to it first, so there is never a partially written one
+        let temporary_path = self.resolv_conf_path.with_extension("linkage-tmp");
+        fs::write(&temporary_path, content)?;
+        fs::rename(&temporary_path, &self.resolv_conf_path)?;
+
+        Ok(())
+    }
+}
+
+/// Restores the DNS configuration from before it was applied. Does nothing if it was restored
+/// already, e.g. when recovering from a crash during disconnecting.
+pub(crate) fn restore_dns(state: &DnsState) -> CliResult<()> {
+    match state {
+        DnsState::File { path, backup_path } => match fs::rename(backup_path, path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        },
+        DnsState::Resolvconf { interface_name } => {
+            run_resolvconf(&["-d", interface_name], None)
+        }
+    }
+}
+
+/// Restores the `resolv.conf` at the supplied path from the supplied backup if there is one. Used
+/// when there is no state file, e.g. after a crash followed by a reboot. Returns whether the
+/// backup was restored.
+pub(crate) fn restore_resolv_conf_backup(path: &Path, backup_path: &Path) -> CliResult<bool> {
+    if fs::symlink_metadata(backup_path).is_err() {
+        return Ok(false);
+    }
+    restore_dns(&DnsState::File {
+        path: path.to_path_buf(),
+        backup_path: backup_path.to_path_buf(),
+    })?;
+
+    Ok(true)
+}
+
+/// Returns a `resolv.conf` that only uses the supplied DNS servers.
+fn format_resolv_conf(interface_name: &str, servers: &[IpAddr]) -> String {
+    let mut content = format!(
+        "# Generated by Linkage for {}, the original configuration is restored when \
+         disconnecting\n",
+        interface_name
+    );
+    for server in servers {
+        content.push_str(&format!("nameserver {}\n", server));
+    }
+
+    content
+}
+
+/// Runs `resolvconf` with the supplied arguments and writes the supplied input to it.
+fn run_resolvconf(args: &[&str], input: Option<&str>) -> CliResult<()> {
+    let mut child = Command::new(RESOLVCONF_BINARY_NAME)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    // Closing stdin tells resolvconf that the input is complete
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.unwrap_or_default().as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(CliError::Resolvconf(status.code()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    #[test]
+    fn test_dns_mode_from_str() {
+        assert_eq!(DnsMode::Auto, DnsMode::from_str("auto").unwrap());
+        assert_eq!(DnsMode::File, DnsMode::from_str("file").unwrap());
+        assert_eq!(DnsMode::Resolvconf, DnsMode::from_str("resolvconf").unwrap());
+        assert_eq!(DnsMode::Off, DnsMode::from_str("off").unwrap());
+        assert!(matches!(DnsMode::from_str("dhcp"), Err(CliError::UnknownDnsMode(_))));
+
+        assert_eq!(Some(DnsMethod::File), DnsMode::File.get_method());
+        assert_eq!(None, DnsMode::Off.get_method());
+    }
+
+    #[test]
+    fn test_dns_manager_file() -> CliResult<()> {
+        let directory = env::temp_dir().join(format!("linkage-dns-test-{}", process::id()));
+        let resolv_conf_path = directory.join("resolv.conf");
+        let backup_path = directory.join("resolv.conf.linkage-backup");
+        fs::create_dir_all(&directory)?;
+        fs::write(directory.join("stub-resolv.conf"), "nameserver 127.0.0.53\n")?;
+        symlink("stub-resolv.conf", &resolv_conf_path)?;
+
+        let manager = DnsManager::with_paths(DnsMethod::File, &resolv_conf_path, &backup_path);
+        let state = manager.apply("tun0", &["10.8.0.1".parse()?, "fd00::1".parse()?])?;
+        assert_eq!(DnsState::File {
+            path: resolv_conf_path.clone(),
+            backup_path: backup_path.clone(),
+        }, state);
+        let content = fs::read_to_string(&resolv_conf_path)?;
+        assert!(content.starts_with("# Generated by Linkage for tun0"));
+        assert!(content.ends_with("\nnameserver 10.8.0.1\nnameserver fd00::1\n"));
+
+        // Applying again, e.g. after reconnecting, keeps the original backup
+        manager.apply("tun1", &["10.9.0.1".parse()?])?;
+        assert!(fs::read_to_string(&resolv_conf_path)?.ends_with("\nnameserver 10.9.0.1\n"));
+
+        restore_dns(&state)?;
+        assert_eq!(Path::new("stub-resolv.conf"), fs::read_link(&resolv_conf_path)?);
+        assert!(fs::symlink_metadata(&backup_path).is_err());
+        // Restoring twice, e.g. after a crash, is fine
+        restore_dns(&state)?;
+        assert_eq!("nameserver 127.0.0.53\n", fs::read_to_string(&resolv_conf_path)?);
+
+        // Without a state file, the backup is restored on its own
+        manager.apply("tun0", &["10.8.0.1".parse()?])?;
+        assert!(restore_resolv_conf_backup(&resolv_conf_path, &backup_path)?);
+        assert_eq!(Path::new("stub-resolv.conf"), fs::read_link(&resolv_conf_path)?);
+        assert!(!restore_resolv_conf_backup(&resolv_conf_path, &backup_path)?);
+
+        fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
@@ -1,10 +1,13 @@
 use linkage_firewall::FirewallError;
+use linkage_firewall::FirewallExceptionError;
 use linkage_firewall::FirewallExceptionProtocolError;
 use std::io;
 use std::net::AddrParseError;
 use std::num::ParseIntError;
 use thiserror::Error;
 use linkage_leaks::LeakError;
+use serde_json::Error as SerdeJsonError;
+use toml::de::Error as TomlError;
 
 #[derive(Error, Debug)]
 pub enum CliError {
@@ -23,6 +26,9 @@ pub enum CliError {
     #[error("can't parse protocol: {0}")]
     FirewallExceptionProtocol(#[from] FirewallExceptionProtocolError),
 
+    #[error("can't parse exception: {0}")]
+    FirewallException(#[from] FirewallExceptionError),
+
     #[error("can't parse int: {0}")]
     ParseInt(#[from] ParseIntError),
 
@@ -35,8 +41,64 @@ pub enum CliError {
     #[error("couldn't get the interface from openvpn")]
     InterfaceParseError,
 
+    #[error("the interface {0} doesn't exist or has no address")]
+    InterfaceAddress(String),
+
     #[error("leak error: {0}")]
     LinkageLeakError(#[from] LeakError),
+
+    #[error("can't parse state file: {0}")]
+    StateFile(#[from] SerdeJsonError),
+
+    #[error("can't parse settings file: {0}")]
+    Settings(#[from] TomlError),
+
+    #[error("the settings file {0} doesn't exist")]
+    SettingsNotFound(String),
+
+    #[error("openvpn management interface error: {0}")]
+    Management(String),
+
+    #[error("openvpn failed: {reason}{}", format_output(.output))]
+    OpenVpn { reason: String, output: Vec<String> },
+
+    #[error("unknown leak reaction {0}")]
+    UnknownLeakReaction(String),
+
+    #[error("unknown vpn type {0}")]
+    UnknownVpnType(String),
+
+    #[error("cannot parse wireguard configuration file: {0}")]
+    WireGuardConfig(String),
+
+    #[error("wg-quick failed with exit code {0:?}")]
+    WgQuick(Option<i32>),
+
+    #[error("wg failed with exit code {0:?}")]
+    Wg(Option<i32>),
+
+    #[error("cannot resolve the host {0} of the vpn server")]
+    ResolveHost(String),
+
+    #[error("unknown dns mode {0}")]
+    UnknownDnsMode(String),
+
+    #[error("resolvconf failed with exit code {0:?}")]
+    Resolvconf(Option<i32>),
+
+    #[error("the state file references the unknown firewall backend {0}")]
+    UnknownFirewallBackend(String),
+
+    #[error("received signal {0} while connecting")]
+    Interrupted(i32),
+}
+
+/// Formats the last lines of the output of a VPN client for an error message.
+fn format_output(output: &[String]) -> String {
+    if output.is_empty() {
+        return String::new();
+    }
+    format!("\nThe last output was:\n  {}", output.join("\n  "))
 }
 
 pub(crate) type CliResult<T> = Result<T, CliError>;
@@ -0,0 +1,130 @@
+//! Looks up the network interfaces of the system and their addresses.
+
+use crate::error::{CliError, CliResult};
+use linkage_leaks::TunnelInterface;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ptr;
+
+/// Returns the name and address of every address that is assigned to an interface.
+pub(crate) fn get_interface_addresses() -> CliResult<Vec<(String, IpAddr)>> {
+    let mut addresses: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addresses) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let mut interface_addresses = Vec::new();
+    let mut current = addresses;
+    while !current.is_null() {
+        let entry = unsafe { &*current };
+        current = entry.ifa_next;
+        if entry.ifa_addr.is_null() {
+            continue;
+        }
+
+        let address = unsafe {
+            match i32::from((*entry.ifa_addr).sa_family) {
+                libc::AF_INET => {
+                    let a = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let a = &*(entry.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(a.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            }
+        };
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) };
+        interface_addresses.push((name.to_string_lossy().into_owned(), address));
+    }
+    unsafe { libc::freeifaddrs(addresses) };
+
+    Ok(interface_addresses)
+}
+
+/// Returns the name of the interface that has the supplied address, if there is one.
+pub(crate) fn get_interface_by_address(address: IpAddr) -> CliResult<Option<String>> {
+    Ok(get_interface_addresses()?
+        .into_iter()
+        .find(|(_, a)| *a == address)
+        .map(|(name, _)| name))
+}
+
+/// Parses the supplied address, or returns an address of the interface with the supplied name.
+/// IPv4 addresses are preferred, since most leak test sites are reachable using IPv4.
+pub(crate) fn resolve_local_address(address_or_interface: &str) -> CliResult<IpAddr> {
+    if let Ok(address) = address_or_interface.parse() {
+        return Ok(address);
+    }
+
+    let addresses: Vec<IpAddr> = get_interface_addresses()?
+        .into_iter()
+        .filter(|(name, _)| name == address_or_interface)
+        .map(|(_, address)| address)
+        .collect();
+    addresses
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or_else(|| addresses.first())
+        .copied()
+        .ok_or_else(|| CliError::InterfaceAddress(address_or_interface.to_string()))
+}
+
+/// Returns the index and addresses of the interface with the supplied name.
+pub(crate) fn get_tunnel_interface(name: &str) -> CliResult<TunnelInterface> {
+    let c_name = CString::new(name).map_err(|_| CliError::InterfaceAddress(name.to_string()))?;
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        return Err(CliError::InterfaceAddress(name.to_string()));
+    }
+
+    Ok(TunnelInterface {
+        name: name.to_string(),
+        index,
+        addresses: get_interface_addresses()?
+            .into_iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, address)| address)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_interface_addresses() -> CliResult<()> {
+        let addresses = get_interface_addresses()?;
+        assert!(addresses.contains(&(String::from("lo"), "127.0.0.1".parse()?)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_local_address() -> CliResult<()> {
+        assert_eq!("10.0.0.2".parse::<IpAddr>()?, resolve_local_address("10.0.0.2")?);
+        assert_eq!("127.0.0.1".parse::<IpAddr>()?, resolve_local_address("lo")?);
+        assert!(matches!(
+            resolve_local_address("linkage-missing0"),
+            Err(CliError::InterfaceAddress(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_tunnel_interface() -> CliResult<()> {
+        let tunnel = get_tunnel_interface("lo")?;
+        assert_eq!(1, tunnel.index);
+        assert!(tunnel.addresses.contains(&"127.0.0.1".parse()?));
+        assert!(matches!(
+            get_tunnel_interface("linkage-missing0"),
+            Err(CliError::InterfaceAddress(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! Detects leaks by comparing the results of the leak tests while connected with the ones from
+//! before connecting, and by auditing the resolver configuration.
+
+use crate::error::CliResult;
+use crate::interfaces::get_tunnel_interface;
+use linkage_leaks::{DnsTestOptions, LeakProvider, LeakReport, LeakTestResults, ResolverAudit};
+
+/// The results of the leak tests from before connecting.
+pub(crate) struct LeakBaseline {
+    /// The service that runs the leak tests.
+    provider: Box<dyn LeakProvider + Send + Sync>,
+    /// How the DNS leak test is run, both now and while connected.
+    dns_options: DnsTestOptions,
+    results: LeakTestResults,
+}
+
+impl LeakBaseline {
+    /// Runs the leak tests using the supplied provider and DNS test options. Has to be called
+    /// before connecting.
+    pub fn capture(
+        provider: Box<dyn LeakProvider + Send + Sync>,
+        dns_options: DnsTestOptions,
+    ) -> CliResult<LeakBaseline> {
+        Ok(LeakBaseline {
+            results: LeakTestResults::run(provider.as_ref(), &dns_options)?,
+            provider,
+            dns_options,
+        })
+    }
+
+    /// Runs the leak tests again and returns what is leaked compared to the baseline.
+    pub fn detect_leak(&self) -> CliResult<LeakReport> {
+        let results = LeakTestResults::run(self.provider.as_ref(), &self.dns_options)?;
+        Ok(LeakReport::new(&self.results, &results))
+    }
+}
+
+/// Audits the resolver configuration of the system, so DNS servers that are reached outside of the
+/// tunnel with the supplied name are found. Nothing is sent.
+pub(crate) fn audit_resolver(interface_name: &str) -> CliResult<ResolverAudit> {
+    Ok(ResolverAudit::run(&get_tunnel_interface(interface_name)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkage_leaks::test_support::{MockLeakServer, MockResponses};
+
+    #[test]
+    fn test_leak_baseline_detect_leak() -> CliResult<()> {
+        let server = MockLeakServer::start(MockResponses::default())?;
+        let baseline = LeakBaseline::capture(Box::new(server.provider()), DnsTestOptions::new(10))?;
+        // Nothing changed, so everything is leaked
+        assert_eq!(LeakReport {
+            ipv4: Some("1.1.1.1".parse()?),
+            ipv6: Some("2001:db8::1".parse()?),
+            dns_servers: vec!["9.9.9.9".parse()?],
+        }, baseline.detect_leak()?);
+
+        server.set_responses(MockResponses {
+            ipv4: "2.2.2.2".parse()?,
+            ipv6: Some("2001:db8:1::1".parse()?),
+            dns_servers: vec!["10.8.0.1".parse()?],
+            ..MockResponses::default()
+        });
+        assert!(!baseline.detect_leak()?.is_leaking());
+
+        server.set_responses(MockResponses {
+            ipv6: Some("2001:db8:1::1".parse()?),
+            dns_servers: vec!["10.8.0.1".parse()?],
+            ..MockResponses::default()
+        });
+        assert_eq!(LeakReport {
+            ipv4: Some("1.1.1.1".parse()?),
+            ..LeakReport::default()
+        }, baseline.detect_leak()?);
+
+        Ok(())
+    }
+}
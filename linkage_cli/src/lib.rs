@@ -1,24 +1,39 @@
+mod clients;
+mod command;
 pub(crate) mod consts;
+mod dns;
 pub mod error;
+mod interfaces;
+mod leaks;
+mod monitor;
+mod reconnect;
+mod settings;
+mod state;
+mod supervisor;
 
+use crate::clients::{VpnClient, VpnStatus, VpnType};
+use crate::dns::{restore_dns, restore_resolv_conf_backup, DnsManager, DnsMode};
 use crate::error::{CliError, CliResult};
+use crate::interfaces::resolve_local_address;
+use crate::leaks::{audit_resolver, LeakBaseline};
+use crate::monitor::{LeakMonitor, LeakReaction};
+use crate::reconnect::ReconnectPolicy;
+use crate::settings::{parse_exceptions, Settings};
+use crate::state::State;
+use crate::supervisor::{Supervisor, SupervisorEvent};
 use clap::{App as ClapApp, Arg as ClapArg, ArgMatches as ClapArgMatches};
 #[cfg(windows)]
 use is_elevated::is_elevated;
-#[cfg(unix)]
-use libc;
 use linkage_firewall::get_backends;
 use linkage_firewall::FirewallBackend;
 use linkage_firewall::FirewallException;
-use linkage_leaks::{dns_test, get_ip_information};
-use ovpnfile::{self, ConfigDirective as OvpnConfigDirective};
-use regex::Regex;
-use std::fs::File;
-use std::io::Read;
+use linkage_firewall::FirewallOptions;
+use linkage_leaks::{get_provider, DnsTestOptions, LeakCheckConfig, LeakReport};
 use std::net::IpAddr;
-use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// The entry point of the cli application.
 pub fn entry() -> CliResult<()> {
@@ -28,88 +43,611 @@ pub fn entry() -> CliResult<()> {
         // Administrator privileges are required
         root_check()?;
 
+        // A previous session might have left the firewall behind, so it has to be restored
+        // before anything else happens
+        if restore_stale_state()? {
+            println!("Restored the firewall from a previous session that didn't exit cleanly");
+        }
+
+        let settings = load_settings(matches.value_of("settings"))?;
+
         // Get the Ip Adresses and DNS Servers before the VPN connection
-        let ip_address_before = get_ip_information()?;
-        // TODO: Make this configurable
-        let dns_addresses_before = dns_test(100)?;
+        let leak_provider = get_provider(
+            match matches.value_of("leak-provider") {
+                Some(provider) => provider,
+                None => settings.leak_provider.as_deref().unwrap_or(consts::DEFAULT_LEAK_PROVIDER),
+            },
+            get_leak_check_config(matches, &settings)?,
+        )?;
+        if !leak_provider.supports_dns_test() {
+            println!("The leak provider can't detect DNS leaks, only IP leaks are checked");
+        }
+        let leak_baseline = Arc::new(LeakBaseline::capture(
+            leak_provider, get_dns_test_options(matches, &settings)?,
+        )?);
 
         // This should not be None
         let config_file_path = matches.value_of("config").unwrap();
         println!("Using configuration file {}", config_file_path);
-        let config_file = File::open(config_file_path)?;
-
-        // Get the exceptions from the configuration file
-        let exceptions = parse_configuration_file(config_file)?;
-
-        // The first backend is currently iptables, will be made more modular in the next versions
-        let firewall_backend = get_backends().first().unwrap();
-        if !firewall_backend.is_available()? {
-            return Err(error::CliError::FirewallBackendNotAvailable);
-        }
-
-        firewall_backend.on_pre_connect(&exceptions)?;
-
-        let c = Command::new("openvpn")
-            .arg(config_file_path)
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
-        let process_id = c.id();
-        let mut stdout = c.stdout.unwrap();
-        let mut buffer = [0; 2048];
-
-        let regex = Regex::new(r"net_iface_up: set (tun[0-9]+) up").unwrap();
-
-        // TODO: Error handling
-        // This loop should probably be limited to about 30 seconds
-        let interface_name = loop {
-            stdout.read(&mut buffer)?;
-            let i = String::from_utf8_lossy(&buffer);
-            let matches = regex.captures(&i);
-            if let Some(matches) = matches {
-                let m = matches
-                    .get(1)
-                    .expect("couldn't get the interface from openvpn");
-                break m.as_str().to_string();
+        let vpn_type = match matches.value_of("type") {
+            Some(vpn_type) => vpn_type.parse()?,
+            None => VpnType::from_path(Path::new(config_file_path)),
+        };
+
+        let mut client = vpn_type.new_client(config_file_path);
+
+        // Get the additional exceptions from the settings and the command line
+        let mut exceptions = settings.get_exceptions()?;
+        exceptions.extend(parse_exceptions(matches.values_of("allow").into_iter().flatten())?);
+
+        let firewall_options = FirewallOptions {
+            allow_lan: matches.is_present("allow-lan") || settings.allow_lan,
+            allow_multicast: matches.is_present("allow-multicast") || settings.allow_multicast,
+            tunnel_interface: client.get_tunnel_interface(),
+        };
+        let connect_timeout = match matches.value_of("timeout") {
+            Some(timeout) => timeout.parse()?,
+            None => settings.connect_timeout.unwrap_or(consts::DEFAULT_CONNECT_TIMEOUT),
+        };
+        let connect_timeout = Duration::from_secs(connect_timeout);
+        let reconnect_policy = ReconnectPolicy {
+            max_retries: match matches.value_of("reconnect-retries") {
+                Some(retries) => retries.parse()?,
+                None => settings.reconnect_retries.unwrap_or(consts::DEFAULT_RECONNECT_RETRIES),
+            },
+            initial_delay: Duration::from_secs(match matches.value_of("reconnect-delay") {
+                Some(delay) => delay.parse()?,
+                None => settings.reconnect_delay.unwrap_or(consts::DEFAULT_RECONNECT_DELAY),
+            }),
+            max_delay: Duration::from_secs(consts::MAX_RECONNECT_DELAY),
+        };
+        let leak_check_interval = match matches.value_of("leak-check-interval") {
+            Some(interval) => interval.parse()?,
+            None => settings.leak_check_interval.unwrap_or(consts::DEFAULT_LEAK_CHECK_INTERVAL),
+        };
+        let leak_reaction = match matches.value_of("leak-reaction") {
+            Some(reaction) => reaction.parse()?,
+            None => settings.leak_reaction.unwrap_or(LeakReaction::Disconnect),
+        };
+        let dns_mode = match matches.value_of("dns") {
+            Some(mode) => mode.parse()?,
+            None => settings.dns.unwrap_or(DnsMode::Auto),
+        };
+
+        let firewall_backend = get_available_backend()?;
+        let state_path = Path::new(consts::STATE_FILE_PATH);
+        // The supervisor handles the stop signals from now on, so everything is torn down again
+        // if Linkage is asked to exit while connecting
+        let mut supervisor = Supervisor::new(
+            Duration::from_secs(consts::SUPERVISOR_INTERVAL), VpnStatus::Stopped,
+        )?;
+        let interface_name = match establish_connection(
+            client.as_mut(),
+            firewall_backend,
+            &mut supervisor,
+            &exceptions,
+            &firewall_options,
+            connect_timeout,
+            state_path,
+        ) {
+            Ok(interface_name) => interface_name,
+            Err(CliError::Interrupted(signal)) => {
+                println!("Received signal {}", signal);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        supervisor.set_status(client.get_status()?);
+        // The leak tests keep running in the background, 0 disables them
+        let leak_monitor = match leak_check_interval {
+            0 => None,
+            interval => {
+                let leak_baseline = leak_baseline.clone();
+                Some(LeakMonitor::start(
+                    Duration::from_secs(interval),
+                    supervisor.waker()?,
+                    move || leak_baseline.detect_leak(),
+                ))
             }
         };
 
-        // After connect
-        firewall_backend.on_post_connect(&interface_name)?;
+        let mut session = Session {
+            client: client.as_mut(),
+            firewall_backend,
+            supervisor,
+            interface_name: Some(interface_name),
+            leak_baseline: &leak_baseline,
+            dns_manager: dns_mode.get_method().map(DnsManager::new),
+            state_path,
+            options: SessionOptions { connect_timeout, reconnect_policy, leak_reaction },
+        };
+        let result = session.run(leak_monitor.as_ref());
+        // Everything is torn down even if the session failed, otherwise the tunnel and the
+        // firewall would stay behind
+        let disconnect_result = disconnect(firewall_backend, Some(client.as_mut()), state_path);
+        match result {
+            Ok(()) => disconnect_result,
+            // The error of the session is more relevant. The state file is kept if tearing down
+            // fails, so the firewall can still be reset.
+            Err(e) => {
+                if let Err(teardown_error) = disconnect_result {
+                    eprintln!("Couldn't tear down the firewall: {}", teardown_error);
+                }
+                Err(e)
+            }
+        }
+    } else if matches.subcommand_matches("reset").is_some() {
+        // Administrator privileges are required
+        root_check()?;
 
-        // Get the ip addresses after the connection is established.
-        let ip_address_after = get_ip_information()?;
-        // TODO: Make this configurable
-        let dns_addresses_after = dns_test(100)?;
-        let matching_dns_addresses: Vec<&IpAddr> = dns_addresses_after
-            .iter()
-            .filter(|&e| dns_addresses_before.contains(e))
-            .collect();
-        if matching_dns_addresses.len() > 0 {
-            println!("Detected DNS-Leak, disconnecting...");
-            return disconnect(firewall_backend, Some(process_id));
+        if restore_stale_state()? {
+            println!("Restored the firewall from the previous session");
+        } else {
+            println!("No state of a previous session found, resetting the firewall to its defaults");
+            get_available_backend()?.on_reset(None)?;
         }
-        let matching_ip_addresses = ip_address_after.ip == ip_address_before.ip
-            || ip_address_after.ipv6 == ip_address_before.ipv6;
-        if matching_ip_addresses {
-            println!("Detected Ip-leak, disconnecting...");
-            return disconnect(firewall_backend, Some(process_id));
+
+        Ok(())
+    } else {
+        Ok(())
+    }
+}
+
+/// Loads the settings from the supplied path. Without a path, the default settings file is used if
+/// it exists.
+fn load_settings(path: Option<&str>) -> CliResult<Settings> {
+    match path {
+        Some(path) => Settings::load(Path::new(path))?
+            .ok_or_else(|| CliError::SettingsNotFound(path.to_string())),
+        None => Ok(Settings::load(Path::new(consts::SETTINGS_FILE_PATH))?.unwrap_or_default()),
+    }
+}
+
+/// Returns how the requests of the leak tests are sent, using the supplied options and settings.
+/// Certificates from both are used.
+fn get_leak_check_config(
+    matches: &ClapArgMatches,
+    settings: &Settings,
+) -> CliResult<LeakCheckConfig> {
+    let defaults = LeakCheckConfig::default();
+    let local_address = match matches.value_of("leak-bind").or(settings.leak_bind.as_deref()) {
+        Some(bind) => Some(resolve_local_address(bind)?),
+        None => None,
+    };
+    let ca_certificates = settings.leak_ca_certificates.iter().map(String::as_str)
+        .chain(matches.values_of("leak-ca-cert").into_iter().flatten())
+        .map(PathBuf::from)
+        .collect();
+
+    Ok(LeakCheckConfig {
+        timeout: match matches.value_of("leak-timeout") {
+            Some(timeout) => Duration::from_secs(timeout.parse()?),
+            None => settings.leak_timeout.map(Duration::from_secs).unwrap_or(defaults.timeout),
+        },
+        retries: match matches.value_of("leak-retries") {
+            Some(retries) => retries.parse()?,
+            None => settings.leak_retries.unwrap_or(defaults.retries),
+        },
+        user_agent: match matches.value_of("leak-user-agent") {
+            Some(user_agent) => user_agent.to_string(),
+            None => settings.leak_user_agent.clone().unwrap_or(defaults.user_agent),
+        },
+        local_address,
+        ca_certificates,
+        ..defaults
+    })
+}
+
+/// Returns how the DNS leak test is run, using the supplied options and settings. Half of the
+/// requests have to succeed unless something else is configured.
+fn get_dns_test_options(
+    matches: &ClapArgMatches,
+    settings: &Settings,
+) -> CliResult<DnsTestOptions> {
+    let requests = match matches.value_of("leak-dns-requests") {
+        Some(requests) => requests.parse()?,
+        None => settings.leak_dns_requests.unwrap_or(consts::DEFAULT_DNS_TEST_REQUESTS),
+    };
+    let defaults = DnsTestOptions::new(requests);
+
+    Ok(DnsTestOptions {
+        min_successes: match matches.value_of("leak-dns-min-successes") {
+            Some(min_successes) => min_successes.parse()?,
+            None => settings.leak_dns_min_successes.unwrap_or(defaults.min_successes),
+        },
+        ..defaults
+    })
+}
+
+/// Restores the firewall using the state file of a previous session, if there is one. The state
+/// file is removed afterwards. Without a state file, only a leftover backup of `resolv.conf` is
+/// restored. Returns whether a state file was found.
+fn restore_stale_state() -> CliResult<bool> {
+    let path = Path::new(consts::STATE_FILE_PATH);
+    let state = match State::load(path)? {
+        Some(state) => state,
+        None => {
+            // The state file is gone after a reboot, but the backup of resolv.conf isn't
+            let resolv_conf_path = Path::new(consts::RESOLV_CONF_PATH);
+            let backup_path = Path::new(consts::RESOLV_CONF_BACKUP_PATH);
+            if restore_resolv_conf_backup(resolv_conf_path, backup_path)? {
+                println!("Restored {} from a previous session", consts::RESOLV_CONF_PATH);
+            }
+            return Ok(false);
         }
+    };
+
+    // The state has to be restored by the same backend that created it
+    let backend = get_backends()
+        .iter()
+        .find(|b| b.get_identifier() == state.backend.as_str())
+        .ok_or_else(|| CliError::UnknownFirewallBackend(state.backend.clone()))?;
+    backend.on_reset(state.snapshot.map(Into::into).as_ref())?;
+    if let Some(dns) = &state.dns {
+        restore_dns(dns)?;
+    }
+    State::remove(path)?;
 
-        let running = Arc::new(AtomicBool::new(true));
-        let r = running.clone();
-        ctrlc::set_handler(move || r.store(false, Ordering::SeqCst)).unwrap();
+    Ok(true)
+}
 
+/// Returns the first firewall backend that is available on this system. The backends are checked
+/// in the order of `get_backends()`, so iptables is preferred over nftables.
+fn get_available_backend() -> CliResult<&'static (dyn FirewallBackend + Sync)> {
+    for backend in get_backends() {
+        if backend.is_available()? {
+            return Ok(backend.as_ref());
+        }
+    }
+    Err(CliError::FirewallBackendNotAvailable)
+}
+
+/// Sets up the firewall with the exceptions of the client and the supplied ones, starts the client
+/// and allows the traffic through the tunnel interface once it's up. The state of the firewall is
+/// saved to the supplied path. Returns the name of the tunnel interface.
+///
+/// If the tunnel isn't up within the timeout, a stop signal is received by the supplied supervisor
+/// or anything else fails, the client is stopped and the firewall is torn down again.
+fn establish_connection(
+    client: &mut dyn VpnClient,
+    firewall_backend: &dyn FirewallBackend,
+    supervisor: &mut Supervisor,
+    exceptions: &[FirewallException],
+    firewall_options: &FirewallOptions,
+    connect_timeout: Duration,
+    state_path: &Path,
+) -> CliResult<String> {
+    let mut all_exceptions = client.get_exceptions()?;
+    all_exceptions.extend(exceptions.iter().cloned());
+
+    let result = set_up_connection(
+        client,
+        firewall_backend,
+        supervisor,
+        &all_exceptions,
+        firewall_options,
+        connect_timeout,
+        state_path,
+    );
+    if result.is_err() {
+        // The error of the connection is more relevant. The state file is kept if tearing down
+        // fails, so the firewall can still be reset.
+        if let Err(e) = disconnect(firewall_backend, Some(client), state_path) {
+            eprintln!("Couldn't tear down the firewall: {}", e);
+        }
+    }
+
+    result
+}
+
+/// Does the work of `establish_connection` without tearing anything down if it fails.
+fn set_up_connection(
+    client: &mut dyn VpnClient,
+    firewall_backend: &dyn FirewallBackend,
+    supervisor: &mut Supervisor,
+    exceptions: &[FirewallException],
+    firewall_options: &FirewallOptions,
+    connect_timeout: Duration,
+    state_path: &Path,
+) -> CliResult<String> {
+    // The state is saved even if setting up the firewall failed, because it might have been
+    // set up partially
+    let pre_connect_result = firewall_backend.on_pre_connect(exceptions, firewall_options);
+    State::from_backend(firewall_backend).save(state_path)?;
+    pre_connect_result?;
+
+    client.start()?;
+    let interface_name = client.wait_for_interface(connect_timeout, &mut || {
+        supervisor.stop_signal()
+    })?;
+
+    // After connect
+    firewall_backend.on_post_connect(&interface_name)?;
+
+    Ok(interface_name)
+}
+
+/// Applies the DNS servers that the VPN server pushed for the supplied tunnel interface and saves
+/// what has to be restored to the state file. The configuration of a previous connection is
+/// restored afterwards, unless it's the same one.
+fn apply_dns(
+    dns_manager: &DnsManager,
+    client: &dyn VpnClient,
+    interface_name: &str,
+    firewall_backend: &dyn FirewallBackend,
+    state_path: &Path,
+) -> CliResult<()> {
+    let servers = client.get_dns_servers();
+    if servers.is_empty() {
+        return Ok(());
+    }
+
+    let previous = State::load(state_path)?.and_then(|s| s.dns);
+    let dns = dns_manager.apply(interface_name, &servers)?;
+    if let Some(previous) = previous.filter(|p| *p != dns) {
+        restore_dns(&previous)?;
+    }
+    State {
+        dns: Some(dns),
+        ..State::from_backend(firewall_backend)
+    }.save(state_path)?;
+
+    let servers: Vec<String> = servers.iter().map(IpAddr::to_string).collect();
+    println!("Using the DNS servers {} of the VPN server", servers.join(", "));
+    Ok(())
+}
+
+/// How an established connection is kept up.
+struct SessionOptions {
+    connect_timeout: Duration,
+    reconnect_policy: ReconnectPolicy,
+    leak_reaction: LeakReaction,
+}
+
+/// What a session does next.
+#[derive(Debug, PartialEq)]
+enum SessionAction {
+    /// Waits for the next event.
+    Continue,
+    /// Restarts the client. `after_leak` is set if a leak caused the reconnect.
+    Reconnect { after_leak: bool },
+    /// Stops the session, so Linkage disconnects.
+    Disconnect,
+}
+
+/// An established connection that is supervised until Linkage should disconnect. The tunnel
+/// interface is only allowed by the firewall while it's up.
+struct Session<'a> {
+    client: &'a mut dyn VpnClient,
+    firewall_backend: &'a dyn FirewallBackend,
+    supervisor: Supervisor,
+    /// The interface that is currently allowed by the firewall.
+    interface_name: Option<String>,
+    leak_baseline: &'a LeakBaseline,
+    /// Applies the DNS servers of the VPN server, `None` if they are left alone.
+    dns_manager: Option<DnsManager>,
+    /// The state file, which also holds the DNS configuration that has to be restored.
+    state_path: &'a Path,
+    options: SessionOptions,
+}
+
+impl<'a> Session<'a> {
+    /// Runs the leak tests and handles the events of the supervisor and the results of the
+    /// supplied leak monitor until a stop signal is received or a leak causes a disconnect.
+    fn run(&mut self, leak_monitor: Option<&LeakMonitor>) -> CliResult<()> {
+        let mut action = self.on_connected(false)?;
         println!("Waiting...");
-        while running.load(Ordering::SeqCst) {}
-        disconnect(firewall_backend, Some(process_id))?;
+
+        loop {
+            action = match action {
+                SessionAction::Continue => self.handle_event(leak_monitor)?,
+                SessionAction::Reconnect { after_leak } => self.reconnect(after_leak)?,
+                SessionAction::Disconnect => return Ok(()),
+            };
+        }
+    }
+
+    /// Waits for the next event of the supervisor and handles it.
+    fn handle_event(&mut self, leak_monitor: Option<&LeakMonitor>) -> CliResult<SessionAction> {
+        Ok(match self.supervisor.wait(self.client)? {
+            SupervisorEvent::Stop(signal) => {
+                println!("Received signal {}", signal);
+                SessionAction::Disconnect
+            }
+            // The kill switch stays engaged, so nothing leaks without the tunnel
+            SupervisorEvent::ClientExited(code) => {
+                println!("The vpn client exited with code {:?}", code);
+                SessionAction::Reconnect { after_leak: false }
+            }
+            // The tunnel interface was removed behind the back of the client
+            SupervisorEvent::StatusChanged(VpnStatus::Stopped) => {
+                println!("The tunnel interface is gone");
+                SessionAction::Reconnect { after_leak: false }
+            }
+            // The client reconnects on its own, the firewall keeps blocking everything else
+            // while the tunnel is down
+            SupervisorEvent::StatusChanged(VpnStatus::Connecting) => {
+                println!("The connection was lost, reconnecting...");
+                self.take_interface_down()?;
+                SessionAction::Continue
+            }
+            SupervisorEvent::StatusChanged(VpnStatus::Connected(interface_name)) => {
+                println!("Reconnected");
+                self.take_interface_down()?;
+                self.firewall_backend.on_post_connect(&interface_name)?;
+                self.interface_name = Some(interface_name);
+                self.on_connected(false)?
+            }
+            // Results from while the tunnel was down are meaningless
+            SupervisorEvent::Wake => match leak_monitor.and_then(LeakMonitor::try_result) {
+                Some(result) if self.interface_name.is_some() => {
+                    self.handle_leak_result(result, false)?
+                }
+                _ => SessionAction::Continue,
+            },
+            SupervisorEvent::StatusChanged(_) | SupervisorEvent::Tick => SessionAction::Continue,
+        })
+    }
+
+    /// Restarts the client according to the reconnect policy and runs the leak tests afterwards.
+    fn reconnect(&mut self, after_leak: bool) -> CliResult<SessionAction> {
+        self.take_interface_down()?;
+
+        Ok(match reconnect(
+            self.client,
+            self.firewall_backend,
+            &mut self.supervisor,
+            &self.options.reconnect_policy,
+            self.options.connect_timeout,
+        )? {
+            ReconnectOutcome::Connected(interface_name) => {
+                println!("Reconnected");
+                self.interface_name = Some(interface_name);
+                self.supervisor.set_status(self.client.get_status()?);
+                self.on_connected(after_leak)?
+            }
+            ReconnectOutcome::Stopped(signal) => {
+                println!("Received signal {}", signal);
+                SessionAction::Disconnect
+            }
+            // The client stays stopped, so the next status mustn't count as a change that
+            // causes another reconnect
+            ReconnectOutcome::GaveUp => {
+                println!("Couldn't reconnect, all traffic stays blocked until Linkage is stopped");
+                self.supervisor.set_status(self.client.get_status()?);
+                SessionAction::Continue
+            }
+        })
+    }
+
+    /// Applies the DNS servers of the VPN server and checks for leaks after the tunnel came up.
+    fn on_connected(&mut self, after_leak: bool) -> CliResult<SessionAction> {
+        if let (Some(dns_manager), Some(interface_name)) = (&self.dns_manager, &self.interface_name)
+        {
+            let result = apply_dns(
+                dns_manager, &*self.client, interface_name, self.firewall_backend, self.state_path,
+            );
+            // The firewall still blocks DNS servers outside of the tunnel, so this isn't fatal
+            if let Err(e) = result {
+                println!("Couldn't apply the DNS servers of the VPN server: {}", e);
+            }
+        }
+        self.check_leaks(after_leak)
+    }
+
+    /// Audits the resolver configuration and runs the leak tests, and handles the result.
+    fn check_leaks(&mut self, after_leak: bool) -> CliResult<SessionAction> {
+        if let Some(interface_name) = &self.interface_name {
+            match audit_resolver(interface_name) {
+                Ok(audit) if audit.is_leaking() => {
+                    for finding in &audit.findings {
+                        println!("DNS configuration warning: {}", finding);
+                    }
+                }
+                Ok(_) => println!("DNS configuration audit passed"),
+                Err(e) => println!("DNS configuration audit failed: {}", e),
+            }
+        }
+        let result = self.leak_baseline.detect_leak();
+        self.handle_leak_result(result, after_leak)
+    }
+
+    /// Logs the supplied result of the leak tests and reacts to a leak as configured. A leak that
+    /// persists after reconnecting because of a leak blocks all traffic.
+    fn handle_leak_result(
+        &mut self,
+        result: CliResult<LeakReport>,
+        after_leak: bool,
+    ) -> CliResult<SessionAction> {
+        let leak = match result {
+            Ok(report) if report.is_leaking() => report,
+            Ok(_) => {
+                println!("Leak test passed");
+                return Ok(SessionAction::Continue);
+            }
+            Err(e) => {
+                println!("Leak test failed: {}", e);
+                return Ok(SessionAction::Continue);
+            }
+        };
+
+        Ok(match self.options.leak_reaction {
+            LeakReaction::Disconnect => {
+                println!("Detected {}, disconnecting...", leak);
+                SessionAction::Disconnect
+            }
+            LeakReaction::Reconnect if !after_leak => {
+                println!("Detected {}, reconnecting...", leak);
+                SessionAction::Reconnect { after_leak: true }
+            }
+            LeakReaction::Reconnect | LeakReaction::Block => {
+                println!("Detected {}, all traffic is blocked until Linkage is stopped", leak);
+                self.take_interface_down()?;
+                self.client.stop()?;
+                self.supervisor.set_status(self.client.get_status()?);
+                SessionAction::Continue
+            }
+        })
+    }
+
+    /// Removes the tunnel interface from the firewall if it's allowed.
+    fn take_interface_down(&mut self) -> CliResult<()> {
+        if let Some(interface_name) = self.interface_name.take() {
+            self.firewall_backend.on_interface_down(&interface_name)?;
+        }
 
         Ok(())
-    } else {
-        Ok(())
     }
 }
 
+/// How reconnecting ended.
+#[derive(Debug, PartialEq)]
+enum ReconnectOutcome {
+    /// The tunnel interface with the name is up and allowed by the firewall.
+    Connected(String),
+    /// A stop signal was received while waiting for the next attempt or while connecting.
+    Stopped(c_int),
+    /// All attempts of the reconnect policy failed.
+    GaveUp,
+}
+
+/// Restarts the client until the tunnel interface is up again or the attempts of the policy are
+/// used up. The rules of `on_pre_connect` stay in place the whole time, so nothing leaks while the
+/// tunnel is down.
+fn reconnect(
+    client: &mut dyn VpnClient,
+    firewall_backend: &dyn FirewallBackend,
+    supervisor: &mut Supervisor,
+    policy: &ReconnectPolicy,
+    connect_timeout: Duration,
+) -> CliResult<ReconnectOutcome> {
+    for attempt in 0..policy.max_retries {
+        let delay = policy.get_delay(attempt);
+        println!(
+            "Reconnecting in {} seconds, attempt {} of {}",
+            delay.as_secs(), attempt + 1, policy.max_retries,
+        );
+        if let Some(signal) = supervisor.sleep(delay)? {
+            return Ok(ReconnectOutcome::Stopped(signal));
+        }
+
+        client.stop()?;
+        let result = client.start().and_then(|_| {
+            client.wait_for_interface(connect_timeout, &mut || supervisor.stop_signal())
+        });
+        match result {
+            Ok(interface_name) => {
+                firewall_backend.on_post_connect(&interface_name)?;
+                return Ok(ReconnectOutcome::Connected(interface_name));
+            }
+            Err(CliError::Interrupted(signal)) => return Ok(ReconnectOutcome::Stopped(signal)),
+            Err(e) => println!("Reconnecting failed: {}", e),
+        }
+    }
+
+    Ok(ReconnectOutcome::GaveUp)
+}
+
 /// Checks if the program is running as root.
 fn root_check() -> CliResult<()> {
     if cfg!(windows) {
@@ -134,62 +672,29 @@ fn root_check() -> CliResult<()> {
     Ok(())
 }
 
+/// Restores the DNS configuration and the firewall, removes the state file at the supplied path
+/// and stops the supplied client.
 fn disconnect(
-    firewall_backend: &Box<dyn FirewallBackend + Sync>,
-    process_id: Option<u32>,
+    firewall_backend: &dyn FirewallBackend,
+    client: Option<&mut dyn VpnClient>,
+    state_path: &Path,
 ) -> CliResult<()> {
     println!("Exiting...");
+    // The DNS configuration is restored from the state file, just like after a crash
+    if let Some(dns) = State::load(state_path)?.and_then(|s| s.dns) {
+        restore_dns(&dns)?;
+    }
     // When disconnecting
     firewall_backend.on_disconnect()?;
+    State::remove(state_path)?;
 
-    // TODO: Move to own function
-    if let Some(id) = process_id {
-        unsafe {
-            libc::kill(id as i32, libc::SIGTERM);
-        }
+    if let Some(client) = client {
+        client.stop()?;
     }
 
     Ok(())
 }
 
-/// Parses the supplied configuration file using ovpnfile.
-fn parse_configuration_file(f: File) -> CliResult<Vec<FirewallException>> {
-    // TODO: Make this more modular to support other VPN applications, not only OpenVPN.
-    let parsed_file = ovpnfile::parse(f).map_err(|_| error::CliError::OvpnFile)?;
-
-    // Get the default settings
-    let mut default_protocol: Option<String> = None;
-    for d in parsed_file.directives() {
-        match d {
-            OvpnConfigDirective::Proto { p } => {
-                default_protocol = Some(p);
-            }
-            _ => (),
-        }
-    }
-
-    // Create the firewall exceptions
-    let mut exceptions: Vec<FirewallException> = Vec::new();
-    for d in parsed_file.directives() {
-        match d {
-            OvpnConfigDirective::Remote { host, port, proto } => {
-                // TODO: Handle the unwrap() calls here
-                let default_protocol_clone = default_protocol.clone();
-                exceptions.push(FirewallException::new(
-                    host.parse()?,
-                    port.unwrap().parse()?,
-                    proto
-                        .unwrap_or_else(|| default_protocol_clone.unwrap())
-                        .parse()?,
-                ));
-            }
-            _ => (),
-        }
-    }
-
-    Ok(exceptions)
-}
-
 /// Returns the options that were supplied to the application.
 fn get_config_matches<'a>() -> ClapArgMatches<'a> {
     ClapApp::new(consts::APP_NAME)
@@ -202,6 +707,558 @@ fn get_config_matches<'a>() -> ClapArgMatches<'a> {
                 .required(true)
                 .short("c")
                 .long("config")
-                .value_name("FILE")))
+                .value_name("FILE"))
+            .arg(ClapArg::with_name("type")
+                .help("the vpn client to use, defaults to wireguard for .conf files with an \
+                       [Interface] section and openvpn otherwise")
+                .short("t")
+                .long("type")
+                .value_name("TYPE")
+                .possible_values(&["openvpn", "wireguard"]))
+            .arg(ClapArg::with_name("settings")
+                .help("the settings file, defaults to /etc/linkage/linkage.toml")
+                .short("s")
+                .long("settings")
+                .value_name("FILE"))
+            .arg(ClapArg::with_name("allow")
+                .help("allows traffic to or from a network while the firewall is active, e.g. \
+                       tcp,192.168.1.0/24,445 or udp,10.0.0.5,1000-2000,in")
+                .short("a")
+                .long("allow")
+                .value_name("PROTOCOL,NETWORK[,PORTS][,in|out]")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(ClapArg::with_name("allow-lan")
+                .help("allows traffic to and from the local network, e.g. 192.168.0.0/16")
+                .long("allow-lan"))
+            .arg(ClapArg::with_name("allow-multicast")
+                .help("allows multicast traffic like mDNS in the local network")
+                .long("allow-multicast")
+                .requires("allow-lan"))
+            .arg(ClapArg::with_name("timeout")
+                .help("the seconds to wait for the connection before giving up, defaults to 30")
+                .long("timeout")
+                .value_name("SECONDS"))
+            .arg(ClapArg::with_name("reconnect-retries")
+                .help("how often reconnecting is attempted after the vpn client exited, defaults \
+                       to 5, 0 disables reconnecting")
+                .long("reconnect-retries")
+                .value_name("COUNT"))
+            .arg(ClapArg::with_name("reconnect-delay")
+                .help("the seconds before the first attempt to reconnect, which are doubled after \
+                       every failed attempt up to 60, defaults to 2")
+                .long("reconnect-delay")
+                .value_name("SECONDS"))
+            .arg(ClapArg::with_name("leak-check-interval")
+                .help("the seconds between the leak tests while connected, defaults to 300, 0 \
+                       disables them")
+                .long("leak-check-interval")
+                .value_name("SECONDS"))
+            .arg(ClapArg::with_name("leak-reaction")
+                .help("what happens when a leak is detected, defaults to disconnect")
+                .long("leak-reaction")
+                .value_name("REACTION")
+                .possible_values(&["block", "reconnect", "disconnect"]))
+            .arg(ClapArg::with_name("leak-provider")
+                .help("the service used for the leak tests, either ipleak, mullvad or a URL that \
+                       returns the IP address as plain text, which can't detect DNS leaks. \
+                       Defaults to ipleak")
+                .long("leak-provider")
+                .value_name("PROVIDER"))
+            .arg(ClapArg::with_name("leak-timeout")
+                .help("the seconds after which a request of the leak tests fails, defaults to 10")
+                .long("leak-timeout")
+                .value_name("SECONDS"))
+            .arg(ClapArg::with_name("leak-retries")
+                .help("how often a failed request of the leak tests is retried, defaults to 2")
+                .long("leak-retries")
+                .value_name("COUNT"))
+            .arg(ClapArg::with_name("leak-user-agent")
+                .help("the user agent of the requests of the leak tests")
+                .long("leak-user-agent")
+                .value_name("AGENT"))
+            .arg(ClapArg::with_name("leak-bind")
+                .help("the local address or interface that the requests of the leak tests are \
+                       sent from")
+                .long("leak-bind")
+                .value_name("ADDRESS|INTERFACE"))
+            .arg(ClapArg::with_name("leak-ca-cert")
+                .help("a PEM file with additional root certificates for the leak tests, e.g. of a \
+                       proxy")
+                .long("leak-ca-cert")
+                .value_name("FILE")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(ClapArg::with_name("leak-dns-requests")
+                .help("the number of requests of the DNS leak test, defaults to 100")
+                .long("leak-dns-requests")
+                .value_name("COUNT"))
+            .arg(ClapArg::with_name("leak-dns-min-successes")
+                .help("how many requests of the DNS leak test have to succeed for its result to \
+                       be trusted, defaults to half of them")
+                .long("leak-dns-min-successes")
+                .value_name("COUNT"))
+            .arg(ClapArg::with_name("dns")
+                .help("how the DNS servers pushed by the VPN server are applied, file rewrites \
+                       /etc/resolv.conf and auto uses resolvconf if it's installed and file \
+                       otherwise. Defaults to auto")
+                .long("dns")
+                .value_name("MODE")
+                .possible_values(&["auto", "file", "resolvconf", "off"])))
+        .subcommand(ClapApp::new("reset")
+            .about("restores the firewall after linkage didn't exit cleanly, e.g. after a crash"))
         .get_matches()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::fake::FakeClient;
+    use crate::dns::DnsMethod;
+    use linkage_firewall::{
+        FirewallError, FirewallExceptionProtocol, FirewallIdentifier, FirewallSnapshot,
+    };
+    use linkage_leaks::test_support::{MockLeakServer, MockResponses};
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A firewall backend that records the calls instead of changing the firewall.
+    struct FakeFirewall {
+        calls: RefCell<Vec<String>>,
+        /// Lets `on_pre_connect` fail.
+        fail_pre_connect: bool,
+    }
+
+    impl FakeFirewall {
+        fn new(fail_pre_connect: bool) -> FakeFirewall {
+            FakeFirewall {
+                calls: RefCell::new(Vec::new()),
+                fail_pre_connect,
+            }
+        }
+    }
+
+    impl FirewallBackend for FakeFirewall {
+        fn get_identifier(&self) -> FirewallIdentifier {
+            FirewallIdentifier::new("fake")
+        }
+
+        fn is_available(&self) -> Result<bool, FirewallError> {
+            Ok(true)
+        }
+
+        fn on_pre_connect(
+            &self,
+            exceptions: &[FirewallException],
+            options: &FirewallOptions,
+        ) -> Result<(), FirewallError> {
+            self.calls.borrow_mut().push(format!(
+                "on_pre_connect {} {}", exceptions.len(), options.allow_lan
+            ));
+            if self.fail_pre_connect {
+                return Err(FirewallError::IptablesError(Some(1)));
+            }
+            Ok(())
+        }
+
+        fn on_post_connect(&self, interface_identifier: &str) -> Result<(), FirewallError> {
+            self.calls.borrow_mut().push(format!("on_post_connect {}", interface_identifier));
+            Ok(())
+        }
+
+        fn on_interface_down(&self, interface_identifier: &str) -> Result<(), FirewallError> {
+            self.calls.borrow_mut().push(format!("on_interface_down {}", interface_identifier));
+            Ok(())
+        }
+
+        fn on_disconnect(&self) -> Result<(), FirewallError> {
+            self.calls.borrow_mut().push(String::from("on_disconnect"));
+            Ok(())
+        }
+
+        fn get_snapshot(&self) -> Option<FirewallSnapshot> {
+            None
+        }
+
+        fn on_reset(&self, _snapshot: Option<&FirewallSnapshot>) -> Result<(), FirewallError> {
+            self.calls.borrow_mut().push(String::from("on_reset"));
+            Ok(())
+        }
+    }
+
+    /// Returns a supervisor that doesn't handle any signals, so it can't interfere with other
+    /// tests.
+    fn supervisor() -> CliResult<Supervisor> {
+        Supervisor::with_signals(&[], &[], Duration::from_secs(1), VpnStatus::Stopped)
+    }
+
+    /// Returns a path for a state file that is unique to the supplied test.
+    fn state_path(test: &str) -> PathBuf {
+        env::temp_dir()
+            .join(format!("linkage-test-{}-{}", test, process::id()))
+            .join("state.json")
+    }
+
+    #[test]
+    fn test_establish_connection() -> CliResult<()> {
+        let path = state_path("establish-connection");
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(vec![
+            FirewallException::new("1.1.1.1".parse()?, 1194, FirewallExceptionProtocol::UDP),
+        ], Some("tun0"));
+
+        let interface_name = establish_connection(
+            &mut client,
+            &firewall,
+            &mut supervisor()?,
+            &[FirewallException::new("9.9.9.9".parse()?, 53, FirewallExceptionProtocol::UDP)],
+            &FirewallOptions { allow_lan: true, ..FirewallOptions::default() },
+            Duration::from_secs(1),
+            &path,
+        )?;
+
+        // The exceptions of the client are merged with the supplied ones
+        assert_eq!("tun0", interface_name);
+        assert_eq!(
+            vec![String::from("on_pre_connect 2 true"), String::from("on_post_connect tun0")],
+            *firewall.calls.borrow()
+        );
+        assert_eq!(vec!["start", "wait_for_interface"], client.calls);
+        assert_eq!(VpnStatus::Connected(String::from("tun0")), client.get_status()?);
+        assert_eq!("fake", State::load(&path)?.unwrap().backend);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_establish_connection_without_interface() -> CliResult<()> {
+        let path = state_path("without-interface");
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), None);
+
+        assert!(establish_connection(
+            &mut client,
+            &firewall,
+            &mut supervisor()?,
+            &[],
+            &FirewallOptions::default(),
+            Duration::from_secs(1),
+            &path,
+        ).is_err());
+
+        // The tunnel is never allowed and everything is torn down again
+        assert_eq!(
+            vec![String::from("on_pre_connect 0 false"), String::from("on_disconnect")],
+            *firewall.calls.borrow()
+        );
+        assert_eq!(vec!["start", "wait_for_interface", "stop"], client.calls);
+        assert_eq!(None, State::load(&path)?);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_establish_connection_interrupted() -> CliResult<()> {
+        let path = state_path("interrupted");
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        let mut supervisor = Supervisor::with_signals(
+            &[libc::SIGVTALRM], &[], Duration::from_secs(1), VpnStatus::Stopped,
+        )?;
+
+        unsafe {
+            libc::raise(libc::SIGVTALRM);
+        }
+        assert!(matches!(
+            establish_connection(
+                &mut client,
+                &firewall,
+                &mut supervisor,
+                &[],
+                &FirewallOptions::default(),
+                Duration::from_secs(1),
+                &path,
+            ),
+            Err(CliError::Interrupted(libc::SIGVTALRM))
+        ));
+
+        // Connecting is given up and everything is torn down again
+        assert_eq!(vec!["start", "wait_for_interface", "stop", "stop"], client.calls);
+        assert_eq!(
+            vec![String::from("on_pre_connect 0 false"), String::from("on_disconnect")],
+            *firewall.calls.borrow()
+        );
+        assert_eq!(None, State::load(&path)?);
+        // The session would stop right away as well
+        assert_eq!(Some(libc::SIGVTALRM), supervisor.sleep(Duration::from_secs(10))?);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    /// Returns a policy that attempts to reconnect three times without waiting long.
+    fn reconnect_policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn test_reconnect() -> CliResult<()> {
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun1"));
+        client.failing_attempts = 2;
+        // The signal handlers are global, so every test that runs in parallel uses its own signals
+        let mut supervisor = Supervisor::with_signals(
+            &[libc::SIGURG], &[], Duration::from_secs(1), VpnStatus::Exited(Some(1)),
+        )?;
+
+        assert_eq!(ReconnectOutcome::Connected(String::from("tun1")), reconnect(
+            &mut client, &firewall, &mut supervisor, &reconnect_policy(), Duration::from_secs(1),
+        )?);
+
+        // The client is restarted until the interface is up, which is allowed afterwards
+        assert_eq!(vec![
+            "stop", "start", "wait_for_interface",
+            "stop", "start", "wait_for_interface",
+            "stop", "start", "wait_for_interface",
+        ], client.calls);
+        assert_eq!(vec![String::from("on_post_connect tun1")], *firewall.calls.borrow());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconnect_gave_up() -> CliResult<()> {
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), None);
+        let mut supervisor = Supervisor::with_signals(
+            &[libc::SIGPROF], &[], Duration::from_secs(1), VpnStatus::Exited(Some(1)),
+        )?;
+
+        assert_eq!(ReconnectOutcome::GaveUp, reconnect(
+            &mut client, &firewall, &mut supervisor, &reconnect_policy(), Duration::from_secs(1),
+        )?);
+        assert_eq!(9, client.calls.len());
+        // The firewall keeps blocking everything
+        assert!(firewall.calls.borrow().is_empty());
+
+        // Reconnecting can be disabled
+        let mut client = FakeClient::new(Vec::new(), Some("tun1"));
+        let policy = ReconnectPolicy { max_retries: 0, ..reconnect_policy() };
+        assert_eq!(ReconnectOutcome::GaveUp, reconnect(
+            &mut client, &firewall, &mut supervisor, &policy, Duration::from_secs(1),
+        )?);
+        assert!(client.calls.is_empty());
+
+        Ok(())
+    }
+
+    /// Returns the results of the leak tests against the supplied server.
+    fn leak_baseline(server: &MockLeakServer) -> CliResult<LeakBaseline> {
+        LeakBaseline::capture(Box::new(server.provider()), DnsTestOptions::new(10))
+    }
+
+    /// Returns a session that is connected through `tun0` and reacts to leaks as supplied. Its
+    /// supervisor doesn't handle any signals and its timer elapses quickly.
+    fn connected_session<'a>(
+        client: &'a mut FakeClient,
+        firewall: &'a FakeFirewall,
+        leak_baseline: &'a LeakBaseline,
+        state_path: &'a Path,
+        leak_reaction: LeakReaction,
+    ) -> CliResult<Session<'a>> {
+        Ok(Session {
+            client,
+            firewall_backend: firewall,
+            supervisor: Supervisor::with_signals(
+                &[], &[], Duration::from_millis(10), VpnStatus::Connected(String::from("tun0")),
+            )?,
+            interface_name: Some(String::from("tun0")),
+            leak_baseline,
+            dns_manager: None,
+            state_path,
+            options: SessionOptions {
+                connect_timeout: Duration::from_secs(1),
+                reconnect_policy: reconnect_policy(),
+                leak_reaction,
+            },
+        })
+    }
+
+    #[test]
+    fn test_session_run() -> CliResult<()> {
+        let server = MockLeakServer::start(MockResponses::default())?;
+        let leak_baseline = leak_baseline(&server)?;
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        let path = state_path("session-run");
+
+        // Nothing changed since the baseline, so everything leaks and the session ends right
+        // after connecting
+        connected_session(
+            &mut client, &firewall, &leak_baseline, &path, LeakReaction::Disconnect,
+        )?.run(None)?;
+
+        // Tearing down is left to `disconnect`
+        assert!(firewall.calls.borrow().is_empty());
+        assert!(client.calls.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_gave_up() -> CliResult<()> {
+        let server = MockLeakServer::start(MockResponses::default())?;
+        let leak_baseline = leak_baseline(&server)?;
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        client.failing_attempts = 10;
+        client.exit(Some(1));
+        let path = state_path("session-gave-up");
+        let mut session = connected_session(
+            &mut client, &firewall, &leak_baseline, &path, LeakReaction::Disconnect,
+        )?;
+
+        assert_eq!(SessionAction::Reconnect { after_leak: false }, session.handle_event(None)?);
+        assert_eq!(SessionAction::Continue, session.reconnect(false)?);
+        // The stopped client isn't restarted again after the attempts are used up
+        assert_eq!(SessionAction::Continue, session.handle_event(None)?);
+        assert_eq!(SessionAction::Continue, session.handle_event(None)?);
+        drop(session);
+
+        assert_eq!(vec![String::from("on_interface_down tun0")], *firewall.calls.borrow());
+        assert_eq!(9, client.calls.len());
+        assert_eq!(VpnStatus::Stopped, client.get_status()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_leak_reactions() -> CliResult<()> {
+        let server = MockLeakServer::start(MockResponses::default())?;
+        let leak_baseline = leak_baseline(&server)?;
+        let path = state_path("session-leak-reactions");
+        let leak = LeakReport { ipv4: Some("1.1.1.1".parse()?), ..LeakReport::default() };
+
+        // Results without a leak never change anything
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        let mut session = connected_session(
+            &mut client, &firewall, &leak_baseline, &path, LeakReaction::Disconnect,
+        )?;
+        assert_eq!(
+            SessionAction::Continue,
+            session.handle_leak_result(Ok(LeakReport::default()), false)?
+        );
+        assert_eq!(
+            SessionAction::Continue,
+            session.handle_leak_result(Err(CliError::InterfaceParseError), false)?
+        );
+        assert_eq!(SessionAction::Disconnect, session.handle_leak_result(Ok(leak.clone()), false)?);
+
+        // Reconnecting is only attempted once, a persisting leak blocks everything
+        let mut session = connected_session(
+            &mut client, &firewall, &leak_baseline, &path, LeakReaction::Reconnect,
+        )?;
+        assert_eq!(
+            SessionAction::Reconnect { after_leak: true },
+            session.handle_leak_result(Ok(leak.clone()), false)?
+        );
+        assert_eq!(SessionAction::Continue, session.handle_leak_result(Ok(leak.clone()), true)?);
+        assert_eq!(None, session.interface_name);
+        drop(session);
+        assert_eq!(vec![String::from("on_interface_down tun0")], *firewall.calls.borrow());
+        assert_eq!(vec!["stop"], client.calls);
+
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        let mut session = connected_session(
+            &mut client, &firewall, &leak_baseline, &path, LeakReaction::Block,
+        )?;
+        assert_eq!(SessionAction::Continue, session.handle_leak_result(Ok(leak), false)?);
+        drop(session);
+        assert_eq!(vec![String::from("on_interface_down tun0")], *firewall.calls.borrow());
+        assert_eq!(vec!["stop"], client.calls);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_dns() -> CliResult<()> {
+        let path = state_path("apply-dns");
+        let resolv_conf_path = path.with_file_name("resolv.conf");
+        let firewall = FakeFirewall::new(false);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        let dns_manager = DnsManager::with_paths(
+            DnsMethod::File, &resolv_conf_path, &path.with_file_name("resolv.conf.backup"),
+        );
+
+        establish_connection(
+            &mut client,
+            &firewall,
+            &mut supervisor()?,
+            &[],
+            &FirewallOptions::default(),
+            Duration::from_secs(1),
+            &path,
+        )?;
+        fs::write(&resolv_conf_path, "nameserver 192.168.1.1\n")?;
+
+        // Nothing is changed if the VPN server didn't push any DNS servers
+        apply_dns(&dns_manager, &client, "tun0", &firewall, &path)?;
+        assert_eq!("nameserver 192.168.1.1\n", fs::read_to_string(&resolv_conf_path)?);
+        assert_eq!(None, State::load(&path)?.unwrap().dns);
+
+        client.dns_servers = vec!["10.8.0.1".parse()?];
+        apply_dns(&dns_manager, &client, "tun0", &firewall, &path)?;
+        assert!(fs::read_to_string(&resolv_conf_path)?.ends_with("\nnameserver 10.8.0.1\n"));
+        assert!(State::load(&path)?.unwrap().dns.is_some());
+
+        // Disconnecting restores the DNS configuration using the state file
+        disconnect(&firewall, Some(&mut client), &path)?;
+        assert_eq!("nameserver 192.168.1.1\n", fs::read_to_string(&resolv_conf_path)?);
+        assert_eq!(None, State::load(&path)?);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_establish_connection_firewall_error() -> CliResult<()> {
+        let path = state_path("firewall-error");
+        let firewall = FakeFirewall::new(true);
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+
+        assert!(matches!(
+            establish_connection(
+                &mut client,
+                &firewall,
+                &mut supervisor()?,
+                &[],
+                &FirewallOptions::default(),
+                Duration::from_secs(1),
+                &path,
+            ),
+            Err(CliError::Firewall(_))
+        ));
+
+        // The client must not be started without the firewall, which is torn down again
+        assert_eq!(vec!["stop"], client.calls);
+        assert_eq!(
+            vec![String::from("on_pre_connect 0 false"), String::from("on_disconnect")],
+            *firewall.calls.borrow()
+        );
+        assert_eq!(None, State::load(&path)?);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Runs the leak tests periodically in the background while connected, so leaks are detected
+//! during the whole session and not only right after connecting.
+
+use crate::error::{CliError, CliResult};
+use crate::supervisor::Waker;
+use linkage_leaks::LeakReport;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// What Linkage does when a leak is detected.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LeakReaction {
+    /// Blocks all traffic by removing the tunnel interface from the firewall and stopping the VPN
+    /// client, until Linkage is stopped.
+    Block,
+    /// Restarts the VPN client. If the leak persists afterwards, all traffic is blocked.
+    Reconnect,
+    /// Disconnects and restores the firewall.
+    Disconnect,
+}
+
+impl FromStr for LeakReaction {
+    type Err = CliError;
+
+    /// Converts the supplied string into LeakReaction.
+    /// # Values
+    /// - Block: `block`
+    /// - Reconnect: `reconnect`
+    /// - Disconnect: `disconnect`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(LeakReaction::Block),
+            "reconnect" => Ok(LeakReaction::Reconnect),
+            "disconnect" => Ok(LeakReaction::Disconnect),
+            _ => Err(CliError::UnknownLeakReaction(s.to_string())),
+        }
+    }
+}
+
+/// Runs a leak test in a background thread after every interval and wakes the supervisor up once
+/// a result is available. The thread stops when the monitor is dropped.
+pub(crate) struct LeakMonitor {
+    results: Receiver<CliResult<LeakReport>>,
+    /// Dropping it disconnects the channel, which stops the thread.
+    _stop: Sender<()>,
+}
+
+impl LeakMonitor {
+    /// Starts running the supplied leak test after every interval.
+    pub fn start<F>(interval: Duration, waker: Waker, detect_leak: F) -> LeakMonitor
+    where
+        F: Fn() -> CliResult<LeakReport> + Send + 'static,
+    {
+        let (result_sender, results) = mpsc::channel();
+        let (stop, stop_receiver) = mpsc::channel::<()>();
+
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                if result_sender.send(detect_leak()).is_err() {
+                    break;
+                }
+                waker.wake();
+            }
+        });
+
+        LeakMonitor { results, _stop: stop }
+    }
+
+    /// Returns the result of the next leak test that finished, without blocking.
+    pub fn try_result(&self) -> Option<CliResult<LeakReport>> {
+        self.results.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::fake::FakeClient;
+    use crate::clients::VpnStatus;
+    use crate::supervisor::{Supervisor, SupervisorEvent};
+
+    #[test]
+    fn test_leak_reaction_from_str() {
+        assert_eq!(LeakReaction::Block, LeakReaction::from_str("block").unwrap());
+        assert_eq!(LeakReaction::Reconnect, LeakReaction::from_str("reconnect").unwrap());
+        assert_eq!(LeakReaction::Disconnect, LeakReaction::from_str("disconnect").unwrap());
+        assert!(matches!(
+            LeakReaction::from_str("ignore"), Err(CliError::UnknownLeakReaction(_))
+        ));
+    }
+
+    #[test]
+    fn test_leak_monitor() -> CliResult<()> {
+        let mut client = FakeClient::new(Vec::new(), None);
+        let mut supervisor = Supervisor::with_signals(
+            &[], &[], Duration::from_secs(10), VpnStatus::Stopped,
+        )?;
+        let monitor = LeakMonitor::start(
+            Duration::from_millis(10), supervisor.waker()?, || Ok(LeakReport::default()),
+        );
+
+        // The supervisor is woken up long before the next tick
+        assert_eq!(None, monitor.try_result().map(|r| r.unwrap()));
+        assert_eq!(SupervisorEvent::Wake, supervisor.wait(&mut client)?);
+        assert_eq!(Some(LeakReport::default()), monitor.try_result().map(|r| r.unwrap()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! The policy that decides how often and when Linkage tries to reconnect after the VPN client
+//! exited.
+
+use std::time::Duration;
+
+/// How often and when reconnecting is attempted. The delay before the first attempt is doubled
+/// after every failed attempt, up to the maximum delay.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct ReconnectPolicy {
+    /// The number of attempts before giving up. Reconnecting is disabled if this is 0.
+    pub max_retries: u32,
+    /// The delay before the first attempt.
+    pub initial_delay: Duration,
+    /// The longest delay between two attempts.
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay before the supplied attempt, starting at 0.
+    pub fn get_delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_delay, |d| d.min(self.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_policy_get_delay() {
+        let policy = ReconnectPolicy {
+            max_retries: 10,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        };
+
+        assert_eq!(Duration::from_secs(2), policy.get_delay(0));
+        assert_eq!(Duration::from_secs(4), policy.get_delay(1));
+        assert_eq!(Duration::from_secs(32), policy.get_delay(4));
+        assert_eq!(Duration::from_secs(60), policy.get_delay(5));
+        // Doesn't overflow
+        assert_eq!(Duration::from_secs(60), policy.get_delay(u32::MAX));
+    }
+}
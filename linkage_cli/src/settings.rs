@@ -0,0 +1,181 @@
+//! The settings of Linkage, which are read from a TOML file. All settings are optional, so a
+//! missing settings file is the same as an empty one.
+
+use crate::dns::DnsMode;
+use crate::error::CliResult;
+use crate::monitor::LeakReaction;
+use linkage_firewall::FirewallException;
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Everything that can be configured in the settings file.
+#[derive(Deserialize, Debug, PartialEq, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Settings {
+    /// Additional firewall exceptions in the same format as the `--allow` option.
+    pub allow: Vec<String>,
+    /// Allows traffic to and from the local network, like the `--allow-lan` option.
+    pub allow_lan: bool,
+    /// Allows multicast traffic together with `allow_lan`, like the `--allow-multicast` option.
+    pub allow_multicast: bool,
+    /// The seconds to wait for the connection, like the `--timeout` option.
+    pub connect_timeout: Option<u64>,
+    /// How often reconnecting is attempted, like the `--reconnect-retries` option.
+    pub reconnect_retries: Option<u32>,
+    /// The seconds before the first attempt to reconnect, like the `--reconnect-delay` option.
+    pub reconnect_delay: Option<u64>,
+    /// The seconds between the leak tests while connected, like the `--leak-check-interval`
+    /// option.
+    pub leak_check_interval: Option<u64>,
+    /// What happens when a leak is detected, like the `--leak-reaction` option.
+    pub leak_reaction: Option<LeakReaction>,
+    /// The service used for the leak tests, like the `--leak-provider` option.
+    pub leak_provider: Option<String>,
+    /// The seconds after which a request of the leak tests fails, like the `--leak-timeout`
+    /// option.
+    pub leak_timeout: Option<u64>,
+    /// How often a failed request of the leak tests is retried, like the `--leak-retries` option.
+    pub leak_retries: Option<u32>,
+    /// The user agent of the requests of the leak tests, like the `--leak-user-agent` option.
+    pub leak_user_agent: Option<String>,
+    /// The local address or interface of the leak tests, like the `--leak-bind` option.
+    pub leak_bind: Option<String>,
+    /// Additional root certificates for the leak tests, which are added to the ones of the
+    /// `--leak-ca-cert` option.
+    pub leak_ca_certificates: Vec<String>,
+    /// The number of requests of the DNS leak test, like the `--leak-dns-requests` option.
+    pub leak_dns_requests: Option<u32>,
+    /// How many requests of the DNS leak test have to succeed, like the
+    /// `--leak-dns-min-successes` option.
+    pub leak_dns_min_successes: Option<u32>,
+    /// How the DNS servers of the VPN server are applied, like the `--dns` option.
+    pub dns: Option<DnsMode>,
+}
+
+impl Settings {
+    /// Reads the settings from the supplied path. Returns `None` if there is no settings file.
+    pub fn load(path: &Path) -> CliResult<Option<Settings>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(toml::from_str(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the firewall exceptions of the `allow` setting.
+    pub fn get_exceptions(&self) -> CliResult<Vec<FirewallException>> {
+        parse_exceptions(self.allow.iter().map(String::as_str))
+    }
+}
+
+/// Parses the supplied exceptions, see `FirewallException::from_str` for the format.
+pub(crate) fn parse_exceptions<'a, I: IntoIterator<Item = &'a str>>(
+    exceptions: I,
+) -> CliResult<Vec<FirewallException>> {
+    let mut parsed = Vec::new();
+    for e in exceptions {
+        parsed.push(e.parse()?);
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CliError;
+    use linkage_firewall::{Direction, FirewallExceptionProtocol, IpNetwork, PortRange};
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_settings_load() -> CliResult<()> {
+        let directory = env::temp_dir().join(format!("linkage-settings-test-{}", process::id()));
+        let path = directory.join("linkage.toml");
+        fs::create_dir_all(&directory)?;
+
+        assert_eq!(None, Settings::load(&path)?);
+
+        fs::write(&path, "")?;
+        assert_eq!(Some(Settings::default()), Settings::load(&path)?);
+
+        fs::write(&path, "allow = [\"tcp,192.168.1.10,445\", \"icmp,192.168.1.1\"]\n")?;
+        assert_eq!(Some(Settings {
+            allow: vec![String::from("tcp,192.168.1.10,445"), String::from("icmp,192.168.1.1")],
+            ..Settings::default()
+        }), Settings::load(&path)?);
+
+        fs::write(&path, "allow_lan = true\nallow_multicast = true\nconnect_timeout = 60\n\
+                          reconnect_retries = 3\nreconnect_delay = 5\n\
+                          leak_check_interval = 600\nleak_reaction = \"block\"\n\
+                          leak_provider = \"mullvad\"\nleak_timeout = 5\nleak_retries = 0\n\
+                          leak_user_agent = \"curl/8.0\"\nleak_bind = \"eth0\"\n\
+                          leak_ca_certificates = [\"/etc/ssl/proxy.pem\"]\n\
+                          leak_dns_requests = 20\nleak_dns_min_successes = 5\n\
+                          dns = \"resolvconf\"\n")?;
+        assert_eq!(Some(Settings {
+            allow: Vec::new(),
+            allow_lan: true,
+            allow_multicast: true,
+            connect_timeout: Some(60),
+            reconnect_retries: Some(3),
+            reconnect_delay: Some(5),
+            leak_check_interval: Some(600),
+            leak_reaction: Some(LeakReaction::Block),
+            leak_provider: Some(String::from("mullvad")),
+            leak_timeout: Some(5),
+            leak_retries: Some(0),
+            leak_user_agent: Some(String::from("curl/8.0")),
+            leak_bind: Some(String::from("eth0")),
+            leak_ca_certificates: vec![String::from("/etc/ssl/proxy.pem")],
+            leak_dns_requests: Some(20),
+            leak_dns_min_successes: Some(5),
+            dns: Some(DnsMode::Resolvconf),
+        }), Settings::load(&path)?);
+
+        // Typos shouldn't be ignored silently
+        fs::write(&path, "alow = []\n")?;
+        assert!(matches!(Settings::load(&path), Err(CliError::Settings(_))));
+
+        fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_get_exceptions() -> CliResult<()> {
+        let settings = Settings {
+            allow: vec![String::from("udp,10.0.0.0/8,5000-5100,in")],
+            ..Settings::default()
+        };
+
+        assert_eq!(vec![
+            FirewallException::from_network(
+                "10.0.0.0/8".parse::<IpNetwork>().unwrap(), FirewallExceptionProtocol::UDP
+            )
+                .ports(PortRange::new(5000, 5100))
+                .direction(Direction::Input),
+        ], settings.get_exceptions()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_exceptions() -> CliResult<()> {
+        assert_eq!(
+            vec![
+                FirewallException::new("1.1.1.1".parse()?, 53, FirewallExceptionProtocol::UDP),
+                FirewallException::new("1.1.1.1".parse()?, 53, FirewallExceptionProtocol::TCP),
+            ],
+            parse_exceptions(vec!["udp,1.1.1.1,53", "tcp,1.1.1.1,53"])?
+        );
+        assert!(parse_exceptions(Vec::new())?.is_empty());
+        assert!(matches!(
+            parse_exceptions(vec!["udp,1.1.1.1,53", "udp"]),
+            Err(CliError::FirewallException(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Persists the state of the firewall while connected, so it can be restored after Linkage exited
+//! without cleaning up, e.g. because it crashed or was killed.
+
+use crate::dns::DnsState;
+use crate::error::CliResult;
+use linkage_firewall::{FirewallBackend, FirewallSnapshot};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The rulesets from before the firewall was set up. Mirrors FirewallSnapshot.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct SnapshotState {
+    pub v4: String,
+    pub v6: String,
+}
+
+impl From<FirewallSnapshot> for SnapshotState {
+    fn from(s: FirewallSnapshot) -> Self {
+        SnapshotState { v4: s.v4, v6: s.v6 }
+    }
+}
+
+impl From<SnapshotState> for FirewallSnapshot {
+    fn from(s: SnapshotState) -> Self {
+        FirewallSnapshot { v4: s.v4, v6: s.v6 }
+    }
+}
+
+/// Everything that is needed to restore the system after an unclean exit.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct State {
+    /// The identifier of the firewall backend that was set up.
+    pub backend: String,
+    /// The rulesets from before the firewall was set up, if the backend saved them.
+    pub snapshot: Option<SnapshotState>,
+    /// The DNS configuration that was applied for the tunnel, if any.
+    #[serde(default)]
+    pub dns: Option<DnsState>,
+}
+
+impl State {
+    /// Returns the current state of the supplied firewall backend.
+    pub fn from_backend(backend: &dyn FirewallBackend) -> State {
+        State {
+            backend: backend.get_identifier().to_string(),
+            snapshot: backend.get_snapshot().map(SnapshotState::from),
+            dns: None,
+        }
+    }
+
+    /// Reads the state from the supplied path. Returns `None` if there is no state file.
+    pub fn load(path: &Path) -> CliResult<Option<State>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the state to the supplied path. The file is written next to it first and renamed
+    /// afterwards, so there is never a partially written state file.
+    pub fn save(&self, path: &Path) -> CliResult<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, serde_json::to_string(self)?)?;
+        fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+
+    /// Removes the state file at the supplied path. Does nothing if it doesn't exist.
+    pub fn remove(path: &Path) -> CliResult<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_state_save_load_remove() -> CliResult<()> {
+        let path = env::temp_dir()
+            .join(format!("linkage-test-{}", process::id()))
+            .join("state.json");
+        let state = State {
+            backend: String::from("iptables"),
+            snapshot: Some(SnapshotState {
+                v4: String::from("*filter\nCOMMIT\n"),
+                v6: String::from("*filter\nCOMMIT\n"),
+            }),
+            dns: Some(DnsState::Resolvconf {
+                interface_name: String::from("tun0"),
+            }),
+        };
+
+        assert_eq!(None, State::load(&path)?);
+        state.save(&path)?;
+        assert_eq!(Some(state), State::load(&path)?);
+        State::remove(&path)?;
+        assert_eq!(None, State::load(&path)?);
+        // Removing it twice is fine
+        State::remove(&path)?;
+
+        // State files of older versions don't have the DNS configuration
+        fs::write(&path, "{\"backend\":\"nftables\",\"snapshot\":null}")?;
+        assert_eq!(None, State::load(&path)?.unwrap().dns);
+        State::remove(&path)?;
+
+        fs::remove_dir(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_state_conversion() {
+        let snapshot = FirewallSnapshot {
+            v4: String::from("v4"),
+            v6: String::from("v6"),
+        };
+        let state = SnapshotState::from(snapshot.clone());
+        assert_eq!(SnapshotState {
+            v4: String::from("v4"),
+            v6: String::from("v6"),
+        }, state);
+        assert_eq!(snapshot, FirewallSnapshot::from(state));
+    }
+}
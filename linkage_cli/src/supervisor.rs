@@ -0,0 +1,369 @@
+//! Supervises the VPN client while connected. Instead of polling in a busy loop, Linkage sleeps
+//! until a signal is received, a child process exits or the timer elapses.
+
+use crate::clients::{VpnClient, VpnStatus};
+use crate::error::CliResult;
+use std::io;
+use std::os::raw::{c_int, c_void};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+/// The signals that ask Linkage to disconnect and exit.
+const STOP_SIGNALS: [c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+/// The number of signals that can be handled, which is the highest signal number on Linux plus
+/// one.
+const SIGNAL_COUNT: usize = 65;
+
+/// The value of SIGNAL_PIPES for signals that aren't handled.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_PIPE: AtomicI32 = AtomicI32::new(-1);
+/// The write end of the pipe for every signal that is handled by a SignalPipe, or -1.
+static SIGNAL_PIPES: [AtomicI32; SIGNAL_COUNT] = [NO_PIPE; SIGNAL_COUNT];
+
+/// The byte that is written to the pipe by a Waker. There is no signal with the number 0.
+const WAKE_BYTE: u8 = 0;
+
+/// Writes the number of the signal to its pipe. Only async-signal-safe functions may be used here.
+extern "C" fn handle_signal(signal: c_int) {
+    if let Some(fd) = SIGNAL_PIPES.get(signal as usize) {
+        let fd = fd.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let byte = signal as u8;
+            unsafe {
+                libc::write(fd, &byte as *const u8 as *const c_void, 1);
+            }
+        }
+    }
+}
+
+/// Turns signals into bytes on a pipe, so they can be waited for with a timeout. The handlers are
+/// reset to their defaults when it's dropped.
+pub(crate) struct SignalPipe {
+    signals: Vec<c_int>,
+    read_fd: c_int,
+    write_fd: c_int,
+}
+
+impl SignalPipe {
+    /// Installs handlers for the supplied signals.
+    pub fn new(signals: &[c_int]) -> CliResult<SignalPipe> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let signal_pipe = SignalPipe {
+            signals: signals.to_vec(),
+            read_fd: fds[0],
+            write_fd: fds[1],
+        };
+
+        for signal in signals {
+            SIGNAL_PIPES[*signal as usize].store(signal_pipe.write_fd, Ordering::SeqCst);
+            // Interrupted system calls are restarted, so the rest of Linkage isn't affected
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = handle_signal as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            if unsafe { libc::sigaction(*signal, &action, std::ptr::null_mut()) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        Ok(signal_pipe)
+    }
+
+    /// Returns a Waker that interrupts `wait` from another thread.
+    pub fn waker(&self) -> CliResult<Waker> {
+        let fd = unsafe { libc::fcntl(self.write_fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Waker { fd })
+    }
+
+    /// Blocks until one of the signals is received, a Waker was used or the timeout elapsed.
+    /// Returns the signal, `Some(0)` for a Waker, or `None` after the timeout.
+    pub fn wait(&mut self, timeout: Duration) -> CliResult<Option<c_int>> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.read_fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Rounded up, so the timeout never elapses early
+        let timeout_ms = timeout.as_micros().div_ceil(1000);
+        let timeout_ms = timeout_ms.min(c_int::MAX as u128) as c_int;
+
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                // Another signal interrupted the wait
+                if e.kind() == io::ErrorKind::Interrupted {
+                    return Ok(None);
+                }
+                Err(e.into())
+            }
+            0 => Ok(None),
+            _ => {
+                let mut byte = 0u8;
+                match unsafe { libc::read(self.read_fd, &mut byte as *mut u8 as *mut c_void, 1) } {
+                    1 => Ok(Some(c_int::from(byte))),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for signal in &self.signals {
+            unsafe {
+                libc::signal(*signal, libc::SIG_DFL);
+            }
+            SIGNAL_PIPES[*signal as usize].store(-1, Ordering::SeqCst);
+        }
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// Wakes up a SignalPipe from another thread. It has its own copy of the write end of the pipe,
+/// so it stays valid after the SignalPipe was dropped.
+pub(crate) struct Waker {
+    fd: c_int,
+}
+
+impl Waker {
+    /// Interrupts the current or next `wait` of the SignalPipe.
+    pub fn wake(&self) {
+        unsafe {
+            libc::write(self.fd, &WAKE_BYTE as *const u8 as *const c_void, 1);
+        }
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Something that happened while connected.
+#[derive(Debug, PartialEq)]
+pub(crate) enum SupervisorEvent {
+    /// A signal that asks Linkage to exit was received.
+    Stop(c_int),
+    /// The VPN client exited on its own with the exit code, if there is one.
+    ClientExited(Option<i32>),
+    /// The status of the VPN client changed.
+    StatusChanged(VpnStatus),
+    /// The interval of the timer elapsed.
+    Tick,
+    /// Another thread used a Waker, e.g. because a leak test finished.
+    Wake,
+}
+
+/// Waits for the events of a connection.
+pub(crate) struct Supervisor {
+    signal_pipe: SignalPipe,
+    /// The signals that cause a `Stop` event.
+    stop_signals: Vec<c_int>,
+    /// The time between two `Tick` events. The status of the client is checked as well.
+    interval: Duration,
+    next_tick: Instant,
+    /// The last status of the client, so only changes are reported.
+    status: VpnStatus,
+    /// The stop signal that was received while connecting, so it isn't lost for later.
+    stop_signal: Option<c_int>,
+}
+
+impl Supervisor {
+    /// Returns a new instance of Supervisor that handles the stop signals and `SIGCHLD`, so the
+    /// exit of the VPN client is noticed immediately.
+    pub fn new(interval: Duration, status: VpnStatus) -> CliResult<Supervisor> {
+        Supervisor::with_signals(&STOP_SIGNALS, &[libc::SIGCHLD], interval, status)
+    }
+
+    /// Returns a new instance of Supervisor that stops on the supplied stop signals and checks the
+    /// status of the client on the other signals.
+    pub(crate) fn with_signals(
+        stop_signals: &[c_int],
+        other_signals: &[c_int],
+        interval: Duration,
+        status: VpnStatus,
+    ) -> CliResult<Supervisor> {
+        let signals: Vec<c_int> = stop_signals.iter().chain(other_signals).copied().collect();
+        Ok(Supervisor {
+            signal_pipe: SignalPipe::new(&signals)?,
+            stop_signals: stop_signals.to_vec(),
+            interval,
+            next_tick: Instant::now() + interval,
+            status,
+            stop_signal: None,
+        })
+    }
+
+    /// Returns a Waker that causes a `Wake` event from another thread.
+    pub fn waker(&self) -> CliResult<Waker> {
+        self.signal_pipe.waker()
+    }
+
+    /// Sets the status that the next status is compared with, e.g. after the client was restarted.
+    pub fn set_status(&mut self, status: VpnStatus) {
+        self.status = status;
+    }
+
+    /// Returns the stop signal that was received, without blocking. Used while connecting, which
+    /// is why other signals and Wakers are ignored: the status of the client is only checked once
+    /// connected, and leak tests from before that are meaningless.
+    pub fn stop_signal(&mut self) -> CliResult<Option<c_int>> {
+        while self.stop_signal.is_none() {
+            match self.signal_pipe.wait(Duration::from_secs(0))? {
+                Some(signal) if self.stop_signals.contains(&signal) => {
+                    self.stop_signal = Some(signal);
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        Ok(self.stop_signal)
+    }
+
+    /// Blocks for the supplied duration. Returns early with the signal if a stop signal is
+    /// received.
+    pub fn sleep(&mut self, duration: Duration) -> CliResult<Option<c_int>> {
+        if self.stop_signal.is_some() {
+            return Ok(self.stop_signal);
+        }
+        let end = Instant::now() + duration;
+        loop {
+            let remaining = end.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Ok(None);
+            }
+            let signal = self.signal_pipe.wait(remaining)?;
+            if let Some(signal) = signal.filter(|s| self.stop_signals.contains(s)) {
+                return Ok(Some(signal));
+            }
+        }
+    }
+
+    /// Blocks until the next event and returns it.
+    pub fn wait(&mut self, client: &mut dyn VpnClient) -> CliResult<SupervisorEvent> {
+        if let Some(signal) = self.stop_signal {
+            return Ok(SupervisorEvent::Stop(signal));
+        }
+        loop {
+            let timeout = self.next_tick.saturating_duration_since(Instant::now());
+            let signal = self.signal_pipe.wait(timeout)?;
+            if signal == Some(c_int::from(WAKE_BYTE)) {
+                return Ok(SupervisorEvent::Wake);
+            }
+            if let Some(signal) = signal.filter(|s| self.stop_signals.contains(s)) {
+                return Ok(SupervisorEvent::Stop(signal));
+            }
+
+            // A child process exited or the timer elapsed, so the status might have changed
+            let status = client.get_status()?;
+            if status != self.status {
+                self.status = status.clone();
+                return Ok(match status {
+                    VpnStatus::Exited(code) => SupervisorEvent::ClientExited(code),
+                    status => SupervisorEvent::StatusChanged(status),
+                });
+            }
+
+            let now = Instant::now();
+            if now >= self.next_tick {
+                self.next_tick = now + self.interval;
+                return Ok(SupervisorEvent::Tick);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::fake::FakeClient;
+
+    #[test]
+    fn test_signal_pipe() -> CliResult<()> {
+        let mut signal_pipe = SignalPipe::new(&[libc::SIGUSR1])?;
+        assert_eq!(None, signal_pipe.wait(Duration::from_millis(10))?);
+
+        unsafe {
+            libc::raise(libc::SIGUSR1);
+        }
+        assert_eq!(Some(libc::SIGUSR1), signal_pipe.wait(Duration::from_secs(1))?);
+        assert_eq!(None, signal_pipe.wait(Duration::from_millis(10))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_supervisor() -> CliResult<()> {
+        let mut client = FakeClient::new(Vec::new(), Some("tun0"));
+        client.start()?;
+        client.wait_for_interface(Duration::from_secs(1), &mut || Ok(None))?;
+        let mut supervisor = Supervisor::with_signals(
+            &[libc::SIGUSR2],
+            &[libc::SIGWINCH],
+            Duration::from_millis(10),
+            VpnStatus::Connected(String::from("tun0")),
+        )?;
+
+        // Nothing changed, so only the timer elapses
+        let start = Instant::now();
+        assert_eq!(SupervisorEvent::Tick, supervisor.wait(&mut client)?);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        client.stop()?;
+        assert_eq!(
+            SupervisorEvent::StatusChanged(VpnStatus::Stopped),
+            supervisor.wait(&mut client)?
+        );
+        assert_eq!(SupervisorEvent::Tick, supervisor.wait(&mut client)?);
+
+        // Other signals only cause the status to be checked
+        unsafe {
+            libc::raise(libc::SIGWINCH);
+        }
+        assert_eq!(SupervisorEvent::Tick, supervisor.wait(&mut client)?);
+
+        unsafe {
+            libc::raise(libc::SIGUSR2);
+        }
+        assert_eq!(SupervisorEvent::Stop(libc::SIGUSR2), supervisor.wait(&mut client)?);
+
+        // Sleeping is interrupted by the stop signals only
+        unsafe {
+            libc::raise(libc::SIGWINCH);
+        }
+        assert_eq!(None, supervisor.sleep(Duration::from_millis(10))?);
+        unsafe {
+            libc::raise(libc::SIGUSR2);
+        }
+        assert_eq!(Some(libc::SIGUSR2), supervisor.sleep(Duration::from_secs(10))?);
+
+        // A stop signal that is received while connecting is kept for later
+        unsafe {
+            libc::raise(libc::SIGWINCH);
+        }
+        assert_eq!(None, supervisor.stop_signal()?);
+        unsafe {
+            libc::raise(libc::SIGUSR2);
+        }
+        assert_eq!(Some(libc::SIGUSR2), supervisor.stop_signal()?);
+        assert_eq!(Some(libc::SIGUSR2), supervisor.stop_signal()?);
+        assert_eq!(Some(libc::SIGUSR2), supervisor.sleep(Duration::from_secs(10))?);
+        assert_eq!(SupervisorEvent::Stop(libc::SIGUSR2), supervisor.wait(&mut client)?);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
-use thiserror;
 use std::io::Error as IoError;
 use which::Error as WhichError;
 
@@ -13,6 +12,11 @@ pub enum FirewallError {
     #[error("iptables exited with non-zero status code {0:?}")]
     IptablesError(Option<i32>),
 
+    /// The `nft` command was executed successfully, but it resulted in an error. Holds the exit
+    /// status code, if there's any.
+    #[error("nft exited with non-zero status code {0:?}")]
+    NftablesError(Option<i32>),
+
     /// An error occurred while using the which crate. Most likely happens when checking for the
     /// existence of a binary like `iptables`.
     #[error("cannot looking up binary path: {0}")]
@@ -1,4 +1,4 @@
-use super::Executor;
+use super::{Executor, run_command};
 use std::process::Command;
 use crate::error::{FirewallError, FirewallResult};
 
@@ -13,6 +13,14 @@ pub enum IptablesBaseCommand {
 pub(crate) const IPTABLES_BINARY_NAME: &str = "iptables";
 /// The binary name for `ip6tables`.
 pub(crate) const IP6TABLES_BINARY_NAME: &str = "ip6tables";
+/// The binary name for `iptables-save`.
+pub(crate) const IPTABLES_SAVE_BINARY_NAME: &str = "iptables-save";
+/// The binary name for `ip6tables-save`.
+pub(crate) const IP6TABLES_SAVE_BINARY_NAME: &str = "ip6tables-save";
+/// The binary name for `iptables-restore`.
+pub(crate) const IPTABLES_RESTORE_BINARY_NAME: &str = "iptables-restore";
+/// The binary name for `ip6tables-restore`.
+pub(crate) const IP6TABLES_RESTORE_BINARY_NAME: &str = "ip6tables-restore";
 
 impl IptablesBaseCommand {
     /// Returns the command according to the selected base command.
@@ -22,6 +30,22 @@ impl IptablesBaseCommand {
             IptablesBaseCommand::Ip6tables => IP6TABLES_BINARY_NAME,
         }.into()
     }
+
+    /// Returns the command used to save the ruleset according to the selected base command.
+    pub fn get_save_command(&self) -> String {
+        match self {
+            IptablesBaseCommand::Iptables => IPTABLES_SAVE_BINARY_NAME,
+            IptablesBaseCommand::Ip6tables => IP6TABLES_SAVE_BINARY_NAME,
+        }.into()
+    }
+
+    /// Returns the command used to restore the ruleset according to the selected base command.
+    pub fn get_restore_command(&self) -> String {
+        match self {
+            IptablesBaseCommand::Iptables => IPTABLES_RESTORE_BINARY_NAME,
+            IptablesBaseCommand::Ip6tables => IP6TABLES_RESTORE_BINARY_NAME,
+        }.into()
+    }
 }
 
 /// Responsible for executing `iptables` using std::process::Command.
@@ -49,6 +73,28 @@ impl Executor for IptablesCommandExecutor {
             Err(FirewallError::IptablesError(exit_status.code()))
         }
     }
+
+    /// Returns the output of `iptables-save`.
+    fn save(&self) -> FirewallResult<String> {
+        let (exit_status, output) = run_command(&self.0.get_save_command(), &[], None)?;
+        if exit_status.success() {
+            Ok(output)
+        } else {
+            Err(FirewallError::IptablesError(exit_status.code()))
+        }
+    }
+
+    /// Feeds the supplied ruleset into `iptables-restore`. Without `flush`, it's called with
+    /// `--noflush` so the tables are not flushed before the ruleset is applied.
+    fn restore(&self, ruleset: String, flush: bool) -> FirewallResult<()> {
+        let args: &[&str] = if flush { &[] } else { &["--noflush"] };
+        let (exit_status, _) = run_command(&self.0.get_restore_command(), args, Some(&ruleset))?;
+        if exit_status.success() {
+            Ok(())
+        } else {
+            Err(FirewallError::IptablesError(exit_status.code()))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -67,4 +113,22 @@ mod tests {
         assert_eq!(String::from("iptables"), IptablesBaseCommand::Iptables.get_command());
         assert_eq!(String::from("ip6tables"), IptablesBaseCommand::Ip6tables.get_command());
     }
+
+    #[test]
+    fn test_iptables_base_command_get_save_command() {
+        assert_eq!(String::from("iptables-save"), IptablesBaseCommand::Iptables.get_save_command());
+        assert_eq!(String::from("ip6tables-save"), IptablesBaseCommand::Ip6tables.get_save_command());
+    }
+
+    #[test]
+    fn test_iptables_base_command_get_restore_command() {
+        assert_eq!(
+            String::from("iptables-restore"),
+            IptablesBaseCommand::Iptables.get_restore_command()
+        );
+        assert_eq!(
+            String::from("ip6tables-restore"),
+            IptablesBaseCommand::Ip6tables.get_restore_command()
+        );
+    }
 }
\ No newline at end of file
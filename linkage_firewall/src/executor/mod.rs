@@ -2,13 +2,44 @@ use crate::error::FirewallResult;
 use std::fmt::Debug;
 #[cfg(test)]
 use std::fmt::{Result as FmtResult, Formatter};
+use std::io::Write;
+use std::process::{Command, ExitStatus, Stdio};
 
 pub mod iptables;
+pub mod nftables;
 
 /// An executor is responsible for executing the commands necessary to configure the firewalls.
 pub trait Executor: Debug {
     /// Executes the firewall command with the supplied arguments.
     fn execute(&self, args: Vec<String>) -> FirewallResult<()>;
+    /// Returns the complete ruleset that is currently active, in a format that is understood by
+    /// `restore`.
+    fn save(&self) -> FirewallResult<String>;
+    /// Applies the supplied ruleset in a single transaction. If `flush` is true, the currently
+    /// active ruleset is replaced, otherwise the supplied ruleset is applied on top of it.
+    fn restore(&self, ruleset: String, flush: bool) -> FirewallResult<()>;
+}
+
+/// Runs the supplied program with the arguments and returns its exit status and stdout. If `input`
+/// is supplied, it is written to the stdin of the program.
+pub(crate) fn run_command(
+    program: &str,
+    args: &[&str],
+    input: Option<&str>,
+) -> FirewallResult<(ExitStatus, String)> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .spawn()?;
+    if let Some(input) = input {
+        // Stdin is dropped at the end of this block, which closes the pipe
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+
+    Ok((output.status, String::from_utf8_lossy(&output.stdout).into_owned()))
 }
 
 #[cfg(test)]
@@ -16,6 +47,8 @@ mockall::mock!{
     pub Executor {}
     impl Executor for MockExecutor {
         fn execute(&self, args: Vec<String>) -> FirewallResult<()>;
+        fn save(&self) -> FirewallResult<String>;
+        fn restore(&self, ruleset: String, flush: bool) -> FirewallResult<()>;
     }
 }
 
@@ -32,6 +65,6 @@ impl PartialEq for MockExecutor {
     /// When their respective pointer location matches, they are equal. This needs to be done since
     /// we do not have any fields to compare.
     fn eq(&self, other: &Self) -> bool {
-        self as *const _ == other as *const _
+        std::ptr::eq(self, other)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,64 @@
+use super::{Executor, run_command};
+use std::process::Command;
+use crate::error::{FirewallError, FirewallResult};
+
+/// The binary name for `nft`.
+pub(crate) const NFT_BINARY_NAME: &str = "nft";
+
+/// Responsible for executing `nft` using std::process::Command.
+#[derive(Debug, PartialEq, Default)]
+pub struct NftablesCommandExecutor;
+
+impl NftablesCommandExecutor {
+    /// Returns a new instance of NftablesCommandExecutor.
+    pub fn new() -> NftablesCommandExecutor {
+        NftablesCommandExecutor
+    }
+}
+
+impl Executor for NftablesCommandExecutor {
+    /// Executes the `nft` command with the given arguments.
+    fn execute(&self, args: Vec<String>) -> FirewallResult<()> {
+        let exit_status = Command::new(NFT_BINARY_NAME)
+            .args(args)
+            .spawn()?
+            .wait()?;
+        if exit_status.success() {
+            Ok(())
+        } else {
+            Err(FirewallError::NftablesError(exit_status.code()))
+        }
+    }
+
+    /// Returns the output of `nft list ruleset`.
+    fn save(&self) -> FirewallResult<String> {
+        let (exit_status, output) = run_command(NFT_BINARY_NAME, &["list", "ruleset"], None)?;
+        if exit_status.success() {
+            Ok(output)
+        } else {
+            Err(FirewallError::NftablesError(exit_status.code()))
+        }
+    }
+
+    /// Loads the supplied ruleset using `nft -f -`. With `flush`, the current ruleset is flushed
+    /// in the same transaction.
+    fn restore(&self, ruleset: String, flush: bool) -> FirewallResult<()> {
+        let input = if flush { format!("flush ruleset\n{}", ruleset) } else { ruleset };
+        let (exit_status, _) = run_command(NFT_BINARY_NAME, &["-f", "-"], Some(&input))?;
+        if exit_status.success() {
+            Ok(())
+        } else {
+            Err(FirewallError::NftablesError(exit_status.code()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nftables_command_executor_new() {
+        assert_eq!(NftablesCommandExecutor, NftablesCommandExecutor::new());
+    }
+}
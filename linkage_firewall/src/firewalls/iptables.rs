@@ -1,13 +1,20 @@
 //! Implementation of the iptables firewall backend
 
-use super::{FirewallBackend, FirewallException};
+use super::{FirewallBackend, FirewallException, FirewallOptions};
 use crate::error::FirewallResult;
 use crate::executor::Executor;
-use crate::executor::iptables::{IPTABLES_BINARY_NAME, IP6TABLES_BINARY_NAME};
-use crate::{to_string_vec, executor_execute_for};
-use crate::firewalls::{FirewallExceptionProtocol, FirewallIdentifier, FirewallExecutors};
+use crate::executor::iptables::{
+    IPTABLES_BINARY_NAME, IP6TABLES_BINARY_NAME, IPTABLES_SAVE_BINARY_NAME,
+    IP6TABLES_SAVE_BINARY_NAME, IPTABLES_RESTORE_BINARY_NAME, IP6TABLES_RESTORE_BINARY_NAME,
+};
+use crate::to_string_vec;
+use crate::firewalls::{FirewallIdentifier, FirewallExecutors, FirewallSnapshot};
+use crate::policy::{get_post_connect_rules, get_pre_connect_rules};
+use crate::rules::{
+    ConnectionState, Direction, Family, FirewallRule, PortRange, Protocol, Verdict,
+};
 use crate::utils::{is_linux, does_binary_exist};
-use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
 
 /// Identifies the iptables backend uniquely.
 const IPTABLES_BACKEND_IDENTIFIER: &str = "iptables";
@@ -16,21 +23,50 @@ const IPTABLES_BACKEND_IDENTIFIER: &str = "iptables";
 pub struct IpTablesFirewall<'a, T: Executor, U: Executor> {
     executor_v4: &'a T,
     executor_v6: &'a U,
+    /// The rulesets from before `on_pre_connect` was called. Can be restored in `on_reset`.
+    snapshot: Mutex<Option<FirewallSnapshot>>,
 }
 
-/// The name for the chain that handles `ACCEPT` for the `INPUT` chain.
-const IN_ACCEPT_CHAIN_NAME: &str = "in_accept";
-/// The name for the chain that handles `ACCEPT` for the `OUTPUT` chain.
-const OUT_ACCEPT_CHAIN_NAME: &str = "out_accept";
+/// The name for the chain that is jumped to from the `INPUT` chain.
+const INPUT_CHAIN_NAME: &str = "LINKAGE_INPUT";
+/// The name for the chain that is jumped to from the `OUTPUT` chain.
+const OUTPUT_CHAIN_NAME: &str = "LINKAGE_OUTPUT";
+/// The name for the chain that is jumped to from the `FORWARD` chain.
+const FORWARD_CHAIN_NAME: &str = "LINKAGE_FORWARD";
+/// The name for the chain that holds the rules for the `LINKAGE_INPUT` chain. Traffic that is not
+/// accepted there gets dropped in `LINKAGE_INPUT`.
+const IN_RULES_CHAIN_NAME: &str = "LINKAGE_IN_RULES";
+/// The name for the chain that holds the rules for the `LINKAGE_OUTPUT` chain. Traffic that is not
+/// accepted there gets dropped in `LINKAGE_OUTPUT`.
+const OUT_RULES_CHAIN_NAME: &str = "LINKAGE_OUT_RULES";
+
+/// All chains that are owned by Linkage, in the order they are created.
+const LINKAGE_CHAIN_NAMES: [&str; 5] = [
+    INPUT_CHAIN_NAME, OUTPUT_CHAIN_NAME, FORWARD_CHAIN_NAME, IN_RULES_CHAIN_NAME,
+    OUT_RULES_CHAIN_NAME,
+];
+/// The built-in chains and the chains owned by Linkage they jump to.
+const HOOKED_CHAINS: [(&str, &str); 3] = [
+    ("INPUT", INPUT_CHAIN_NAME),
+    ("OUTPUT", OUTPUT_CHAIN_NAME),
+    ("FORWARD", FORWARD_CHAIN_NAME),
+];
 
 impl<'a, T: Executor, U: Executor> IpTablesFirewall<'a, T, U> {
     /// Returns a new instance of IpTablesInstance with the supplied executors.
     pub fn new(executor_v4: &'a T, executor_v6: &'a U) -> IpTablesFirewall<'a, T, U> {
-        return IpTablesFirewall {
+        IpTablesFirewall {
             executor_v4,
             executor_v6,
+            snapshot: Mutex::new(None),
         }
     }
+
+    /// Returns the guard for the saved snapshot. A poisoned lock is recovered since the snapshot
+    /// itself cannot be left in an inconsistent state.
+    fn lock_snapshot(&self) -> MutexGuard<'_, Option<FirewallSnapshot>> {
+        self.snapshot.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
 
 impl<'a, T: Executor, U: Executor> FirewallExecutors<T, U> for IpTablesFirewall<'a, T, U> {
@@ -45,167 +81,356 @@ impl<'a, T: Executor, U: Executor> FirewallExecutors<T, U> for IpTablesFirewall<
 
 impl<'a, T: Executor, U: Executor> FirewallBackend for IpTablesFirewall<'a, T, U> {
     fn get_identifier(&self) -> FirewallIdentifier {
-        return FirewallIdentifier {
+        FirewallIdentifier {
             identifier: IPTABLES_BACKEND_IDENTIFIER,
         }
     }
 
-    /// The IpTablesFirewall backend is available if the operating system is Linux and an executable
-    /// with the name `iptables` is found.
+    /// The IpTablesFirewall backend is available if the operating system is Linux and the
+    /// executables `iptables` and `ip6tables` are found, including their `-save` and `-restore`
+    /// variants.
     fn is_available(&self) -> FirewallResult<bool> {
         // iptables is only available if we use linux
         if is_linux() {
-            // If the two binaries are available, the backend can be used
-            let iptables_exist = does_binary_exist(IPTABLES_BINARY_NAME)?;
-            let ip6tables_exist = does_binary_exist(IP6TABLES_BINARY_NAME)?;
+            // If all binaries are available, the backend can be used
+            for binary in [
+                IPTABLES_BINARY_NAME, IP6TABLES_BINARY_NAME,
+                IPTABLES_SAVE_BINARY_NAME, IP6TABLES_SAVE_BINARY_NAME,
+                IPTABLES_RESTORE_BINARY_NAME, IP6TABLES_RESTORE_BINARY_NAME,
+            ].iter() {
+                if !does_binary_exist(binary)? {
+                    return Ok(false);
+                }
+            }
 
-            Ok(iptables_exist && ip6tables_exist)
+            Ok(true)
         } else {
             Ok(false)
         }
     }
 
-    /// Applies the following rules:
-    /// - Sets the default policy to `DROP` for the chains `INPUT`, `OUTPUT` and `FORWARD`
-    /// - For both the `INPUT` and `OUTPUT` chain, it will:
-    ///     - Accept related/established traffic
-    ///     - Drop invalid packets
-    ///     - Allow traffic on the loopback device
-    /// - Create a chain that will be used for new and untracked connections in the `INPUT` chain
-    /// - Create a chain that will be used for new and untracked connections in the `OUTPUT` chain
-    /// - Add exceptions for the supplied FirewallExceptions. They can be used for e.g. whitelisting
-    /// VPN servers
-    fn on_pre_connect(&self, exceptions: &[FirewallException]) -> FirewallResult<()> {
+    /// Deletes the jumps to the chains owned by Linkage that are left from a previous session, e.g.
+    /// because tearing down failed, and saves the current rulesets of both `iptables` and
+    /// `ip6tables` so they can be restored by `on_reset`. Afterwards, the rules of the policy are applied in a single `iptables-restore`
+    /// transaction per family, so either all or none of them are active. All of them live in
+    /// chains that are owned by Linkage, the built-in chains only get a jump to them:
+    /// - Create the chains `LINKAGE_INPUT`, `LINKAGE_OUTPUT` and `LINKAGE_FORWARD`, which pass the
+    ///   traffic to `LINKAGE_IN_RULES`/`LINKAGE_OUT_RULES` and drop everything that was not
+    ///   accepted there
+    /// - Add the rules of the policy to `LINKAGE_IN_RULES` and `LINKAGE_OUT_RULES`
+    /// - Insert jumps to the chains at position 1 of `INPUT`, `OUTPUT` and `FORWARD`
+    fn on_pre_connect(
+        &self,
+        exceptions: &[FirewallException],
+        options: &FirewallOptions,
+    ) -> FirewallResult<()> {
         let executor_v4 = self.get_executor_v4();
         let executor_v6 = self.get_executor_v6();
 
-        // Default policies
-        executor_execute_for!(to_string_vec!("-P", "INPUT", "DROP"), executor_v4, executor_v6);
-        executor_execute_for!(to_string_vec!("-P", "OUTPUT", "DROP"), executor_v4, executor_v6);
-        executor_execute_for!(to_string_vec!("-P", "FORWARD", "DROP"), executor_v4, executor_v6);
-
-        for chain in ["INPUT", "OUTPUT"].iter() {
-            // Related/established traffic should be allowed
-            executor_execute_for!(to_string_vec!(
-                "-A", *chain, "-m", "state", "--state", "RELATED,ESTABLISHED", "-j", "ACCEPT"
-            ), executor_v4, executor_v6);
-
-            // Drop invalid packets
-            executor_execute_for!(to_string_vec!(
-                "-A", *chain, "-m", "state", "--state", "INVALID", "-j", "DROP"
-            ), executor_v4, executor_v6);
-        }
+        // Otherwise the jumps inserted below would be added a second time
+        delete_stale_jumps(executor_v4);
+        delete_stale_jumps(executor_v6);
 
-        // Allow traffic on loopback device
-        executor_execute_for!(
-            to_string_vec!("-A", "INPUT", "-i", "lo", "-j", "ACCEPT"),
-            executor_v4, executor_v6
+        // Save the current rulesets before changing anything. They are only kept once both
+        // families are set up, so a failed attempt can't leave a snapshot behind.
+        let snapshot = FirewallSnapshot {
+            v4: executor_v4.save()?,
+            v6: executor_v6.save()?,
+        };
+
+        let rules = get_pre_connect_rules(exceptions, options);
+        let ruleset_v4 = render_ruleset(
+            &LINKAGE_CHAIN_NAMES, &get_pre_connect_args(&rules, Family::V4),
         );
-        executor_execute_for!(
-            to_string_vec!("-A", "OUTPUT", "-o", "lo", "-j", "ACCEPT"),
-            executor_v4, executor_v6
+        let ruleset_v6 = render_ruleset(
+            &LINKAGE_CHAIN_NAMES, &get_pre_connect_args(&rules, Family::V6),
         );
 
-        // Create new chain for incoming allow and hook it up
-        executor_execute_for!(to_string_vec!("-N", IN_ACCEPT_CHAIN_NAME), executor_v4, executor_v6);
-        executor_execute_for!(
-            to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "NEW,UNTRACKED", "-j", IN_ACCEPT_CHAIN_NAME
-            ),
-            executor_v4, executor_v6
-        );
+        executor_v4.restore(ruleset_v4, false)?;
+        if let Err(e) = executor_v6.restore(ruleset_v6, false) {
+            // The IPv4 rules are already active, so they have to be removed again to leave the
+            // firewall as it was before. If that fails too, there's nothing more we can do.
+            executor_v4.restore(render_ruleset(&[], &get_teardown_args()), false).ok();
+            return Err(e);
+        }
+        *self.lock_snapshot() = Some(snapshot);
 
-        // Create new chain for outgoing allow and hook it up
-        executor_execute_for!(to_string_vec!("-N", OUT_ACCEPT_CHAIN_NAME), executor_v4, executor_v6);
-        executor_execute_for!(
-            to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "NEW,UNTRACKED", "-j", OUT_ACCEPT_CHAIN_NAME
-            ),
-            executor_v4, executor_v6
-        );
+        Ok(())
+    }
 
-        // Add exceptions
-        for e in exceptions.iter() {
-            let protocol = match e.protocol {
-                FirewallExceptionProtocol::TCP => "tcp",
-                FirewallExceptionProtocol::UDP => "udp",
-            };
-            match e.host {
-                IpAddr::V4(ip) => {
-                    executor_v4.execute(to_string_vec!(
-                        "-A", OUT_ACCEPT_CHAIN_NAME, "-d", format!("{}/32", ip.to_string()),
-                        "-p", protocol, "-m", protocol, "--dport", format!("{}", e.port), "-j",
-                        "ACCEPT"
-                    ))?;
-                },
-                IpAddr::V6(ip) => {
-                    executor_v6.execute(to_string_vec!(
-                        "-A", OUT_ACCEPT_CHAIN_NAME, "-d", format!("{}/128", ip.to_string()),
-                        "-p", protocol, "-m", protocol, "--dport", format!("{}", e.port), "-j",
-                        "ACCEPT"
-                    ))?;
-                },
+    /// Appends the rules of the policy to `LINKAGE_IN_RULES` and `LINKAGE_OUT_RULES`.
+    fn on_post_connect(&self, interface_identifier: &str) -> FirewallResult<()> {
+        let executor_v4 = self.get_executor_v4();
+        let executor_v6 = self.get_executor_v6();
+
+        for rule in get_post_connect_rules(interface_identifier).iter() {
+            if rule.applies_to(Family::V4) {
+                executor_v4.execute(append_rule_args(rule))?;
+            }
+            if rule.applies_to(Family::V6) {
+                executor_v6.execute(append_rule_args(rule))?;
             }
         }
 
         Ok(())
     }
 
-    /// Applies the following rules:
-    /// - Allows outgoing connections from the supplied interface identifier
-    fn on_post_connect(&self, interface_identifier: &str) -> FirewallResult<()> {
+    /// Deletes the rules that were appended in `on_post_connect` for the supplied interface
+    /// identifier.
+    fn on_interface_down(&self, interface_identifier: &str) -> FirewallResult<()> {
         let executor_v4 = self.get_executor_v4();
         let executor_v6 = self.get_executor_v6();
 
-        // TODO: Do we need to accept incoming connections on the supplied interface identifier?
-        executor_execute_for!(
-            to_string_vec!("-A", OUT_ACCEPT_CHAIN_NAME, "-o", interface_identifier, "-j", "ACCEPT"),
-            executor_v4, executor_v6
-        );
+        for rule in get_post_connect_rules(interface_identifier).iter() {
+            if rule.applies_to(Family::V4) {
+                executor_v4.execute(delete_rule_args(rule))?;
+            }
+            if rule.applies_to(Family::V6) {
+                executor_v6.execute(delete_rule_args(rule))?;
+            }
+        }
 
         Ok(())
     }
 
-    /// Applies the following rules:
-    /// - Sets the default policy of the `INPUT`, `OUTPUT` and `FORWARD` chains to `ACCEPT`
-    /// - Flushes all chains
-    /// - Deletes the chains that are responsible for `ACCEPT` in the `INPUT` and `OUTPUT` chain
+    /// Removes everything that was created in `on_pre_connect` and `on_post_connect` in a single
+    /// `iptables-restore` transaction per family, rules from other applications are not touched:
+    /// - Deletes the jumps from `INPUT`, `OUTPUT` and `FORWARD`
+    /// - Flushes and deletes all chains owned by Linkage
+    ///
+    /// The snapshot from `on_pre_connect` is intentionally not restored here. Since all rules of
+    /// Linkage live in its own chains, removing them is enough to get back to the state from
+    /// before connecting, and rules that other applications, e.g. docker or fail2ban, added while
+    /// connected are kept instead of being reverted. The snapshot is only discarded.
     fn on_disconnect(&self) -> FirewallResult<()> {
         let executor_v4 = self.get_executor_v4();
         let executor_v6 = self.get_executor_v6();
 
-        // TODO: Reload firewall state from before creation
-        // Default policies
-        executor_execute_for!(to_string_vec!("-P", "INPUT", "ACCEPT"), executor_v4, executor_v6);
-        executor_execute_for!(to_string_vec!("-P", "OUTPUT", "ACCEPT"), executor_v4, executor_v6);
-        executor_execute_for!(to_string_vec!("-P", "FORWARD", "ACCEPT"), executor_v4, executor_v6);
+        let ruleset = render_ruleset(&[], &get_teardown_args());
+        executor_v4.restore(ruleset.clone(), false)?;
+        executor_v6.restore(ruleset, false)?;
+
+        // The rules are gone, so the snapshot isn't needed anymore
+        *self.lock_snapshot() = None;
 
-        // Flush rules
-        executor_execute_for!(to_string_vec!("-F"), executor_v4, executor_v6);
+        Ok(())
+    }
+
+    /// Returns the rulesets that were saved in `on_pre_connect` until `on_disconnect` or
+    /// `on_reset` is called. They are only used by `on_reset` to recover from a session that
+    /// didn't exit cleanly, e.g. because of a crash, a normal exit doesn't restore them.
+    fn get_snapshot(&self) -> Option<FirewallSnapshot> {
+        self.lock_snapshot().clone()
+    }
+
+    /// Restores the rulesets of the supplied snapshot for both `iptables` and `ip6tables`, which
+    /// reverts the firewall to the state from before `on_pre_connect`. Without a snapshot, it
+    /// does the same as `on_disconnect`, but ignores errors since we cannot know which of the
+    /// rules and chains still exist.
+    fn on_reset(&self, snapshot: Option<&FirewallSnapshot>) -> FirewallResult<()> {
+        let executor_v4 = self.get_executor_v4();
+        let executor_v6 = self.get_executor_v6();
 
-        // Delete the created chains
-        executor_execute_for!(to_string_vec!("-X", IN_ACCEPT_CHAIN_NAME), executor_v4, executor_v6);
-        executor_execute_for!(to_string_vec!("-X", OUT_ACCEPT_CHAIN_NAME), executor_v4, executor_v6);
+        match snapshot {
+            Some(snapshot) => {
+                executor_v4.restore(snapshot.v4.clone(), true)?;
+                executor_v6.restore(snapshot.v6.clone(), true)?;
+            },
+            None => {
+                for args in get_teardown_args() {
+                    // Errors only mean that the rule or chain is already gone
+                    executor_v4.execute(args.clone()).ok();
+                    executor_v6.execute(args).ok();
+                }
+            },
+        }
+        *self.lock_snapshot() = None;
 
         Ok(())
     }
 }
 
+/// Returns the name of the chain that holds the rules for the supplied direction.
+fn get_rules_chain_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Input => IN_RULES_CHAIN_NAME,
+        Direction::Output => OUT_RULES_CHAIN_NAME,
+    }
+}
+
+/// Formats the port range the way `iptables` expects it, e.g. `1337` or `1000:2000`.
+fn format_ports(ports: &PortRange) -> String {
+    if ports.is_single() {
+        ports.start().to_string()
+    } else {
+        format!("{}:{}", ports.start(), ports.end())
+    }
+}
+
+/// Returns the arguments that append the supplied rule to the chain of its direction.
+fn append_rule_args(rule: &FirewallRule) -> Vec<String> {
+    let mut args = to_string_vec!("-A", get_rules_chain_name(rule.direction));
+
+    if let Some(interface) = &rule.interface {
+        let interface_flag = match rule.direction {
+            Direction::Input => "-i",
+            Direction::Output => "-o",
+        };
+        args.extend(to_string_vec!(interface_flag, interface));
+    }
+    if let Some(interface) = &rule.excluded_interface {
+        let interface_flag = match rule.direction {
+            Direction::Input => "-i",
+            Direction::Output => "-o",
+        };
+        args.extend(to_string_vec!("!", interface_flag, interface));
+    }
+    if let Some(source) = &rule.source {
+        args.extend(to_string_vec!("-s", source.to_string()));
+    }
+    if let Some(destination) = &rule.destination {
+        args.extend(to_string_vec!("-d", destination.to_string()));
+    }
+    if let Some(protocol) = &rule.protocol {
+        let protocol = match protocol {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Icmp => "icmp",
+            Protocol::IcmpV6 => "icmpv6",
+        };
+        args.extend(to_string_vec!("-p", protocol));
+        if rule.source_ports.is_some() || rule.destination_ports.is_some() {
+            args.extend(to_string_vec!("-m", protocol));
+        }
+        if let Some(ports) = &rule.source_ports {
+            args.extend(to_string_vec!("--sport", format_ports(ports)));
+        }
+        if let Some(ports) = &rule.destination_ports {
+            args.extend(to_string_vec!("--dport", format_ports(ports)));
+        }
+        if let Some(icmp_type) = rule.icmp_type {
+            let (module, option) = match protocol {
+                "icmpv6" => ("icmp6", "--icmpv6-type"),
+                _ => ("icmp", "--icmp-type"),
+            };
+            args.extend(to_string_vec!("-m", module, option, icmp_type.to_string()));
+        }
+    }
+    if let Some(hop_limit) = rule.hop_limit {
+        args.extend(to_string_vec!("-m", "hl", "--hl-eq", hop_limit.to_string()));
+    }
+    if !rule.states.is_empty() {
+        let states: Vec<&str> = rule.states.iter().map(|s| match s {
+            ConnectionState::New => "NEW",
+            ConnectionState::Established => "ESTABLISHED",
+            ConnectionState::Related => "RELATED",
+            ConnectionState::Invalid => "INVALID",
+            ConnectionState::Untracked => "UNTRACKED",
+        }).collect();
+        args.extend(to_string_vec!("-m", "state", "--state", states.join(",")));
+    }
+    let target = match rule.verdict {
+        Verdict::Accept => "ACCEPT",
+        Verdict::Drop => "DROP",
+    };
+    args.extend(to_string_vec!("-j", target));
+
+    args
+}
+
+/// Returns the arguments that delete the supplied rule from the chain of its direction. The rule
+/// has to match the appended one exactly.
+fn delete_rule_args(rule: &FirewallRule) -> Vec<String> {
+    let mut args = append_rule_args(rule);
+    args[0] = String::from("-D");
+    args
+}
+
+/// Returns the arguments of all commands that are applied in `on_pre_connect` for the supplied
+/// family, in the order they have to be applied. Rules of the other family are skipped.
+fn get_pre_connect_args(rules: &[FirewallRule], family: Family) -> Vec<Vec<String>> {
+    let mut args: Vec<Vec<String>> = rules.iter()
+        .filter(|r| r.applies_to(family))
+        .map(append_rule_args)
+        .collect();
+
+    // Pass the traffic to the rules and drop everything that was not accepted there
+    for (chain, rules_chain) in [
+        (INPUT_CHAIN_NAME, IN_RULES_CHAIN_NAME),
+        (OUTPUT_CHAIN_NAME, OUT_RULES_CHAIN_NAME),
+    ].iter() {
+        args.push(to_string_vec!("-A", *chain, "-j", *rules_chain));
+        args.push(to_string_vec!("-A", *chain, "-j", "DROP"));
+    }
+    args.push(to_string_vec!("-A", FORWARD_CHAIN_NAME, "-j", "DROP"));
+
+    // Activate the chains as the last step
+    for (built_in_chain, chain) in HOOKED_CHAINS.iter() {
+        args.push(to_string_vec!("-I", *built_in_chain, "1", "-j", *chain));
+    }
+
+    args
+}
+
+/// Deletes every jump from `INPUT`, `OUTPUT` and `FORWARD` to the chains owned by Linkage. A jump
+/// can exist more than once, so each one is deleted until `iptables` reports that there is none
+/// left.
+fn delete_stale_jumps<E: Executor>(executor: &E) {
+    for (built_in_chain, chain) in HOOKED_CHAINS.iter() {
+        while executor.execute(to_string_vec!("-D", *built_in_chain, "-j", *chain)).is_ok() {}
+    }
+}
+
+/// Renders the supplied chains and rules for the `filter` table in the format that is understood
+/// by `iptables-restore`. The chains are declared before the rules.
+fn render_ruleset(chains: &[&str], rules: &[Vec<String>]) -> String {
+    let mut ruleset = String::from("*filter\n");
+    for chain in chains.iter() {
+        ruleset.push_str(&format!(":{} - [0:0]\n", chain));
+    }
+    for rule in rules.iter() {
+        ruleset.push_str(&rule.join(" "));
+        ruleset.push('\n');
+    }
+    ruleset.push_str("COMMIT\n");
+    ruleset
+}
+
+/// Returns the arguments of the commands that remove everything owned by Linkage, in the order
+/// they have to be executed.
+fn get_teardown_args() -> Vec<Vec<String>> {
+    let mut args = Vec::new();
+
+    // Deactivate the chains first
+    for (built_in_chain, chain) in HOOKED_CHAINS.iter() {
+        args.push(to_string_vec!("-D", *built_in_chain, "-j", *chain));
+    }
+    // Chains can only be deleted when no other chain references them, so all of them have to be
+    // flushed first
+    for chain in LINKAGE_CHAIN_NAMES.iter() {
+        args.push(to_string_vec!("-F", *chain));
+    }
+    for chain in LINKAGE_CHAIN_NAMES.iter() {
+        args.push(to_string_vec!("-X", *chain));
+    }
+
+    args
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::executor::MockExecutor;
     use mockall::predicate::*;
-    use crate::expect_execute;
+    use crate::{expect_execute, expect_restore, expect_save};
+    use crate::error::FirewallError;
+    use crate::firewalls::FirewallExceptionProtocol;
 
     #[test]
     fn test_get_identifier() {
         let executor_v4_mock = MockExecutor::new();
         let executor_v6_mock = MockExecutor::new();
-        let f = IpTablesFirewall {
-            executor_v4: &executor_v4_mock,
-            executor_v6: &executor_v6_mock,
-        };
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
 
         assert_eq!(FirewallIdentifier {
             identifier: "iptables"
@@ -216,12 +441,18 @@ mod tests {
     fn test_is_available() -> FirewallResult<()> {
         let executor_v4_mock = MockExecutor::new();
         let executor_v6_mock = MockExecutor::new();
-            let f = IpTablesFirewall {
-            executor_v4: &executor_v4_mock,
-            executor_v6: &executor_v6_mock,
-        };
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
 
-        assert!(f.is_available()?);
+        // Depends on the host, so the result is compared with the binaries that are installed
+        let mut binaries_exist = true;
+        for binary in [
+            IPTABLES_BINARY_NAME, IP6TABLES_BINARY_NAME,
+            IPTABLES_SAVE_BINARY_NAME, IP6TABLES_SAVE_BINARY_NAME,
+            IPTABLES_RESTORE_BINARY_NAME, IP6TABLES_RESTORE_BINARY_NAME,
+        ] {
+            binaries_exist &= does_binary_exist(binary)?;
+        }
+        assert_eq!(is_linux() && binaries_exist, f.is_available()?);
 
         Ok(())
     }
@@ -250,158 +481,352 @@ mod tests {
         assert_eq!(executor_v6_mock, executor_v6_mock);
         assert_ne!(executor_v4_mock, executor_v6_mock);
 
-        let f = IpTablesFirewall {
-            executor_v4: &executor_v4_mock,
-            executor_v6: &executor_v6_mock,
-        };
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
 
         assert_eq!(f.get_executor_v4(), &executor_v4_mock);
         assert_eq!(f.get_executor_v6(), &executor_v6_mock);
     }
 
-    #[test]
-    fn test_on_pre_connect() {
-        let mut executor_v4_mock = MockExecutor::new();
-        let mut executor_v6_mock = MockExecutor::new();
-
-        // Default policies
-        expect_execute!(executor_v4_mock, to_string_vec!("-P", "INPUT", "DROP"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-P", "INPUT", "DROP"));
-        expect_execute!(executor_v4_mock, to_string_vec!("-P", "OUTPUT", "DROP"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-P", "OUTPUT", "DROP"));
-        expect_execute!(executor_v4_mock, to_string_vec!("-P", "FORWARD", "DROP"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-P", "FORWARD", "DROP"));
-
-        // Related/established traffic should be allowed for INPUT
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "RELATED,ESTABLISHED", "-j", "ACCEPT"
-            )
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "RELATED,ESTABLISHED", "-j", "ACCEPT"
-            )
-        );
-
-        // Drop invalid packets for INPUT
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "INVALID", "-j", "DROP"
-            )
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "INVALID", "-j", "DROP"
-            )
-        );
+    /// Expects the supplied arguments on both mocks.
+    macro_rules! expect_execute_both {
+        ( $m4:ident, $m6:ident, $e:expr ) => {{
+            expect_execute!($m4, $e);
+            expect_execute!($m6, $e);
+        }};
+    }
 
-        // Related/established traffic should be allowed for OUTPUT
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "RELATED,ESTABLISHED", "-j", "ACCEPT"
-            )
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "RELATED,ESTABLISHED", "-j", "ACCEPT"
-            )
-        );
+    /// Expects that no jump to the chains owned by Linkage is left from a previous session on the
+    /// supplied mock.
+    macro_rules! expect_no_stale_jumps {
+        ( $m:ident ) => {{
+            for (built_in_chain, chain) in HOOKED_CHAINS.iter() {
+                expect_execute!(
+                    $m, to_string_vec!("-D", *built_in_chain, "-j", *chain),
+                    Err(FirewallError::IptablesError(Some(1)))
+                );
+            }
+        }};
+    }
 
-        // Drop invalid packets for OUTPUT
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "INVALID", "-j", "DROP"
-            )
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "INVALID", "-j", "DROP"
-            )
-        );
+    /// The ruleset that removes everything owned by Linkage.
+    const TEARDOWN_RULESET: &str = "*filter
+-D INPUT -j LINKAGE_INPUT
+-D OUTPUT -j LINKAGE_OUTPUT
+-D FORWARD -j LINKAGE_FORWARD
+-F LINKAGE_INPUT
+-F LINKAGE_OUTPUT
+-F LINKAGE_FORWARD
+-F LINKAGE_IN_RULES
+-F LINKAGE_OUT_RULES
+-X LINKAGE_INPUT
+-X LINKAGE_OUTPUT
+-X LINKAGE_FORWARD
+-X LINKAGE_IN_RULES
+-X LINKAGE_OUT_RULES
+COMMIT
+";
+
+    /// The rules for DHCPv4 that are applied in `on_pre_connect`. They never apply to the tunnel.
+    const LINK_RULES_V4: &str = "\
+-A LINKAGE_OUT_RULES ! -o tun+ -d 255.255.255.255/32 -p udp -m udp --sport 68 --dport 67 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d 10.0.0.0/8 -p udp -m udp --sport 68 --dport 67 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d 172.16.0.0/12 -p udp -m udp --sport 68 --dport 67 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d 192.168.0.0/16 -p udp -m udp --sport 68 --dport 67 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d 169.254.0.0/16 -p udp -m udp --sport 68 --dport 67 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -p udp -m udp --sport 67 --dport 68 -j ACCEPT
+";
+
+    /// The rules for DHCPv6 and the neighbor discovery protocol that are applied in
+    /// `on_pre_connect`. Neighbor discovery only accepts messages that weren't forwarded.
+    const LINK_RULES_V6: &str = "\
+-A LINKAGE_OUT_RULES ! -o tun+ -s fe80::/10 -d ff02::1:2/128 \
+    -p udp -m udp --sport 546 --dport 547 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -s fe80::/10 -d fe80::/10 \
+    -p udp -m udp --sport 546 --dport 547 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -s fe80::/10 -d fe80::/10 \
+    -p udp -m udp --sport 547 --dport 546 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d fe80::/10 -p icmpv6 -m icmp6 --icmpv6-type 133 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d ff02::/16 -p icmpv6 -m icmp6 --icmpv6-type 133 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -d fe80::/10 -p icmpv6 -m icmp6 --icmpv6-type 134 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -d ff02::/16 -p icmpv6 -m icmp6 --icmpv6-type 134 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d fe80::/10 -p icmpv6 -m icmp6 --icmpv6-type 135 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d ff02::/16 -p icmpv6 -m icmp6 --icmpv6-type 135 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -d fe80::/10 -p icmpv6 -m icmp6 --icmpv6-type 135 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -d ff02::/16 -p icmpv6 -m icmp6 --icmpv6-type 135 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d fe80::/10 -p icmpv6 -m icmp6 --icmpv6-type 136 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_OUT_RULES ! -o tun+ -d ff02::/16 -p icmpv6 -m icmp6 --icmpv6-type 136 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -d fe80::/10 -p icmpv6 -m icmp6 --icmpv6-type 136 \
+    -m hl --hl-eq 255 -j ACCEPT
+-A LINKAGE_IN_RULES ! -i tun+ -d ff02::/16 -p icmpv6 -m icmp6 --icmpv6-type 136 \
+    -m hl --hl-eq 255 -j ACCEPT
+";
+
+    /// Returns the ruleset that is applied in `on_pre_connect` for the supplied family with the
+    /// supplied exceptions.
+    fn pre_connect_ruleset(family: Family, exceptions: &str) -> String {
+        let link_rules = match family {
+            Family::V4 => LINK_RULES_V4,
+            Family::V6 => LINK_RULES_V6,
+        };
+        format!("*filter
+:LINKAGE_INPUT - [0:0]
+:LINKAGE_OUTPUT - [0:0]
+:LINKAGE_FORWARD - [0:0]
+:LINKAGE_IN_RULES - [0:0]
+:LINKAGE_OUT_RULES - [0:0]
+-A LINKAGE_IN_RULES -m state --state RELATED,ESTABLISHED -j ACCEPT
+-A LINKAGE_IN_RULES -m state --state INVALID -j DROP
+-A LINKAGE_IN_RULES -i lo -j ACCEPT
+-A LINKAGE_OUT_RULES -m state --state RELATED,ESTABLISHED -j ACCEPT
+-A LINKAGE_OUT_RULES -m state --state INVALID -j DROP
+-A LINKAGE_OUT_RULES -o lo -j ACCEPT
+{}{}-A LINKAGE_INPUT -j LINKAGE_IN_RULES
+-A LINKAGE_INPUT -j DROP
+-A LINKAGE_OUTPUT -j LINKAGE_OUT_RULES
+-A LINKAGE_OUTPUT -j DROP
+-A LINKAGE_FORWARD -j DROP
+-I INPUT 1 -j LINKAGE_INPUT
+-I OUTPUT 1 -j LINKAGE_OUTPUT
+-I FORWARD 1 -j LINKAGE_FORWARD
+COMMIT
+", link_rules, exceptions)
+    }
 
-        // Allow traffic on loopback device for INPUT
-        expect_execute!(
-            executor_v4_mock, to_string_vec!("-A", "INPUT", "-i", "lo", "-j", "ACCEPT")
+    #[test]
+    fn test_append_rule_args() {
+        assert_eq!(
+            to_string_vec!("-A", "LINKAGE_IN_RULES", "-j", "DROP"),
+            append_rule_args(&FirewallRule::new(Direction::Input, Verdict::Drop))
         );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!("-A", "INPUT", "-i", "lo", "-j", "ACCEPT")
+        assert_eq!(
+            to_string_vec!(
+                "-A", "LINKAGE_IN_RULES", "-i", "eth0", "-s", "192.168.0.0/16", "-p", "udp",
+                "-m", "udp", "--sport", "67", "--dport", "68", "-j", "ACCEPT"
+            ),
+            append_rule_args(&FirewallRule::new(Direction::Input, Verdict::Accept)
+                .interface("eth0")
+                .source("192.168.0.0/16".parse().unwrap())
+                .protocol(Protocol::Udp)
+                .source_ports(PortRange::single(67))
+                .destination_ports(PortRange::single(68)))
         );
-
-        // Allow traffic on loopback device for OUTPUT
-        expect_execute!(
-            executor_v4_mock, to_string_vec!("-A", "OUTPUT", "-o", "lo", "-j", "ACCEPT")
+        assert_eq!(
+            to_string_vec!(
+                "-A", "LINKAGE_OUT_RULES", "-d", "fe80::/10", "-p", "tcp", "-m", "tcp", "--dport",
+                "1000:2000", "-m", "state", "--state", "NEW,UNTRACKED", "-j", "ACCEPT"
+            ),
+            append_rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination("fe80::/10".parse().unwrap())
+                .protocol(Protocol::Tcp)
+                .destination_ports(PortRange::new(1000, 2000))
+                .states(&[ConnectionState::New, ConnectionState::Untracked]))
         );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!("-A", "OUTPUT", "-o", "lo", "-j", "ACCEPT")
+        assert_eq!(
+            to_string_vec!(
+                "-A", "LINKAGE_IN_RULES", "-p", "icmpv6", "-m", "icmp6", "--icmpv6-type", "134",
+                "-j", "ACCEPT"
+            ),
+            append_rule_args(&FirewallRule::new(Direction::Input, Verdict::Accept)
+                .protocol(Protocol::IcmpV6)
+                .icmp_type(134))
         );
-
-        // New chain for incoming allow and hook of it into INPUT chain
-        expect_execute!(
-            executor_v4_mock, to_string_vec!("-N", "in_accept")
+        assert_eq!(
+            to_string_vec!(
+                "-A", "LINKAGE_OUT_RULES", "-p", "icmp", "-m", "icmp", "--icmp-type", "8", "-j",
+                "ACCEPT"
+            ),
+            append_rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .protocol(Protocol::Icmp)
+                .icmp_type(8))
         );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!("-N", "in_accept")
+        assert_eq!(
+            to_string_vec!("-A", "LINKAGE_OUT_RULES", "-p", "icmpv6", "-j", "ACCEPT"),
+            append_rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .protocol(Protocol::IcmpV6))
         );
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "NEW,UNTRACKED", "-j", "in_accept"
-            )
+        assert_eq!(
+            to_string_vec!("-A", "LINKAGE_OUT_RULES", "-p", "tcp", "-j", "ACCEPT"),
+            append_rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .protocol(Protocol::Tcp))
         );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "INPUT", "-m", "state", "--state", "NEW,UNTRACKED", "-j", "in_accept"
-            )
+        assert_eq!(
+            to_string_vec!(
+                "-A", "LINKAGE_IN_RULES", "!", "-i", "tun+", "-d", "ff02::/16", "-p", "icmpv6",
+                "-m", "icmp6", "--icmpv6-type", "135", "-m", "hl", "--hl-eq", "255", "-j", "ACCEPT"
+            ),
+            append_rule_args(&FirewallRule::new(Direction::Input, Verdict::Accept)
+                .excluded_interface("tun+")
+                .destination("ff02::/16".parse().unwrap())
+                .protocol(Protocol::IcmpV6)
+                .icmp_type(135)
+                .hop_limit(255))
         );
+    }
 
+    #[test]
+    fn test_get_pre_connect_args_family() {
+        let rules = vec![
+            FirewallRule::new(Direction::Output, Verdict::Accept).family(Family::V6),
+            FirewallRule::new(Direction::Output, Verdict::Drop),
+        ];
+
+        // Rules of the other family are skipped
+        let args = get_pre_connect_args(&rules, Family::V4);
+        assert_eq!(to_string_vec!("-A", "LINKAGE_OUT_RULES", "-j", "DROP"), args[0]);
+        let args = get_pre_connect_args(&rules, Family::V6);
+        assert_eq!(to_string_vec!("-A", "LINKAGE_OUT_RULES", "-j", "ACCEPT"), args[0]);
+        assert_eq!(to_string_vec!("-A", "LINKAGE_OUT_RULES", "-j", "DROP"), args[1]);
+    }
 
-        // New chain for outgoing allow and hook of it into OUTPUT chain
-        expect_execute!(
-            executor_v4_mock, to_string_vec!("-N", "out_accept")
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!("-N", "out_accept")
-        );
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "NEW,UNTRACKED", "-j", "out_accept"
-            )
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "OUTPUT", "-m", "state", "--state", "NEW,UNTRACKED", "-j", "out_accept"
-            )
+    #[test]
+    fn test_render_ruleset() {
+        assert_eq!("*filter\nCOMMIT\n", render_ruleset(&[], &[]));
+        assert_eq!(
+            "*filter\n:a - [0:0]\n:b - [0:0]\n-A a -j DROP\n-I INPUT 1 -j b\nCOMMIT\n",
+            render_ruleset(&["a", "b"], &[
+                to_string_vec!("-A", "a", "-j", "DROP"),
+                to_string_vec!("-I", "INPUT", "1", "-j", "b"),
+            ])
         );
+    }
 
-        // Firewall exceptions should get added
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "out_accept", "-d", "1.1.1.1/32", "-p", "tcp", "-m", "tcp", "--dport", "1337",
-                "-j", "ACCEPT"
-            )
-        );
-        expect_execute!(
-            executor_v4_mock, to_string_vec!(
-                "-A", "out_accept", "-d", "127.0.0.1/32", "-p", "udp", "-m", "udp", "--dport",
-                "4200", "-j", "ACCEPT"
-            )
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!(
-                "-A", "out_accept", "-d", "2001:db8:85a3::8a2e:370:7334/128", "-p",
-                "udp", "-m", "udp", "--dport", "2020", "-j", "ACCEPT"
-            )
-        );
+    #[test]
+    fn test_on_pre_connect() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
 
-        let f = IpTablesFirewall {
-            executor_v4: &executor_v4_mock,
-            executor_v6: &executor_v6_mock,
-        };
+        // There are no jumps left from a previous session
+        expect_no_stale_jumps!(executor_v4_mock);
+        expect_no_stale_jumps!(executor_v6_mock);
+
+        // Save the current rulesets
+        expect_save!(executor_v4_mock, "*filter\nCOMMIT\n");
+        expect_save!(executor_v6_mock, "*filter\nCOMMIT\n");
+
+        // Apply the rules without flushing, exceptions and the rules for DHCP and neighbor
+        // discovery are only added for their family
+        expect_restore!(executor_v4_mock, pre_connect_ruleset(
+            Family::V4,
+            "-A LINKAGE_OUT_RULES -d 1.1.1.1/32 -p tcp -m tcp --dport 1337 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES -d 127.0.0.1/32 -p udp -m udp --dport 4200 -j ACCEPT\n"
+        ), false);
+        expect_restore!(executor_v6_mock, pre_connect_ruleset(
+            Family::V6,
+            "-A LINKAGE_OUT_RULES -d 2001:db8:85a3::8a2e:370:7334/128 -p udp -m udp --dport \
+             2020 -j ACCEPT\n"
+        ), false);
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
         f.on_pre_connect(&[
             FirewallException::new("1.1.1.1".parse().unwrap(), 1337, FirewallExceptionProtocol::TCP),
             FirewallException::new("127.0.0.1".parse().unwrap(), 4200, FirewallExceptionProtocol::UDP),
             FirewallException::new("2001:0db8:85a3:0000:0000:8a2e:0370:7334".parse().unwrap(), 2020, FirewallExceptionProtocol::UDP),
-        ]).unwrap();
+        ], &FirewallOptions::default()).unwrap();
+
+        assert_eq!(Some(FirewallSnapshot {
+            v4: String::from("*filter\nCOMMIT\n"),
+            v6: String::from("*filter\nCOMMIT\n"),
+        }), *f.lock_snapshot());
+    }
+
+    #[test]
+    fn test_on_pre_connect_stale_jumps() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
+
+        // A failed teardown left the IPv4 jump to LINKAGE_INPUT twice, both are deleted before
+        // the current rulesets are saved
+        expect_execute!(executor_v4_mock, to_string_vec!("-D", "INPUT", "-j", "LINKAGE_INPUT"));
+        expect_execute!(executor_v4_mock, to_string_vec!("-D", "INPUT", "-j", "LINKAGE_INPUT"));
+        expect_no_stale_jumps!(executor_v4_mock);
+        expect_no_stale_jumps!(executor_v6_mock);
+        expect_save!(executor_v4_mock, "*filter\nCOMMIT\n");
+        expect_save!(executor_v6_mock, "*filter\nCOMMIT\n");
+
+        // Only a single jump to each chain is inserted
+        expect_restore!(executor_v4_mock, pre_connect_ruleset(Family::V4, ""), false);
+        expect_restore!(executor_v6_mock, pre_connect_ruleset(Family::V6, ""), false);
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        f.on_pre_connect(&[], &FirewallOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn test_on_pre_connect_allow_lan() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
+
+        expect_no_stale_jumps!(executor_v4_mock);
+        expect_no_stale_jumps!(executor_v6_mock);
+        expect_save!(executor_v4_mock, "*filter\nCOMMIT\n");
+        expect_save!(executor_v6_mock, "*filter\nCOMMIT\n");
+
+        // The local networks are allowed before the exceptions, each for its own family and never
+        // through the tunnel
+        expect_restore!(executor_v4_mock, pre_connect_ruleset(
+            Family::V4,
+            "-A LINKAGE_IN_RULES ! -i tun+ -s 10.0.0.0/8 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d 10.0.0.0/8 -j ACCEPT\n\
+             -A LINKAGE_IN_RULES ! -i tun+ -s 172.16.0.0/12 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d 172.16.0.0/12 -j ACCEPT\n\
+             -A LINKAGE_IN_RULES ! -i tun+ -s 192.168.0.0/16 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d 192.168.0.0/16 -j ACCEPT\n\
+             -A LINKAGE_IN_RULES ! -i tun+ -s 169.254.0.0/16 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d 169.254.0.0/16 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d 224.0.0.0/4 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES -d 1.1.1.1/32 -p udp -m udp --dport 1194 -j ACCEPT\n"
+        ), false);
+        expect_restore!(executor_v6_mock, pre_connect_ruleset(
+            Family::V6,
+            "-A LINKAGE_IN_RULES ! -i tun+ -s fc00::/7 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d fc00::/7 -j ACCEPT\n\
+             -A LINKAGE_IN_RULES ! -i tun+ -s fe80::/10 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d fe80::/10 -j ACCEPT\n\
+             -A LINKAGE_OUT_RULES ! -o tun+ -d ff00::/8 -j ACCEPT\n"
+        ), false);
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        f.on_pre_connect(&[
+            FirewallException::new("1.1.1.1".parse().unwrap(), 1194, FirewallExceptionProtocol::UDP),
+        ], &FirewallOptions {
+            allow_lan: true,
+            allow_multicast: true,
+            tunnel_interface: None,
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_on_pre_connect_rollback() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
+
+        expect_no_stale_jumps!(executor_v4_mock);
+        expect_no_stale_jumps!(executor_v6_mock);
+        expect_save!(executor_v4_mock, "*filter\nCOMMIT\n");
+        expect_save!(executor_v6_mock, "*filter\nCOMMIT\n");
+
+        // The IPv6 transaction fails, so the IPv4 rules have to be removed again
+        expect_restore!(executor_v4_mock, pre_connect_ruleset(Family::V4, ""), false);
+        expect_restore!(
+            executor_v6_mock, pre_connect_ruleset(Family::V6, ""), false,
+            Err(FirewallError::IptablesError(Some(1)))
+        );
+        expect_restore!(executor_v4_mock, TEARDOWN_RULESET, false);
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        assert!(f.on_pre_connect(&[], &FirewallOptions::default()).is_err());
+        // The firewall is back to how it was, so there's nothing to restore
+        assert_eq!(None, f.get_snapshot());
     }
 
     #[test]
@@ -410,47 +835,108 @@ mod tests {
         let mut executor_v6_mock = MockExecutor::new();
 
         // Allow outgoing connections on the supplied interfaces
-        expect_execute!(
-            executor_v4_mock, to_string_vec!("-A", "out_accept", "-o", "tun1", "-j", "ACCEPT")
-        );
-        expect_execute!(
-            executor_v6_mock, to_string_vec!("-A", "out_accept", "-o", "tun1", "-j", "ACCEPT")
-        );
+        expect_execute_both!(executor_v4_mock, executor_v6_mock, to_string_vec!(
+            "-A", "LINKAGE_OUT_RULES", "-o", "tun1", "-j", "ACCEPT"
+        ));
 
-        let f = IpTablesFirewall {
-            executor_v4: &executor_v4_mock,
-            executor_v6: &executor_v6_mock,
-        };
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
         f.on_post_connect("tun1").unwrap();
     }
 
+    #[test]
+    fn test_on_interface_down() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
+
+        // Deletes exactly the rules of on_post_connect
+        expect_execute_both!(executor_v4_mock, executor_v6_mock, to_string_vec!(
+            "-D", "LINKAGE_OUT_RULES", "-o", "tun1", "-j", "ACCEPT"
+        ));
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        f.on_interface_down("tun1").unwrap();
+    }
+
     #[test]
     fn test_on_disconnect() {
         let mut executor_v4_mock = MockExecutor::new();
         let mut executor_v6_mock = MockExecutor::new();
 
-        // Default policies
-        expect_execute!(executor_v4_mock, to_string_vec!("-P", "INPUT", "ACCEPT"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-P", "INPUT", "ACCEPT"));
-        expect_execute!(executor_v4_mock, to_string_vec!("-P", "OUTPUT", "ACCEPT"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-P", "OUTPUT", "ACCEPT"));
-        expect_execute!(executor_v4_mock, to_string_vec!("-P", "FORWARD", "ACCEPT"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-P", "FORWARD", "ACCEPT"));
-
-        // Flushes rules
-        expect_execute!(executor_v4_mock, to_string_vec!("-F"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-F"));
-
-        // Deletes the created chains
-        expect_execute!(executor_v4_mock, to_string_vec!("-X", "in_accept"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-X", "in_accept"));
-        expect_execute!(executor_v4_mock, to_string_vec!("-X", "out_accept"));
-        expect_execute!(executor_v6_mock, to_string_vec!("-X", "out_accept"));
-
-        let f = IpTablesFirewall {
-            executor_v4: &executor_v4_mock,
-            executor_v6: &executor_v6_mock,
-        };
+        // Removes the jumps and chains without flushing anything else
+        expect_restore!(executor_v4_mock, TEARDOWN_RULESET, false);
+        expect_restore!(executor_v6_mock, TEARDOWN_RULESET, false);
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        *f.lock_snapshot() = Some(FirewallSnapshot {
+            v4: String::from("*filter\nCOMMIT\n"),
+            v6: String::from("*filter\nCOMMIT\n"),
+        });
         f.on_disconnect().unwrap();
+
+        // The snapshot is not needed anymore
+        assert_eq!(None, *f.lock_snapshot());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_get_snapshot() {
+        let executor_v4_mock = MockExecutor::new();
+        let executor_v6_mock = MockExecutor::new();
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        assert_eq!(None, f.get_snapshot());
+
+        let snapshot = FirewallSnapshot {
+            v4: String::from("*filter\nCOMMIT\n"),
+            v6: String::from("*filter\nCOMMIT\n"),
+        };
+        *f.lock_snapshot() = Some(snapshot.clone());
+        assert_eq!(Some(snapshot), f.get_snapshot());
+    }
+
+    #[test]
+    fn test_on_reset() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
+
+        // Replaces the current rulesets with the supplied ones
+        expect_restore!(executor_v4_mock, "*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n", true);
+        expect_restore!(executor_v6_mock, "*filter\n:OUTPUT ACCEPT [0:0]\nCOMMIT\n", true);
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        f.on_reset(Some(&FirewallSnapshot {
+            v4: String::from("*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n"),
+            v6: String::from("*filter\n:OUTPUT ACCEPT [0:0]\nCOMMIT\n"),
+        })).unwrap();
+    }
+
+    #[test]
+    fn test_on_reset_without_snapshot() {
+        let mut executor_v4_mock = MockExecutor::new();
+        let mut executor_v6_mock = MockExecutor::new();
+
+        // Errors are ignored, the chain might not exist anymore
+        executor_v4_mock.expect_execute()
+            .times(1)
+            .with(eq(to_string_vec!("-D", "INPUT", "-j", "LINKAGE_INPUT")))
+            .returning(|_| Err(FirewallError::IptablesError(Some(1))));
+        expect_execute!(executor_v6_mock, to_string_vec!("-D", "INPUT", "-j", "LINKAGE_INPUT"));
+        for (built_in_chain, chain) in [
+            ("OUTPUT", "LINKAGE_OUTPUT"),
+            ("FORWARD", "LINKAGE_FORWARD"),
+        ].iter() {
+            expect_execute_both!(executor_v4_mock, executor_v6_mock, to_string_vec!(
+                "-D", *built_in_chain, "-j", *chain
+            ));
+        }
+        for chain in [
+            "LINKAGE_INPUT", "LINKAGE_OUTPUT", "LINKAGE_FORWARD", "LINKAGE_IN_RULES",
+            "LINKAGE_OUT_RULES",
+        ].iter() {
+            expect_execute_both!(executor_v4_mock, executor_v6_mock, to_string_vec!("-F", *chain));
+            expect_execute_both!(executor_v4_mock, executor_v6_mock, to_string_vec!("-X", *chain));
+        }
+
+        let f = IpTablesFirewall::new(&executor_v4_mock, &executor_v6_mock);
+        f.on_reset(None).unwrap();
+    }
+}
@@ -1,9 +1,11 @@
 //! Definition and implementation of different firewall backends.
 
 pub mod iptables;
+pub mod nftables;
 
 use crate::error::FirewallResult;
 use crate::executor::Executor;
+use crate::rules::{Direction, IpNetwork, IpNetworkError, PortRange, PortRangeError};
 use std::error;
 use std::fmt;
 use std::net::IpAddr;
@@ -14,6 +16,8 @@ use std::str::FromStr;
 pub enum FirewallExceptionProtocol {
     TCP,
     UDP,
+    /// ICMP for IPv4 and ICMPv6 for IPv6 networks.
+    ICMP,
 }
 
 /// Occurs when the supplied protocol cannot be parsed using FromStr in FirewallExceptionProtocol.
@@ -37,32 +41,168 @@ impl FromStr for FirewallExceptionProtocol {
     /// FirewallExceptionProtocolError.
     /// - UDP: `udp` | `UDP`
     /// - TCP: `tcp` | `TCP`
+    /// - ICMP: `icmp` | `ICMP`
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "udp" | "UDP" => Ok(Self::UDP),
             "tcp" | "TCP" => Ok(Self::TCP),
+            "icmp" | "ICMP" => Ok(Self::ICMP),
             _ => Err(FirewallExceptionProtocolError),
         }
     }
 }
 
 /// When activating a firewall, the connections to these exceptions will be allowed.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct FirewallException {
-    host: IpAddr,
-    port: u16, // log2(65536)=16
-    protocol: FirewallExceptionProtocol,
+    /// The remote network, i.e. the destination of outbound and the source of inbound connections.
+    pub(crate) network: IpNetwork,
+    /// The local ports of inbound and the remote ports of outbound connections. All ports are
+    /// allowed if this is `None`. Ignored for ICMP.
+    pub(crate) ports: Option<PortRange>,
+    pub(crate) protocol: FirewallExceptionProtocol,
+    pub(crate) direction: Direction,
 }
 
 impl FirewallException {
-    /// Returns a new configured instance of FirewallException.
+    /// Returns a new configured instance of FirewallException that allows outbound connections to
+    /// the supplied port of the host.
     pub fn new(host: IpAddr, port: u16, protocol: FirewallExceptionProtocol) -> FirewallException {
-        return FirewallException {
-            host,
-            port,
+        FirewallException::from_network(IpNetwork::host(host), protocol)
+            .ports(PortRange::single(port))
+    }
+
+    /// Returns a new instance of FirewallException that allows outbound connections to all ports
+    /// of the supplied network.
+    pub fn from_network(network: IpNetwork, protocol: FirewallExceptionProtocol) -> FirewallException {
+        FirewallException {
+            network,
+            ports: None,
             protocol,
-        };
+            direction: Direction::Output,
+        }
     }
+
+    /// Restricts the exception to the supplied ports.
+    pub fn ports(mut self, ports: PortRange) -> FirewallException {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Sets the direction of the connections that are allowed.
+    pub fn direction(mut self, direction: Direction) -> FirewallException {
+        self.direction = direction;
+        self
+    }
+}
+
+/// Occurs when the supplied exception cannot be parsed using FromStr in FirewallException.
+#[derive(Debug, PartialEq)]
+pub enum FirewallExceptionError {
+    /// The exception doesn't have the format `PROTOCOL,NETWORK[,PORTS][,DIRECTION]`.
+    Format,
+    Protocol(FirewallExceptionProtocolError),
+    Network(IpNetworkError),
+    Ports(PortRangeError),
+    /// Ports were supplied for a protocol that doesn't have ports.
+    PortsNotSupported,
+}
+
+impl fmt::Display for FirewallExceptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Format => write!(
+                f, "The exception has to be in the format PROTOCOL,NETWORK[,PORTS][,in|out]"
+            ),
+            Self::Protocol(e) => write!(f, "{}", e),
+            Self::Network(e) => write!(f, "{}", e),
+            Self::Ports(e) => write!(f, "{}", e),
+            Self::PortsNotSupported => write!(f, "ICMP exceptions can't have ports"),
+        }
+    }
+}
+
+impl error::Error for FirewallExceptionError {}
+
+impl FromStr for FirewallException {
+    type Err = FirewallExceptionError;
+
+    /// Converts the supplied string in the format `PROTOCOL,NETWORK[,PORTS][,DIRECTION]` into a
+    /// FirewallException.
+    /// # Values
+    /// - PROTOCOL: see FirewallExceptionProtocol
+    /// - NETWORK: an address, e.g. `192.168.1.10`, or a network, e.g. `192.168.1.0/24`
+    /// - PORTS: a port, e.g. `443`, a range of ports, e.g. `1000-2000`, or `any`, which is the
+    ///   default
+    /// - DIRECTION: `out` for outbound connections to the network, which is the default, or `in`
+    ///   for inbound connections from the network
+    /// # Examples
+    /// - `tcp,192.168.1.10,445`
+    /// - `udp,10.0.0.0/8,any,in`
+    /// - `icmp,fe80::/10`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let protocol: FirewallExceptionProtocol = parts.next()
+            .ok_or(FirewallExceptionError::Format)?
+            .parse()
+            .map_err(FirewallExceptionError::Protocol)?;
+        let network: IpNetwork = parts.next()
+            .ok_or(FirewallExceptionError::Format)?
+            .parse()
+            .map_err(FirewallExceptionError::Network)?;
+        let mut exception = FirewallException::from_network(network, protocol);
+
+        let mut part = parts.next();
+        // The ports are optional, so the third value might already be the direction
+        if let Some(ports) = part.filter(|p| !matches!(*p, "in" | "out")) {
+            if protocol == FirewallExceptionProtocol::ICMP {
+                return Err(FirewallExceptionError::PortsNotSupported);
+            }
+            if ports != "any" {
+                exception = exception.ports(ports.parse().map_err(FirewallExceptionError::Ports)?);
+            }
+            part = parts.next();
+        }
+        if let Some(direction) = part {
+            exception = exception.direction(match direction {
+                "in" => Direction::Input,
+                "out" => Direction::Output,
+                _ => return Err(FirewallExceptionError::Format),
+            });
+        }
+
+        // There mustn't be anything left
+        if parts.next().is_some() {
+            return Err(FirewallExceptionError::Format);
+        }
+
+        Ok(exception)
+    }
+}
+
+/// Options that change which traffic is allowed by the firewall in addition to the exceptions.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FirewallOptions {
+    /// Allows traffic to and from the local network, i.e. private IPv4 networks (RFC 1918),
+    /// link-local addresses and IPv6 unique local addresses.
+    pub allow_lan: bool,
+    /// Additionally allows outgoing multicast traffic, e.g. mDNS. Only has an effect if `allow_lan`
+    /// is set.
+    pub allow_multicast: bool,
+    /// The name of the tunnel interface if it's known before connecting. The traffic that keeps
+    /// the physical interfaces configured, e.g. DHCP, is never allowed on it. Without a name, all
+    /// interfaces whose name starts with `tun` are excluded.
+    pub tunnel_interface: Option<String>,
+}
+
+/// The rulesets that were active before the firewall was set up. Used to restore the state of the
+/// firewall from before the connection.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FirewallSnapshot {
+    /// The saved IPv4 ruleset.
+    pub v4: String,
+    /// The saved IPv6 ruleset.
+    pub v6: String,
 }
 
 /// Holds a identifier for a firewall backend that is unique to the specific backend. Used for
@@ -72,6 +212,13 @@ pub struct FirewallIdentifier {
     identifier: &'static str,
 }
 
+impl FirewallIdentifier {
+    /// Returns a new instance of FirewallIdentifier with the supplied identifier.
+    pub fn new(identifier: &'static str) -> FirewallIdentifier {
+        FirewallIdentifier { identifier }
+    }
+}
+
 impl PartialEq<&str> for FirewallIdentifier {
     /// Returns whether the identifier of the firewall backend is equal to a supplied string.
     fn eq(&self, other: &&str) -> bool {
@@ -82,7 +229,13 @@ impl PartialEq<&str> for FirewallIdentifier {
 impl PartialEq<FirewallIdentifier> for &str {
     /// Returns whether the string is equal to the identifier of a firewall backend.
     fn eq(&self, other: &FirewallIdentifier) -> bool {
-        return *other == *self
+        *other == *self
+    }
+}
+
+impl fmt::Display for FirewallIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.identifier)
     }
 }
 
@@ -103,13 +256,30 @@ pub trait FirewallBackend {
     /// operating system or different installed packages.
     fn is_available(&self) -> FirewallResult<bool>;
     /// Called before connecting to the VPN server. Blocks all traffic into the internet while still
-    /// allowing connections to the supplied exceptions. These include the vpn server.
-    fn on_pre_connect(&self, exceptions: &[FirewallException]) -> FirewallResult<()>;
+    /// allowing connections to the supplied exceptions. These include the vpn server. The options
+    /// can allow additional traffic, e.g. to the local network.
+    fn on_pre_connect(
+        &self,
+        exceptions: &[FirewallException],
+        options: &FirewallOptions,
+    ) -> FirewallResult<()>;
     /// Called after connecting to the VPN server. Allows all traffic from and to the supplied
     /// interface identifier.
     fn on_post_connect(&self, interface_identifier: &str) -> FirewallResult<()>;
-    /// Called when the connection to the VPN server was closed. Resets the firewall.
+    /// Called when the tunnel interface went down while connected, e.g. before reconnecting.
+    /// Removes the rules of `on_post_connect` for the supplied interface identifier, while the
+    /// rules of `on_pre_connect` stay in place.
+    fn on_interface_down(&self, interface_identifier: &str) -> FirewallResult<()>;
+    /// Called when the connection to the VPN server was closed. Restores the firewall to the state
+    /// it had before `on_pre_connect` was called.
     fn on_disconnect(&self) -> FirewallResult<()>;
+    /// Returns the rulesets that were saved in `on_pre_connect`, if the backend saves them. They
+    /// can be persisted and later passed to `on_reset`.
+    fn get_snapshot(&self) -> Option<FirewallSnapshot>;
+    /// Called when the application exited without calling `on_disconnect`, e.g. after a crash.
+    /// Restores the supplied snapshot if there is one, otherwise it resets the firewall as well as
+    /// possible.
+    fn on_reset(&self, snapshot: Option<&FirewallSnapshot>) -> FirewallResult<()>;
 }
 
 #[cfg(test)]
@@ -151,13 +321,28 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_firewall_identifier_new() {
+        assert_eq!(FirewallIdentifier {
+            identifier: "imagine"
+        }, FirewallIdentifier::new("imagine"));
+    }
+
+    #[test]
+    fn test_firewall_identifier_display() {
+        assert_eq!("imagine", format!("{}", FirewallIdentifier {
+            identifier: "imagine"
+        }));
+    }
+
     #[test]
     fn test_firewall_exception_new() {
         assert_eq!(
             FirewallException {
-                host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-                port: 1337,
+                network: IpNetwork::host(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+                ports: Some(PortRange::single(1337)),
                 protocol: FirewallExceptionProtocol::TCP,
+                direction: Direction::Output,
             },
             FirewallException::new(
                 IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
@@ -167,6 +352,99 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_firewall_exception_builder() {
+        let network = IpNetwork::from_str("192.168.0.0/16").unwrap();
+        assert_eq!(
+            FirewallException {
+                network,
+                ports: Some(PortRange::new(1000, 2000)),
+                protocol: FirewallExceptionProtocol::UDP,
+                direction: Direction::Input,
+            },
+            FirewallException::from_network(network, FirewallExceptionProtocol::UDP)
+                .ports(PortRange::new(1000, 2000))
+                .direction(Direction::Input)
+        );
+        assert_eq!(
+            FirewallException {
+                network,
+                ports: None,
+                protocol: FirewallExceptionProtocol::ICMP,
+                direction: Direction::Output,
+            },
+            FirewallException::from_network(network, FirewallExceptionProtocol::ICMP)
+        );
+    }
+
+    #[test]
+    fn test_firewall_exception_from_str() {
+        let network = IpNetwork::from_str("192.168.1.0/24").unwrap();
+        assert_eq!(
+            FirewallException::new("192.168.1.10".parse().unwrap(), 445, FirewallExceptionProtocol::TCP),
+            FirewallException::from_str("tcp,192.168.1.10,445").unwrap()
+        );
+        assert_eq!(
+            FirewallException::from_network(network, FirewallExceptionProtocol::UDP)
+                .ports(PortRange::new(1000, 2000))
+                .direction(Direction::Input),
+            FirewallException::from_str("udp,192.168.1.0/24,1000-2000,in").unwrap()
+        );
+        assert_eq!(
+            FirewallException::from_network(network, FirewallExceptionProtocol::TCP),
+            FirewallException::from_str("tcp, 192.168.1.0/24, any, out").unwrap()
+        );
+        assert_eq!(
+            FirewallException::from_network(network, FirewallExceptionProtocol::TCP)
+                .direction(Direction::Input),
+            FirewallException::from_str("TCP,192.168.1.0/24,in").unwrap()
+        );
+        assert_eq!(
+            FirewallException::from_network(
+                IpNetwork::from_str("fe80::/10").unwrap(), FirewallExceptionProtocol::ICMP
+            ),
+            FirewallException::from_str("icmp,fe80::/10").unwrap()
+        );
+
+        assert_eq!(Err(FirewallExceptionError::Format), FirewallException::from_str("tcp"));
+        assert_eq!(
+            Err(FirewallExceptionError::Format),
+            FirewallException::from_str("tcp,10.0.0.1,22,in,out")
+        );
+        assert_eq!(
+            Err(FirewallExceptionError::Format),
+            FirewallException::from_str("tcp,10.0.0.1,22,up")
+        );
+        assert_eq!(
+            Err(FirewallExceptionError::Protocol(FirewallExceptionProtocolError)),
+            FirewallException::from_str("sctp,10.0.0.1")
+        );
+        assert_eq!(
+            Err(FirewallExceptionError::Network(IpNetworkError)),
+            FirewallException::from_str("tcp,10.0.0.1/40")
+        );
+        assert_eq!(
+            Err(FirewallExceptionError::Ports(PortRangeError)),
+            FirewallException::from_str("tcp,10.0.0.1,ssh")
+        );
+        assert_eq!(
+            Err(FirewallExceptionError::PortsNotSupported),
+            FirewallException::from_str("icmp,10.0.0.1,any")
+        );
+    }
+
+    #[test]
+    fn test_firewall_exception_error_format() {
+        assert_eq!(
+            "ICMP exceptions can't have ports",
+            format!("{}", FirewallExceptionError::PortsNotSupported)
+        );
+        assert_eq!(
+            "The network is invalid",
+            format!("{}", FirewallExceptionError::Network(IpNetworkError))
+        );
+    }
+
     #[test]
     fn test_firewall_exception_protocol_error_format() {
         assert_eq!(
@@ -193,6 +471,14 @@ mod tests {
             FirewallExceptionProtocol::TCP,
             FirewallExceptionProtocol::from_str("TCP").unwrap()
         );
+        assert_eq!(
+            FirewallExceptionProtocol::ICMP,
+            FirewallExceptionProtocol::from_str("icmp").unwrap()
+        );
+        assert_eq!(
+            FirewallExceptionProtocol::ICMP,
+            FirewallExceptionProtocol::from_str("ICMP").unwrap()
+        );
 
         assert_eq!(
             FirewallExceptionProtocolError,
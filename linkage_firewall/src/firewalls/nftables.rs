@@ -0,0 +1,571 @@
+//! Implementation of the nftables firewall backend
+
+use super::{FirewallBackend, FirewallException, FirewallOptions};
+use crate::error::FirewallResult;
+use crate::executor::Executor;
+use crate::executor::nftables::NFT_BINARY_NAME;
+use crate::to_string_vec;
+use crate::firewalls::{FirewallIdentifier, FirewallSnapshot};
+use crate::policy::{get_post_connect_rules, get_pre_connect_rules};
+use crate::rules::{
+    ConnectionState, Direction, Family, FirewallRule, IpNetwork, PortRange, Protocol, Verdict,
+};
+use crate::utils::{is_linux, does_binary_exist};
+
+/// Identifies the nftables backend uniquely.
+const NFTABLES_BACKEND_IDENTIFIER: &str = "nftables";
+
+/// The family of the table. `inet` handles both IPv4 and IPv6 traffic.
+const TABLE_FAMILY: &str = "inet";
+/// The name of the table that contains all rules of the backend.
+const TABLE_NAME: &str = "linkage";
+/// The name of the chain that is hooked into `input`.
+const INPUT_CHAIN_NAME: &str = "input";
+/// The name of the chain that is hooked into `output`.
+const OUTPUT_CHAIN_NAME: &str = "output";
+/// The name of the chain that is hooked into `forward`.
+const FORWARD_CHAIN_NAME: &str = "forward";
+/// The name of the regular chain that contains the inbound rules of the tunnel interface. It's
+/// flushed when the interface goes down.
+const TUNNEL_INPUT_CHAIN_NAME: &str = "tunnel_input";
+/// The name of the regular chain that contains the outbound rules of the tunnel interface.
+const TUNNEL_OUTPUT_CHAIN_NAME: &str = "tunnel_output";
+
+/// Uses `nft` as a backend for the firewall configuration. All rules are kept in a dedicated
+/// `inet` table, so IPv4 and IPv6 are handled by a single executor.
+pub struct NftablesFirewall<'a, T: Executor> {
+    executor: &'a T,
+}
+
+impl<'a, T: Executor> NftablesFirewall<'a, T> {
+    /// Returns a new instance of NftablesFirewall with the supplied executor.
+    pub fn new(executor: &'a T) -> NftablesFirewall<'a, T> {
+        NftablesFirewall {
+            executor,
+        }
+    }
+
+    /// Returns the executor used for the `nft` operations.
+    fn get_executor(&self) -> &T {
+        self.executor
+    }
+}
+
+/// Returns the name of the base chain that contains the rules of the supplied direction.
+fn get_chain_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Input => INPUT_CHAIN_NAME,
+        Direction::Output => OUTPUT_CHAIN_NAME,
+    }
+}
+
+/// Returns the name of the regular chain that contains the tunnel rules of the supplied direction.
+fn get_tunnel_chain_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Input => TUNNEL_INPUT_CHAIN_NAME,
+        Direction::Output => TUNNEL_OUTPUT_CHAIN_NAME,
+    }
+}
+
+/// Returns the nftables address family keyword (`ip` or `ip6`) of the supplied family.
+fn get_address_family(family: Family) -> &'static str {
+    match family {
+        Family::V4 => "ip",
+        Family::V6 => "ip6",
+    }
+}
+
+/// Formats a port range the way nftables expects it, e.g. `53` or `1000-2000`.
+fn format_ports(ports: &PortRange) -> String {
+    if ports.is_single() {
+        ports.start().to_string()
+    } else {
+        format!("{}-{}", ports.start(), ports.end())
+    }
+}
+
+/// Quotes the supplied interface name and replaces the trailing `+` wildcard of the rule model with
+/// the `*` of nftables.
+fn format_interface(interface: &str) -> String {
+    match interface.strip_suffix('+') {
+        Some(prefix) => format!("\"{}*\"", prefix),
+        None => format!("\"{}\"", interface),
+    }
+}
+
+/// Returns the arguments to match the supplied network as source (`saddr`) or destination
+/// (`daddr`).
+fn network_args(network: &IpNetwork, keyword: &str) -> Vec<String> {
+    to_string_vec!(get_address_family(network.family()), keyword, network.to_string())
+}
+
+/// Returns the arguments of the supplied rule as they are written inside of a chain, e.g.
+/// `ip daddr 1.1.1.1/32 tcp dport 1337 accept`.
+fn rule_args(rule: &FirewallRule) -> Vec<String> {
+    let mut args = Vec::new();
+
+    if let Some(interface) = &rule.interface {
+        let interface_keyword = match rule.direction {
+            Direction::Input => "iifname",
+            Direction::Output => "oifname",
+        };
+        args.extend(to_string_vec!(interface_keyword, format_interface(interface)));
+    }
+    if let Some(interface) = &rule.excluded_interface {
+        let interface_keyword = match rule.direction {
+            Direction::Input => "iifname",
+            Direction::Output => "oifname",
+        };
+        args.extend(to_string_vec!(interface_keyword, "!=", format_interface(interface)));
+    }
+    // Addresses and ICMP already restrict the family, so it only has to be matched explicitly if
+    // the rule doesn't contain any of them
+    let family_restricted = rule.source.is_some() || rule.destination.is_some()
+        || rule.protocol.and_then(|p| p.family()).is_some();
+    if let (Some(family), false) = (rule.family, family_restricted) {
+        let nfproto = match family {
+            Family::V4 => "ipv4",
+            Family::V6 => "ipv6",
+        };
+        args.extend(to_string_vec!("meta", "nfproto", nfproto));
+    }
+    if let Some(source) = &rule.source {
+        args.extend(network_args(source, "saddr"));
+    }
+    if let Some(destination) = &rule.destination {
+        args.extend(network_args(destination, "daddr"));
+    }
+    if let Some(hop_limit) = rule.hop_limit {
+        args.extend(to_string_vec!("ip6", "hoplimit", hop_limit.to_string()));
+    }
+    if let Some(protocol) = &rule.protocol {
+        let (protocol, header) = match protocol {
+            Protocol::Tcp => ("tcp", "tcp"),
+            Protocol::Udp => ("udp", "udp"),
+            Protocol::Icmp => ("icmp", "icmp"),
+            Protocol::IcmpV6 => ("ipv6-icmp", "icmpv6"),
+        };
+        // Matching a header field implies the protocol
+        if rule.source_ports.is_none() && rule.destination_ports.is_none()
+            && rule.icmp_type.is_none() {
+            args.extend(to_string_vec!("meta", "l4proto", protocol));
+        }
+        if let Some(icmp_type) = rule.icmp_type {
+            args.extend(to_string_vec!(header, "type", icmp_type.to_string()));
+        }
+        if let Some(ports) = &rule.source_ports {
+            args.extend(to_string_vec!(header, "sport", format_ports(ports)));
+        }
+        if let Some(ports) = &rule.destination_ports {
+            args.extend(to_string_vec!(header, "dport", format_ports(ports)));
+        }
+    }
+    if !rule.states.is_empty() {
+        let states: Vec<&str> = rule.states.iter().map(|s| match s {
+            ConnectionState::New => "new",
+            ConnectionState::Established => "established",
+            ConnectionState::Related => "related",
+            ConnectionState::Invalid => "invalid",
+            ConnectionState::Untracked => "untracked",
+        }).collect();
+        args.extend(to_string_vec!("ct", "state", states.join(",")));
+    }
+    let verdict = match rule.verdict {
+        Verdict::Accept => "accept",
+        Verdict::Drop => "drop",
+    };
+    args.push(verdict.to_string());
+
+    args
+}
+
+/// Renders a chain block with the supplied statements. Base chains get the header that hooks them
+/// into `hook` with a `drop` policy.
+fn render_chain(chain: &str, hook: Option<&str>, statements: &[String]) -> String {
+    let mut rendered = format!("\tchain {} {{\n", chain);
+    if let Some(hook) = hook {
+        rendered.push_str(&format!(
+            "\t\ttype filter hook {} priority 0; policy drop;\n", hook
+        ));
+    }
+    for statement in statements.iter() {
+        rendered.push_str(&format!("\t\t{}\n", statement));
+    }
+    rendered.push_str("\t}\n");
+    rendered
+}
+
+/// Renders the script that (re)creates the `linkage` table with all of its chains and the
+/// supplied rules. An existing table is deleted in the same transaction, so `nft -f` either
+/// replaces the table completely or leaves it untouched.
+fn render_table(rules: &[FirewallRule]) -> String {
+    let mut script = format!(
+        "add table {0} {1}\ndelete table {0} {1}\ntable {0} {1} {{\n", TABLE_FAMILY, TABLE_NAME,
+    );
+
+    // The tunnel chains are declared first since the base chains jump to them
+    for direction in [Direction::Input, Direction::Output].iter() {
+        script.push_str(&render_chain(get_tunnel_chain_name(*direction), None, &[]));
+    }
+    for (direction, hook) in [(Direction::Input, "input"), (Direction::Output, "output")].iter() {
+        let mut statements: Vec<String> = rules.iter()
+            .filter(|r| r.direction == *direction)
+            .map(|r| rule_args(r).join(" "))
+            .collect();
+        statements.push(format!("jump {}", get_tunnel_chain_name(*direction)));
+        script.push_str(&render_chain(get_chain_name(*direction), Some(hook), &statements));
+    }
+    script.push_str(&render_chain(FORWARD_CHAIN_NAME, Some("forward"), &[]));
+
+    script.push_str("}\n");
+    script
+}
+
+/// Renders the commands that flush both tunnel chains.
+fn render_flush_tunnel_chains() -> String {
+    [TUNNEL_INPUT_CHAIN_NAME, TUNNEL_OUTPUT_CHAIN_NAME].iter()
+        .map(|chain| format!("flush chain {} {} {}\n", TABLE_FAMILY, TABLE_NAME, chain))
+        .collect()
+}
+
+impl<'a, T: Executor> FirewallBackend for NftablesFirewall<'a, T> {
+    fn get_identifier(&self) -> FirewallIdentifier {
+        FirewallIdentifier {
+            identifier: NFTABLES_BACKEND_IDENTIFIER,
+        }
+    }
+
+    /// The NftablesFirewall backend is available if the operating system is Linux and an
+    /// executable with the name `nft` is found.
+    fn is_available(&self) -> FirewallResult<bool> {
+        // nftables is only available if we use linux
+        if is_linux() {
+            does_binary_exist(NFT_BINARY_NAME)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Applies the following rules in a single `nft -f` transaction, so either all or none of them
+    /// are active:
+    /// - (Re)creates the `inet` table `linkage`
+    /// - Creates the chains `input`, `output` and `forward` with a default policy of `drop`
+    /// - Creates the chains `tunnel_input` and `tunnel_output` for the rules of `on_post_connect`
+    /// - For both the `input` and `output` chain, it will:
+    ///     - Accept related/established traffic
+    ///     - Drop invalid packets
+    ///     - Allow traffic on the loopback device
+    /// - Allow DHCPv4, DHCPv6 and the neighbor discovery protocol
+    /// - Allow traffic to and from the local network if enabled in the options
+    /// - Add exceptions for the supplied FirewallExceptions. They can be used for e.g. whitelisting
+    ///   VPN servers
+    /// - Jump to the tunnel chains from `input` and `output`
+    fn on_pre_connect(
+        &self,
+        exceptions: &[FirewallException],
+        options: &FirewallOptions,
+    ) -> FirewallResult<()> {
+        let script = render_table(&get_pre_connect_rules(exceptions, options));
+        self.get_executor().restore(script, false)
+    }
+
+    /// Applies the following rules in the tunnel chains in a single `nft -f` transaction:
+    /// - Allows outgoing connections from the supplied interface identifier
+    fn on_post_connect(&self, interface_identifier: &str) -> FirewallResult<()> {
+        let script: String = get_post_connect_rules(interface_identifier).iter()
+            .map(|rule| format!(
+                "add rule {} {} {} {}\n", TABLE_FAMILY, TABLE_NAME,
+                get_tunnel_chain_name(rule.direction), rule_args(rule).join(" "),
+            ))
+            .collect();
+
+        self.get_executor().restore(script, false)
+    }
+
+    /// Flushes the tunnel chains, which only contain the rules of `on_post_connect`, in a single
+    /// `nft -f` transaction.
+    fn on_interface_down(&self, _interface_identifier: &str) -> FirewallResult<()> {
+        self.get_executor().restore(render_flush_tunnel_chains(), false)
+    }
+
+    /// Deletes the `linkage` table including all of its chains and rules. Other tables are not
+    /// touched.
+    fn on_disconnect(&self) -> FirewallResult<()> {
+        self.get_executor().execute(to_string_vec!("delete", "table", TABLE_FAMILY, TABLE_NAME))?;
+
+        Ok(())
+    }
+
+    /// Since all rules live in a dedicated table, there is nothing to save.
+    fn get_snapshot(&self) -> Option<FirewallSnapshot> {
+        None
+    }
+
+    /// Deletes the `linkage` table if it exists. The snapshot is ignored because it is never
+    /// created by this backend.
+    fn on_reset(&self, _snapshot: Option<&FirewallSnapshot>) -> FirewallResult<()> {
+        let executor = self.get_executor();
+
+        // Adding an existing table is a no-op, so this ensures that the deletion won't fail
+        executor.execute(to_string_vec!("add", "table", TABLE_FAMILY, TABLE_NAME))?;
+        executor.execute(to_string_vec!("delete", "table", TABLE_FAMILY, TABLE_NAME))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::MockExecutor;
+    use mockall::predicate::*;
+    use crate::{expect_execute, expect_restore};
+    use crate::error::FirewallError;
+    use crate::firewalls::FirewallExceptionProtocol;
+
+    #[test]
+    fn test_get_identifier() {
+        let executor_mock = MockExecutor::new();
+        let f = NftablesFirewall {
+            executor: &executor_mock,
+        };
+
+        assert_eq!(FirewallIdentifier {
+            identifier: "nftables"
+        }, f.get_identifier());
+    }
+
+    #[test]
+    fn test_is_available() -> FirewallResult<()> {
+        let executor_mock = MockExecutor::new();
+        let f = NftablesFirewall {
+            executor: &executor_mock,
+        };
+
+        assert_eq!(is_linux() && does_binary_exist("nft")?, f.is_available()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new() {
+        let executor_mock = MockExecutor::new();
+
+        let f = NftablesFirewall::new(&executor_mock);
+
+        assert_eq!(f.executor, &executor_mock);
+        assert_eq!(f.get_executor(), &executor_mock);
+    }
+
+    #[test]
+    fn test_rule_args() {
+        assert_eq!(
+            to_string_vec!("drop"),
+            rule_args(&FirewallRule::new(Direction::Input, Verdict::Drop))
+        );
+        assert_eq!(
+            to_string_vec!(
+                "iifname", "\"eth0\"", "ip", "saddr", "192.168.0.0/16", "udp", "sport", "67",
+                "udp", "dport", "68", "accept"
+            ),
+            rule_args(&FirewallRule::new(Direction::Input, Verdict::Accept)
+                .interface("eth0")
+                .source("192.168.0.0/16".parse().unwrap())
+                .protocol(Protocol::Udp)
+                .source_ports(PortRange::single(67))
+                .destination_ports(PortRange::single(68)))
+        );
+        assert_eq!(
+            to_string_vec!(
+                "ip6", "daddr", "fe80::/10", "tcp", "dport", "1000-2000", "ct", "state",
+                "new,untracked", "accept"
+            ),
+            rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination("fe80::/10".parse().unwrap())
+                .protocol(Protocol::Tcp)
+                .destination_ports(PortRange::new(1000, 2000))
+                .states(&[ConnectionState::New, ConnectionState::Untracked]))
+        );
+        assert_eq!(
+            to_string_vec!("ip", "daddr", "192.168.1.1/32", "meta", "l4proto", "icmp", "accept"),
+            rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination("192.168.1.1/32".parse().unwrap())
+                .protocol(Protocol::Icmp))
+        );
+        assert_eq!(
+            to_string_vec!("icmpv6", "type", "134", "accept"),
+            rule_args(&FirewallRule::new(Direction::Input, Verdict::Accept)
+                .protocol(Protocol::IcmpV6)
+                .icmp_type(134))
+        );
+        assert_eq!(
+            to_string_vec!("meta", "l4proto", "ipv6-icmp", "accept"),
+            rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .family(Family::V6)
+                .protocol(Protocol::IcmpV6))
+        );
+        assert_eq!(
+            to_string_vec!("meta", "nfproto", "ipv6", "meta", "l4proto", "tcp", "accept"),
+            rule_args(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .family(Family::V6)
+                .protocol(Protocol::Tcp))
+        );
+        assert_eq!(
+            to_string_vec!(
+                "iifname", "!=", "\"tun*\"", "ip6", "daddr", "ff02::/16", "ip6", "hoplimit", "255",
+                "icmpv6", "type", "135", "accept"
+            ),
+            rule_args(&FirewallRule::new(Direction::Input, Verdict::Accept)
+                .excluded_interface("tun+")
+                .destination("ff02::/16".parse().unwrap())
+                .protocol(Protocol::IcmpV6)
+                .icmp_type(135)
+                .hop_limit(255))
+        );
+    }
+
+    #[test]
+    fn test_on_pre_connect() {
+        let mut executor_mock = MockExecutor::new();
+
+        // The table is replaced as a whole in a single transaction
+        expect_restore!(executor_mock, concat!(
+            "add table inet linkage\n",
+            "delete table inet linkage\n",
+            "table inet linkage {\n",
+            "\tchain tunnel_input {\n",
+            "\t}\n",
+            "\tchain tunnel_output {\n",
+            "\t}\n",
+            "\tchain input {\n",
+            "\t\ttype filter hook input priority 0; policy drop;\n",
+            "\t\tct state related,established accept\n",
+            "\t\tct state invalid drop\n",
+            "\t\tiifname \"lo\" accept\n",
+            "\t\tiifname != \"tun*\" meta nfproto ipv4 udp sport 67 udp dport 68 accept\n",
+            "\t\tiifname != \"tun*\" ip6 saddr fe80::/10 ip6 daddr fe80::/10 udp sport 547 \
+             udp dport 546 accept\n",
+            "\t\tiifname != \"tun*\" ip6 daddr fe80::/10 ip6 hoplimit 255 icmpv6 type 134 accept\n",
+            "\t\tiifname != \"tun*\" ip6 daddr ff02::/16 ip6 hoplimit 255 icmpv6 type 134 accept\n",
+            "\t\tiifname != \"tun*\" ip6 daddr fe80::/10 ip6 hoplimit 255 icmpv6 type 135 accept\n",
+            "\t\tiifname != \"tun*\" ip6 daddr ff02::/16 ip6 hoplimit 255 icmpv6 type 135 accept\n",
+            "\t\tiifname != \"tun*\" ip6 daddr fe80::/10 ip6 hoplimit 255 icmpv6 type 136 accept\n",
+            "\t\tiifname != \"tun*\" ip6 daddr ff02::/16 ip6 hoplimit 255 icmpv6 type 136 accept\n",
+            "\t\tjump tunnel_input\n",
+            "\t}\n",
+            "\tchain output {\n",
+            "\t\ttype filter hook output priority 0; policy drop;\n",
+            "\t\tct state related,established accept\n",
+            "\t\tct state invalid drop\n",
+            "\t\toifname \"lo\" accept\n",
+            "\t\toifname != \"tun*\" ip daddr 255.255.255.255/32 udp sport 68 udp dport 67 \
+             accept\n",
+            "\t\toifname != \"tun*\" ip daddr 10.0.0.0/8 udp sport 68 udp dport 67 accept\n",
+            "\t\toifname != \"tun*\" ip daddr 172.16.0.0/12 udp sport 68 udp dport 67 accept\n",
+            "\t\toifname != \"tun*\" ip daddr 192.168.0.0/16 udp sport 68 udp dport 67 accept\n",
+            "\t\toifname != \"tun*\" ip daddr 169.254.0.0/16 udp sport 68 udp dport 67 accept\n",
+            "\t\toifname != \"tun*\" ip6 saddr fe80::/10 ip6 daddr ff02::1:2/128 udp sport 546 \
+             udp dport 547 accept\n",
+            "\t\toifname != \"tun*\" ip6 saddr fe80::/10 ip6 daddr fe80::/10 udp sport 546 \
+             udp dport 547 accept\n",
+            "\t\toifname != \"tun*\" ip6 daddr fe80::/10 ip6 hoplimit 255 icmpv6 type 133 accept\n",
+            "\t\toifname != \"tun*\" ip6 daddr ff02::/16 ip6 hoplimit 255 icmpv6 type 133 accept\n",
+            "\t\toifname != \"tun*\" ip6 daddr fe80::/10 ip6 hoplimit 255 icmpv6 type 135 accept\n",
+            "\t\toifname != \"tun*\" ip6 daddr ff02::/16 ip6 hoplimit 255 icmpv6 type 135 accept\n",
+            "\t\toifname != \"tun*\" ip6 daddr fe80::/10 ip6 hoplimit 255 icmpv6 type 136 accept\n",
+            "\t\toifname != \"tun*\" ip6 daddr ff02::/16 ip6 hoplimit 255 icmpv6 type 136 accept\n",
+            "\t\tip daddr 1.1.1.1/32 tcp dport 1337 accept\n",
+            "\t\tip6 daddr 2001:db8:85a3::8a2e:370:7334/128 udp dport 2020 accept\n",
+            "\t\tjump tunnel_output\n",
+            "\t}\n",
+            "\tchain forward {\n",
+            "\t\ttype filter hook forward priority 0; policy drop;\n",
+            "\t}\n",
+            "}\n",
+        ), false);
+
+        let f = NftablesFirewall {
+            executor: &executor_mock,
+        };
+        f.on_pre_connect(&[
+            FirewallException::new("1.1.1.1".parse().unwrap(), 1337, FirewallExceptionProtocol::TCP),
+            FirewallException::new("2001:0db8:85a3:0000:0000:8a2e:0370:7334".parse().unwrap(), 2020, FirewallExceptionProtocol::UDP),
+        ], &FirewallOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn test_on_pre_connect_error() {
+        let mut executor_mock = MockExecutor::new();
+        executor_mock.expect_restore()
+            .times(1)
+            .returning(|_, _| Err(FirewallError::NftablesError(Some(1))));
+
+        let f = NftablesFirewall::new(&executor_mock);
+        assert!(f.on_pre_connect(&[], &FirewallOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_on_post_connect() {
+        let mut executor_mock = MockExecutor::new();
+
+        // Allow outgoing connections on the supplied interface
+        expect_restore!(
+            executor_mock, "add rule inet linkage tunnel_output oifname \"tun1\" accept\n", false
+        );
+
+        let f = NftablesFirewall {
+            executor: &executor_mock,
+        };
+        f.on_post_connect("tun1").unwrap();
+    }
+
+    #[test]
+    fn test_on_interface_down() {
+        let mut executor_mock = MockExecutor::new();
+
+        // Flushes the tunnel chains, the other rules stay in place
+        expect_restore!(
+            executor_mock,
+            "flush chain inet linkage tunnel_input\nflush chain inet linkage tunnel_output\n",
+            false
+        );
+
+        let f = NftablesFirewall {
+            executor: &executor_mock,
+        };
+        f.on_interface_down("tun1").unwrap();
+    }
+
+    #[test]
+    fn test_on_disconnect() {
+        let mut executor_mock = MockExecutor::new();
+
+        // Deletes the table
+        expect_execute!(executor_mock, to_string_vec!("delete", "table", "inet", "linkage"));
+
+        let f = NftablesFirewall {
+            executor: &executor_mock,
+        };
+        f.on_disconnect().unwrap();
+    }
+
+    #[test]
+    fn test_get_snapshot() {
+        let executor_mock = MockExecutor::new();
+        let f = NftablesFirewall::new(&executor_mock);
+
+        assert_eq!(None, f.get_snapshot());
+    }
+
+    #[test]
+    fn test_on_reset() {
+        let mut executor_mock = MockExecutor::new();
+
+        // Makes sure that the table exists and deletes it afterwards
+        expect_execute!(executor_mock, to_string_vec!("add", "table", "inet", "linkage"));
+        expect_execute!(executor_mock, to_string_vec!("delete", "table", "inet", "linkage"));
+
+        let f = NftablesFirewall::new(&executor_mock);
+        f.on_reset(None).unwrap();
+    }
+}
@@ -3,12 +3,19 @@
 
 pub use error::FirewallError;
 pub use firewalls::{
-    FirewallBackend, FirewallException, FirewallExceptionProtocol, FirewallExceptionProtocolError,
+    FirewallBackend, FirewallException, FirewallExceptionError, FirewallExceptionProtocol,
+    FirewallExceptionProtocolError, FirewallIdentifier, FirewallOptions, FirewallSnapshot,
+};
+pub use rules::{
+    ConnectionState, Direction, Family, FirewallRule, IpNetwork, IpNetworkError, PortRange,
+    PortRangeError, Protocol, Verdict,
 };
 
 mod error;
 mod executor;
 pub(crate) mod firewalls;
+mod policy;
+mod rules;
 #[macro_use]
 mod utils;
 #[cfg(test)]
@@ -16,6 +23,7 @@ mod utils;
 mod test_utils;
 
 use executor::iptables::{IptablesBaseCommand, IptablesCommandExecutor};
+use executor::nftables::NftablesCommandExecutor;
 use lazy_static::lazy_static;
 
 /// A list that contains firewall backends.
@@ -26,25 +34,27 @@ lazy_static! {
     static ref IPTABLES_COMMAND_EXECUTOR: IptablesCommandExecutor = IptablesCommandExecutor::new(IptablesBaseCommand::Iptables);
     /// The command executor for `ip6tables` actions.
     static ref IP6TABLES_COMMAND_EXECUTOR: IptablesCommandExecutor = IptablesCommandExecutor::new(IptablesBaseCommand::Ip6tables);
+    /// The command executor for `nft` actions.
+    static ref NFT_COMMAND_EXECUTOR: NftablesCommandExecutor = NftablesCommandExecutor::new();
 
     /// A list of all implemented firewall backends.
-    static ref ALL_BACKENDS: FirewallBackendList = {
-        let mut v: FirewallBackendList = Vec::new();
-        v.push(
-            Box::new(
-                firewalls::iptables::IpTablesFirewall::new(
-                    // Deref is necessary so that we get a value that implements the Executor trait
-                    &*IPTABLES_COMMAND_EXECUTOR,
-                    &*IP6TABLES_COMMAND_EXECUTOR,
-                )
+    static ref ALL_BACKENDS: FirewallBackendList = vec![
+        Box::new(
+            firewalls::iptables::IpTablesFirewall::new(
+                // Deref is necessary so that we get a value that implements the Executor trait
+                &*IPTABLES_COMMAND_EXECUTOR,
+                &*IP6TABLES_COMMAND_EXECUTOR,
             )
-        );
-        v
-    };
+        ),
+        Box::new(
+            firewalls::nftables::NftablesFirewall::new(&*NFT_COMMAND_EXECUTOR)
+        ),
+    ];
 }
 
 /// Returns all available firewall backends. Currently, this are:
 /// - `iptables`
+/// - `nftables`
 pub fn get_backends() -> &'static FirewallBackendList {
     &ALL_BACKENDS
 }
@@ -56,9 +66,10 @@ mod tests {
     #[test]
     fn test_all_backends() {
         let backends = get_backends();
-        assert_eq!(backends.len(), 1);
+        assert_eq!(backends.len(), 2);
 
-        assert_eq!(backends.get(0).unwrap().get_identifier(), "iptables");
-        assert!(backends.get(1).is_none());
+        assert_eq!(backends.first().unwrap().get_identifier(), "iptables");
+        assert_eq!(backends.get(1).unwrap().get_identifier(), "nftables");
+        assert!(backends.get(2).is_none());
     }
 }
@@ -0,0 +1,468 @@
+//! The policy of the firewall, i.e. which traffic is allowed before and after connecting to the
+//! VPN server. The backends only compile the rules returned here into their own syntax.
+
+use crate::firewalls::{FirewallException, FirewallExceptionProtocol, FirewallOptions};
+use crate::rules::{
+    ConnectionState, Direction, Family, FirewallRule, IpNetwork, PortRange, Protocol, Verdict,
+};
+
+/// The name of the loopback interface.
+const LOOPBACK_INTERFACE: &str = "lo";
+/// Matches the tunnel interfaces of OpenVPN if the name of the tunnel interface isn't known.
+const DEFAULT_TUNNEL_INTERFACE: &str = "tun+";
+
+/// The networks of the local network: the private IPv4 networks (RFC 1918), the IPv4 link-local
+/// network, IPv6 unique local addresses and the IPv6 link-local network.
+const LAN_NETWORKS: [&str; 6] = [
+    "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16", "fc00::/7", "fe80::/10",
+];
+
+/// The port of DHCPv4 servers.
+const DHCPV4_SERVER_PORT: u16 = 67;
+/// The port of DHCPv4 clients.
+const DHCPV4_CLIENT_PORT: u16 = 68;
+/// The port of DHCPv6 clients.
+const DHCPV6_CLIENT_PORT: u16 = 546;
+/// The port of DHCPv6 servers and relay agents.
+const DHCPV6_SERVER_PORT: u16 = 547;
+/// DHCPv6 messages between clients and servers on the same link use link-local addresses.
+const IPV6_LINK_LOCAL_NETWORK: &str = "fe80::/10";
+/// The destinations of DHCPv4 requests: the limited broadcast address before the client has an
+/// address and the server in the local network when renewing the lease.
+const DHCPV4_DESTINATIONS: [&str; 5] = [
+    "255.255.255.255/32", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16",
+];
+/// The destinations of DHCPv6 requests: the All_DHCP_Relay_Agents_and_Servers multicast address
+/// and the link-local address of the server.
+const DHCPV6_DESTINATIONS: [&str; 2] = ["ff02::1:2/128", IPV6_LINK_LOCAL_NETWORK];
+/// The destinations of neighbor discovery messages: link-local unicast and multicast addresses.
+const NEIGHBOR_DISCOVERY_DESTINATIONS: [&str; 2] = [IPV6_LINK_LOCAL_NETWORK, "ff02::/16"];
+/// Neighbor discovery messages are sent with this hop limit, so a lower one means that the
+/// message was forwarded by a router and didn't originate on the link (RFC 4861).
+const NEIGHBOR_DISCOVERY_HOP_LIMIT: u8 = 255;
+
+/// The ICMPv6 type of router solicitations.
+const ICMPV6_ROUTER_SOLICITATION: u8 = 133;
+/// The ICMPv6 type of router advertisements.
+const ICMPV6_ROUTER_ADVERTISEMENT: u8 = 134;
+/// The ICMPv6 type of neighbor solicitations.
+const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+/// The ICMPv6 type of neighbor advertisements.
+const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// The IPv4 and IPv6 multicast networks. mDNS e.g. uses `224.0.0.251` and `ff02::fb`.
+const MULTICAST_NETWORKS: [&str; 2] = ["224.0.0.0/4", "ff00::/8"];
+
+/// Parses the supplied constant networks.
+fn parse_networks(networks: &[&str]) -> Vec<IpNetwork> {
+    networks.iter()
+        .map(|n| n.parse().expect("the constant network is invalid"))
+        .collect()
+}
+
+impl From<&FirewallException> for FirewallRule {
+    /// Returns a rule that allows connections from or to the network of the exception, depending
+    /// on its direction.
+    fn from(e: &FirewallException) -> Self {
+        let rule = FirewallRule::new(e.direction, Verdict::Accept);
+        let rule = match e.direction {
+            Direction::Input => rule.source(e.network),
+            Direction::Output => rule.destination(e.network),
+        };
+        let protocol = match (e.protocol, e.network.family()) {
+            (FirewallExceptionProtocol::TCP, _) => Protocol::Tcp,
+            (FirewallExceptionProtocol::UDP, _) => Protocol::Udp,
+            (FirewallExceptionProtocol::ICMP, Family::V4) => Protocol::Icmp,
+            (FirewallExceptionProtocol::ICMP, Family::V6) => Protocol::IcmpV6,
+        };
+        let rule = rule.protocol(protocol);
+
+        match e.ports {
+            Some(ports) if protocol.has_ports() => rule.destination_ports(ports),
+            _ => rule,
+        }
+    }
+}
+
+/// Returns the rules that are applied before connecting to the VPN server:
+/// - For both directions, it will:
+///     - Accept related/established traffic
+///     - Drop invalid packets
+///     - Allow traffic on the loopback device
+/// - Allow DHCPv4, DHCPv6 and the ICMPv6 messages of the neighbor discovery protocol to local
+///   destinations, so the physical interfaces keep their addresses while connected
+/// - If `allow_lan` is set, allow connections to and from the local network. If `allow_multicast`
+///   is set as well, allow outgoing multicast traffic
+/// - Allow connections to and from the supplied exceptions. They can be used for e.g. whitelisting
+///   VPN servers or hosts in the local network
+pub(crate) fn get_pre_connect_rules(
+    exceptions: &[FirewallException],
+    options: &FirewallOptions,
+) -> Vec<FirewallRule> {
+    let mut rules = Vec::new();
+
+    for direction in [Direction::Input, Direction::Output].iter() {
+        // Related/established traffic should be allowed
+        rules.push(FirewallRule::new(*direction, Verdict::Accept)
+            .states(&[ConnectionState::Related, ConnectionState::Established]));
+
+        // Drop invalid packets
+        rules.push(FirewallRule::new(*direction, Verdict::Drop)
+            .states(&[ConnectionState::Invalid]));
+
+        // Allow traffic on loopback device
+        rules.push(FirewallRule::new(*direction, Verdict::Accept).interface(LOOPBACK_INTERFACE));
+    }
+
+    let tunnel_interface = options.tunnel_interface.as_deref().unwrap_or(DEFAULT_TUNNEL_INTERFACE);
+    rules.extend(get_link_rules(tunnel_interface));
+
+    if options.allow_lan {
+        rules.extend(get_lan_rules(options.allow_multicast, tunnel_interface));
+    }
+
+    // Add exceptions
+    rules.extend(exceptions.iter().map(FirewallRule::from));
+
+    rules
+}
+
+/// Returns the rules that are required to keep the addresses of the physical interfaces: DHCPv4,
+/// DHCPv6 and the neighbor discovery protocol, which also handles router advertisements. The
+/// physical interfaces aren't known before connecting, so the rules are restricted to the ports,
+/// message types and local destinations of these protocols instead, and they never apply to the
+/// supplied tunnel interface.
+fn get_link_rules(tunnel_interface: &str) -> Vec<FirewallRule> {
+    let link_local: IpNetwork = IPV6_LINK_LOCAL_NETWORK.parse()
+        .expect("the constant network is invalid");
+    let mut rules = Vec::new();
+
+    for destination in parse_networks(&DHCPV4_DESTINATIONS) {
+        rules.push(FirewallRule::new(Direction::Output, Verdict::Accept)
+            .excluded_interface(tunnel_interface)
+            .destination(destination)
+            .protocol(Protocol::Udp)
+            .source_ports(PortRange::single(DHCPV4_CLIENT_PORT))
+            .destination_ports(PortRange::single(DHCPV4_SERVER_PORT)));
+    }
+    // Replies can be sent by relay agents with any address, so only the ports are restricted
+    rules.push(FirewallRule::new(Direction::Input, Verdict::Accept)
+        .excluded_interface(tunnel_interface)
+        .family(Family::V4)
+        .protocol(Protocol::Udp)
+        .source_ports(PortRange::single(DHCPV4_SERVER_PORT))
+        .destination_ports(PortRange::single(DHCPV4_CLIENT_PORT)));
+
+    for destination in parse_networks(&DHCPV6_DESTINATIONS) {
+        rules.push(FirewallRule::new(Direction::Output, Verdict::Accept)
+            .excluded_interface(tunnel_interface)
+            .source(link_local)
+            .destination(destination)
+            .protocol(Protocol::Udp)
+            .source_ports(PortRange::single(DHCPV6_CLIENT_PORT))
+            .destination_ports(PortRange::single(DHCPV6_SERVER_PORT)));
+    }
+    rules.push(FirewallRule::new(Direction::Input, Verdict::Accept)
+        .excluded_interface(tunnel_interface)
+        .source(link_local)
+        .destination(link_local)
+        .protocol(Protocol::Udp)
+        .source_ports(PortRange::single(DHCPV6_SERVER_PORT))
+        .destination_ports(PortRange::single(DHCPV6_CLIENT_PORT)));
+
+    // Neighbor discovery, hosts only send router solicitations and receive router advertisements
+    let neighbor_discovery = [
+        (Direction::Output, ICMPV6_ROUTER_SOLICITATION),
+        (Direction::Input, ICMPV6_ROUTER_ADVERTISEMENT),
+        (Direction::Output, ICMPV6_NEIGHBOR_SOLICITATION),
+        (Direction::Input, ICMPV6_NEIGHBOR_SOLICITATION),
+        (Direction::Output, ICMPV6_NEIGHBOR_ADVERTISEMENT),
+        (Direction::Input, ICMPV6_NEIGHBOR_ADVERTISEMENT),
+    ];
+    for (direction, icmp_type) in neighbor_discovery.iter() {
+        for destination in parse_networks(&NEIGHBOR_DISCOVERY_DESTINATIONS) {
+            rules.push(FirewallRule::new(*direction, Verdict::Accept)
+                .excluded_interface(tunnel_interface)
+                .destination(destination)
+                .protocol(Protocol::IcmpV6)
+                .icmp_type(*icmp_type)
+                .hop_limit(NEIGHBOR_DISCOVERY_HOP_LIMIT));
+        }
+    }
+
+    rules
+}
+
+/// Returns the rules that allow connections to and from the local network and, if `multicast` is
+/// set, outgoing multicast traffic. The VPN server uses private networks as well, so the rules
+/// never apply to the supplied tunnel interface.
+fn get_lan_rules(multicast: bool, tunnel_interface: &str) -> Vec<FirewallRule> {
+    let mut rules = Vec::new();
+
+    for network in parse_networks(&LAN_NETWORKS) {
+        rules.push(FirewallRule::new(Direction::Input, Verdict::Accept)
+            .excluded_interface(tunnel_interface)
+            .source(network));
+        rules.push(FirewallRule::new(Direction::Output, Verdict::Accept)
+            .excluded_interface(tunnel_interface)
+            .destination(network));
+    }
+    if multicast {
+        for network in parse_networks(&MULTICAST_NETWORKS) {
+            rules.push(FirewallRule::new(Direction::Output, Verdict::Accept)
+                .excluded_interface(tunnel_interface)
+                .destination(network));
+        }
+    }
+
+    rules
+}
+
+/// Returns the rules that are applied after connecting to the VPN server:
+/// - Allow outgoing connections on the supplied interface
+pub(crate) fn get_post_connect_rules(interface_identifier: &str) -> Vec<FirewallRule> {
+    // TODO: Do we need to accept incoming connections on the supplied interface identifier?
+    vec![FirewallRule::new(Direction::Output, Verdict::Accept).interface(interface_identifier)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_firewall_rule_from_firewall_exception() {
+        assert_eq!(
+            FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination(IpNetwork::from_str("1.1.1.1/32").unwrap())
+                .protocol(Protocol::Udp)
+                .destination_ports(PortRange::single(1194)),
+            FirewallRule::from(&FirewallException::new(
+                "1.1.1.1".parse().unwrap(), 1194, FirewallExceptionProtocol::UDP
+            ))
+        );
+        assert_eq!(
+            FirewallRule::new(Direction::Input, Verdict::Accept)
+                .source(IpNetwork::from_str("192.168.1.0/24").unwrap())
+                .protocol(Protocol::Tcp)
+                .destination_ports(PortRange::new(8000, 8080)),
+            FirewallRule::from(&FirewallException::from_str("tcp,192.168.1.0/24,8000-8080,in").unwrap())
+        );
+        assert_eq!(
+            FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination(IpNetwork::from_str("10.0.0.0/8").unwrap())
+                .protocol(Protocol::Udp),
+            FirewallRule::from(&FirewallException::from_str("udp,10.0.0.0/8").unwrap())
+        );
+        assert_eq!(
+            FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination(IpNetwork::from_str("192.168.1.1").unwrap())
+                .protocol(Protocol::Icmp),
+            FirewallRule::from(&FirewallException::from_str("icmp,192.168.1.1").unwrap())
+        );
+        assert_eq!(
+            FirewallRule::new(Direction::Output, Verdict::Accept)
+                .destination(IpNetwork::from_str("fe80::/10").unwrap())
+                .protocol(Protocol::IcmpV6),
+            FirewallRule::from(&FirewallException::from_str("icmp,fe80::/10").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_get_pre_connect_rules() {
+        let rules = get_pre_connect_rules(&[
+            FirewallException::new("1.1.1.1".parse().unwrap(), 1337, FirewallExceptionProtocol::TCP),
+        ], &FirewallOptions::default());
+
+        let mut expected = Vec::new();
+        for direction in [Direction::Input, Direction::Output].iter() {
+            expected.push(FirewallRule::new(*direction, Verdict::Accept)
+                .states(&[ConnectionState::Related, ConnectionState::Established]));
+            expected.push(FirewallRule::new(*direction, Verdict::Drop)
+                .states(&[ConnectionState::Invalid]));
+            expected.push(FirewallRule::new(*direction, Verdict::Accept).interface("lo"));
+        }
+        expected.extend(get_link_rules("tun+"));
+        expected.push(FirewallRule::new(Direction::Output, Verdict::Accept)
+            .destination(IpNetwork::from_str("1.1.1.1/32").unwrap())
+            .protocol(Protocol::Tcp)
+            .destination_ports(PortRange::single(1337)));
+
+        assert_eq!(expected, rules);
+    }
+
+    #[test]
+    fn test_get_pre_connect_rules_blocks_new_connections() {
+        // Without exceptions, no new connection except on loopback, DHCP and neighbor discovery
+        // is accepted
+        for rule in get_pre_connect_rules(&[], &FirewallOptions::default()).iter() {
+            if rule.verdict == Verdict::Accept && rule.interface.is_none() {
+                if rule.states.is_empty() {
+                    assert!(rule.destination_ports.is_some() || rule.icmp_type.is_some());
+                } else {
+                    assert!(!rule.states.contains(&ConnectionState::New));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_pre_connect_rules_allow_lan() {
+        let default_rules = get_pre_connect_rules(&[], &FirewallOptions::default());
+        let lan_rules = get_pre_connect_rules(&[], &FirewallOptions {
+            allow_lan: true,
+            allow_multicast: false,
+            tunnel_interface: None,
+        });
+
+        // The LAN rules are added after the default rules
+        assert_eq!(default_rules[..], lan_rules[..default_rules.len()]);
+        assert_eq!(get_lan_rules(false, "tun+")[..], lan_rules[default_rules.len()..]);
+
+        // Multicast is only allowed together with the LAN
+        assert_eq!(default_rules, get_pre_connect_rules(&[], &FirewallOptions {
+            allow_lan: false,
+            allow_multicast: true,
+            tunnel_interface: None,
+        }));
+    }
+
+    #[test]
+    fn test_get_pre_connect_rules_tunnel_interface() {
+        let rules = get_pre_connect_rules(&[], &FirewallOptions {
+            tunnel_interface: Some(String::from("wg0")),
+            ..FirewallOptions::default()
+        });
+
+        // The link rules exclude the supplied tunnel interface instead of the default one
+        let excluded: Vec<&str> = rules.iter()
+            .filter_map(|r| r.excluded_interface.as_deref())
+            .collect();
+        assert_eq!(get_link_rules("wg0").len(), excluded.len());
+        assert!(excluded.iter().all(|i| *i == "wg0"));
+
+        // So do the LAN rules
+        let rules = get_pre_connect_rules(&[], &FirewallOptions {
+            allow_lan: true,
+            allow_multicast: true,
+            tunnel_interface: Some(String::from("wg0")),
+        });
+        assert!(rules.ends_with(&get_lan_rules(true, "wg0")));
+    }
+
+    #[test]
+    fn test_get_link_rules() {
+        let rules = get_link_rules("tun+");
+
+        // DHCPv4 requests are restricted to the broadcast address and local networks
+        let dhcpv4_destinations: Vec<String> = rules.iter()
+            .filter(|r| r.destination_ports == Some(PortRange::single(67)))
+            .map(|r| r.destination.unwrap().to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "255.255.255.255/32", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16",
+                "169.254.0.0/16",
+            ],
+            dhcpv4_destinations
+        );
+        assert!(rules.contains(&FirewallRule::new(Direction::Output, Verdict::Accept)
+            .excluded_interface("tun+")
+            .destination(IpNetwork::from_str("255.255.255.255/32").unwrap())
+            .protocol(Protocol::Udp)
+            .source_ports(PortRange::single(68))
+            .destination_ports(PortRange::single(67))));
+        assert!(rules.contains(&FirewallRule::new(Direction::Input, Verdict::Accept)
+            .excluded_interface("tun+")
+            .family(Family::V4)
+            .protocol(Protocol::Udp)
+            .source_ports(PortRange::single(67))
+            .destination_ports(PortRange::single(68))));
+
+        // DHCPv6 is restricted to link-local addresses and the multicast address of the servers
+        for destination in ["ff02::1:2/128", "fe80::/10"].iter() {
+            assert!(rules.contains(&FirewallRule::new(Direction::Output, Verdict::Accept)
+                .excluded_interface("tun+")
+                .source(IpNetwork::from_str("fe80::/10").unwrap())
+                .destination(IpNetwork::from_str(destination).unwrap())
+                .protocol(Protocol::Udp)
+                .source_ports(PortRange::single(546))
+                .destination_ports(PortRange::single(547))));
+        }
+        assert!(rules.contains(&FirewallRule::new(Direction::Input, Verdict::Accept)
+            .excluded_interface("tun+")
+            .source(IpNetwork::from_str("fe80::/10").unwrap())
+            .destination(IpNetwork::from_str("fe80::/10").unwrap())
+            .protocol(Protocol::Udp)
+            .source_ports(PortRange::single(547))
+            .destination_ports(PortRange::single(546))));
+
+        // Neighbor discovery and router advertisements
+        let icmpv6_types = |direction| -> Vec<u8> {
+            rules.iter()
+                .filter(|r| r.direction == direction && r.protocol == Some(Protocol::IcmpV6))
+                .map(|r| r.icmp_type.unwrap())
+                .collect()
+        };
+        assert_eq!(vec![133, 133, 135, 135, 136, 136], icmpv6_types(Direction::Output));
+        assert_eq!(vec![134, 134, 135, 135, 136, 136], icmpv6_types(Direction::Input));
+        // Only messages from the link to link-local or multicast destinations are allowed
+        for rule in rules.iter().filter(|r| r.protocol == Some(Protocol::IcmpV6)) {
+            assert_eq!(Some(255), rule.hop_limit);
+            let destination = rule.destination.unwrap().to_string();
+            assert!(destination == "fe80::/10" || destination == "ff02::/16");
+        }
+
+        // Nothing else is allowed, especially not on arbitrary ports or through the tunnel
+        assert_eq!(21, rules.len());
+        assert!(rules.iter().all(|r| r.states.is_empty() && r.interface.is_none()));
+        assert!(rules.iter().all(|r| r.excluded_interface.as_deref() == Some("tun+")));
+        assert!(rules.iter().all(|r| r.protocol.is_some()));
+        assert!(rules.iter().filter(|r| r.direction == Direction::Output)
+            .all(|r| r.destination.is_some()));
+    }
+
+    #[test]
+    fn test_get_lan_rules() {
+        let rules = get_lan_rules(false, "tun+");
+        assert_eq!(12, rules.len());
+        assert_eq!(
+            FirewallRule::new(Direction::Input, Verdict::Accept)
+                .excluded_interface("tun+")
+                .source(IpNetwork::from_str("192.168.0.0/16").unwrap()),
+            rules[4]
+        );
+        assert_eq!(
+            FirewallRule::new(Direction::Output, Verdict::Accept)
+                .excluded_interface("tun+")
+                .destination(IpNetwork::from_str("fe80::/10").unwrap()),
+            rules[11]
+        );
+        // Every rule is restricted to a network, so the internet is still blocked, and the VPN
+        // server can't reach the local network through the tunnel
+        assert!(rules.iter().all(|r| r.source.is_some() || r.destination.is_some()));
+        assert!(rules.iter().all(|r| r.excluded_interface.as_deref() == Some("tun+")));
+
+        let rules = get_lan_rules(true, "tun+");
+        assert_eq!(14, rules.len());
+        assert_eq!(
+            vec![
+                FirewallRule::new(Direction::Output, Verdict::Accept)
+                    .excluded_interface("tun+")
+                    .destination(IpNetwork::from_str("224.0.0.0/4").unwrap()),
+                FirewallRule::new(Direction::Output, Verdict::Accept)
+                    .excluded_interface("tun+")
+                    .destination(IpNetwork::from_str("ff00::/8").unwrap()),
+            ],
+            rules[12..]
+        );
+    }
+
+    #[test]
+    fn test_get_post_connect_rules() {
+        assert_eq!(
+            vec![FirewallRule::new(Direction::Output, Verdict::Accept).interface("tun1")],
+            get_post_connect_rules("tun1")
+        );
+    }
+}
@@ -0,0 +1,518 @@
+//! A backend-independent representation of firewall rules. The rules are evaluated in order, the
+//! first rule that matches a packet decides about its verdict. Packets that don't match any rule
+//! are dropped, forwarded packets are always dropped. Each firewall backend compiles these rules
+//! into its own syntax.
+
+use std::error;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// The direction of the traffic a rule applies to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// Traffic to the local system.
+    Input,
+    /// Traffic from the local system.
+    Output,
+}
+
+/// The address family a rule applies to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl From<&IpAddr> for Family {
+    fn from(address: &IpAddr) -> Self {
+        match address {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+}
+
+/// Occurs when the supplied network cannot be parsed or its prefix is too long.
+#[derive(Debug, PartialEq)]
+pub struct IpNetworkError;
+
+impl fmt::Display for IpNetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The network is invalid")
+    }
+}
+
+impl error::Error for IpNetworkError {}
+
+/// An IP network in CIDR notation, e.g. `192.168.0.0/16`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Returns a new network. Fails if the prefix is longer than the address.
+    pub fn new(address: IpAddr, prefix: u8) -> Result<IpNetwork, IpNetworkError> {
+        let max_prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max_prefix {
+            return Err(IpNetworkError);
+        }
+        Ok(IpNetwork { address, prefix })
+    }
+
+    /// Returns a network that only contains the supplied address.
+    pub fn host(address: IpAddr) -> IpNetwork {
+        let prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        IpNetwork { address, prefix }
+    }
+
+    /// Returns the address of the network.
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Returns the prefix length of the network.
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Returns the address family of the network.
+    pub fn family(&self) -> Family {
+        Family::from(&self.address)
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = IpNetworkError;
+
+    /// Parses a network in CIDR notation. An address without a prefix is parsed as a network that
+    /// only contains this address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((address, prefix)) => IpNetwork::new(
+                address.parse().map_err(|_| IpNetworkError)?,
+                prefix.parse().map_err(|_| IpNetworkError)?,
+            ),
+            None => Ok(IpNetwork::host(s.parse().map_err(|_| IpNetworkError)?)),
+        }
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// A protocol a rule can match.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    /// Restricts the rule to IPv4.
+    Icmp,
+    /// Restricts the rule to IPv6.
+    IcmpV6,
+}
+
+impl Protocol {
+    /// Returns whether the protocol has ports.
+    pub fn has_ports(&self) -> bool {
+        matches!(self, Protocol::Tcp | Protocol::Udp)
+    }
+
+    /// Returns the address family the protocol is restricted to, if any.
+    pub fn family(&self) -> Option<Family> {
+        match self {
+            Protocol::Icmp => Some(Family::V4),
+            Protocol::IcmpV6 => Some(Family::V6),
+            _ => None,
+        }
+    }
+}
+
+/// Occurs when the supplied port range cannot be parsed.
+#[derive(Debug, PartialEq)]
+pub struct PortRangeError;
+
+impl fmt::Display for PortRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The port range is invalid")
+    }
+}
+
+impl error::Error for PortRangeError {}
+
+/// An inclusive range of ports.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PortRange {
+    start: u16,
+    end: u16,
+}
+
+impl PortRange {
+    /// Returns a new range from `start` to `end`. The bounds are swapped if `start` is greater
+    /// than `end`.
+    pub fn new(start: u16, end: u16) -> PortRange {
+        PortRange {
+            start: start.min(end),
+            end: start.max(end),
+        }
+    }
+
+    /// Returns a range that only contains the supplied port.
+    pub fn single(port: u16) -> PortRange {
+        PortRange::new(port, port)
+    }
+
+    /// Returns the first port of the range.
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    /// Returns the last port of the range.
+    pub fn end(&self) -> u16 {
+        self.end
+    }
+
+    /// Returns whether the range only contains a single port.
+    pub fn is_single(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = PortRangeError;
+
+    /// Parses a single port, e.g. `443`, or a range of ports, e.g. `1000-2000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((start, end)) => Ok(PortRange::new(
+                start.parse().map_err(|_| PortRangeError)?,
+                end.parse().map_err(|_| PortRangeError)?,
+            )),
+            None => Ok(PortRange::single(s.parse().map_err(|_| PortRangeError)?)),
+        }
+    }
+}
+
+/// The state of a connection as tracked by the firewall.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConnectionState {
+    New,
+    Established,
+    Related,
+    Invalid,
+    Untracked,
+}
+
+/// What happens with a packet that matches a rule.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Verdict {
+    Accept,
+    Drop,
+}
+
+/// A single firewall rule. All criteria that are set have to match for the verdict to apply.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FirewallRule {
+    pub direction: Direction,
+    /// Restricts the rule to an address family. Rules with an address are restricted to its family
+    /// automatically.
+    pub family: Option<Family>,
+    /// The incoming interface for `Input` and the outgoing interface for `Output` rules. A trailing
+    /// `+` matches all interfaces whose name starts with the rest.
+    pub interface: Option<String>,
+    /// An interface the rule doesn't apply to, in the same direction and with the same wildcard as
+    /// `interface`.
+    pub excluded_interface: Option<String>,
+    pub source: Option<IpNetwork>,
+    pub destination: Option<IpNetwork>,
+    pub protocol: Option<Protocol>,
+    /// Only valid together with a protocol that has ports.
+    pub source_ports: Option<PortRange>,
+    /// Only valid together with a protocol that has ports.
+    pub destination_ports: Option<PortRange>,
+    /// Only valid together with `Icmp` or `IcmpV6`.
+    pub icmp_type: Option<u8>,
+    /// Matches the hop limit of IPv6 packets exactly. Only valid for IPv6 rules.
+    pub hop_limit: Option<u8>,
+    /// Matches if the connection is in any of the states. Matches all states if empty.
+    pub states: Vec<ConnectionState>,
+    pub verdict: Verdict,
+}
+
+impl FirewallRule {
+    /// Returns a new rule that matches all traffic in the supplied direction.
+    pub fn new(direction: Direction, verdict: Verdict) -> FirewallRule {
+        FirewallRule {
+            direction,
+            family: None,
+            interface: None,
+            excluded_interface: None,
+            source: None,
+            destination: None,
+            protocol: None,
+            source_ports: None,
+            destination_ports: None,
+            icmp_type: None,
+            hop_limit: None,
+            states: Vec::new(),
+            verdict,
+        }
+    }
+
+    /// Restricts the rule to the supplied address family.
+    pub fn family(mut self, family: Family) -> FirewallRule {
+        self.family = Some(family);
+        self
+    }
+
+    /// Restricts the rule to the supplied interface.
+    pub fn interface(mut self, interface: &str) -> FirewallRule {
+        self.interface = Some(interface.to_string());
+        self
+    }
+
+    /// Excludes the supplied interface from the rule.
+    pub fn excluded_interface(mut self, interface: &str) -> FirewallRule {
+        self.excluded_interface = Some(interface.to_string());
+        self
+    }
+
+    /// Restricts the rule to the supplied source network.
+    pub fn source(mut self, source: IpNetwork) -> FirewallRule {
+        self.source = Some(source);
+        self
+    }
+
+    /// Restricts the rule to the supplied destination network.
+    pub fn destination(mut self, destination: IpNetwork) -> FirewallRule {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Restricts the rule to the supplied protocol.
+    pub fn protocol(mut self, protocol: Protocol) -> FirewallRule {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Restricts the rule to the supplied source ports.
+    pub fn source_ports(mut self, ports: PortRange) -> FirewallRule {
+        self.source_ports = Some(ports);
+        self
+    }
+
+    /// Restricts the rule to the supplied destination ports.
+    pub fn destination_ports(mut self, ports: PortRange) -> FirewallRule {
+        self.destination_ports = Some(ports);
+        self
+    }
+
+    /// Restricts the rule to the supplied ICMP or ICMPv6 message type.
+    pub fn icmp_type(mut self, icmp_type: u8) -> FirewallRule {
+        self.icmp_type = Some(icmp_type);
+        self
+    }
+
+    /// Restricts the rule to IPv6 packets with the supplied hop limit.
+    pub fn hop_limit(mut self, hop_limit: u8) -> FirewallRule {
+        self.hop_limit = Some(hop_limit);
+        self
+    }
+
+    /// Restricts the rule to connections in the supplied states.
+    pub fn states(mut self, states: &[ConnectionState]) -> FirewallRule {
+        self.states = states.to_vec();
+        self
+    }
+
+    /// Returns the address family the rule is restricted to, either explicitly, by one of its
+    /// addresses or by its protocol. Returns `None` if the rule applies to both families.
+    pub fn get_family(&self) -> Option<Family> {
+        self.family
+            .or_else(|| self.source.map(|n| n.family()))
+            .or_else(|| self.destination.map(|n| n.family()))
+            .or_else(|| self.protocol.and_then(|p| p.family()))
+    }
+
+    /// Returns whether the rule applies to traffic of the supplied address family.
+    pub fn applies_to(&self, family: Family) -> bool {
+        self.get_family().is_none_or(|f| f == family)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_ip_network_new() {
+        assert_eq!(
+            Ok(IpNetwork {
+                address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+                prefix: 8,
+            }),
+            IpNetwork::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8)
+        );
+        assert_eq!(
+            Err(IpNetworkError),
+            IpNetwork::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 33)
+        );
+        assert!(IpNetwork::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 128).is_ok());
+        assert_eq!(
+            Err(IpNetworkError),
+            IpNetwork::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 129)
+        );
+    }
+
+    #[test]
+    fn test_ip_network_host() {
+        assert_eq!(32, IpNetwork::host(IpAddr::V4(Ipv4Addr::LOCALHOST)).prefix());
+        assert_eq!(128, IpNetwork::host(IpAddr::V6(Ipv6Addr::LOCALHOST)).prefix());
+    }
+
+    #[test]
+    fn test_ip_network_from_str() {
+        assert_eq!(
+            IpNetwork::new("192.168.0.0".parse().unwrap(), 16).unwrap(),
+            IpNetwork::from_str("192.168.0.0/16").unwrap()
+        );
+        assert_eq!(
+            IpNetwork::host("1.1.1.1".parse().unwrap()),
+            IpNetwork::from_str("1.1.1.1").unwrap()
+        );
+        assert_eq!(
+            IpNetwork::new("fe80::".parse().unwrap(), 10).unwrap(),
+            IpNetwork::from_str("fe80::/10").unwrap()
+        );
+
+        assert_eq!(Err(IpNetworkError), IpNetwork::from_str("1.1.1.1/33"));
+        assert_eq!(Err(IpNetworkError), IpNetwork::from_str("1.1.1.1/"));
+        assert_eq!(Err(IpNetworkError), IpNetwork::from_str("1.1.1/8"));
+        assert_eq!(Err(IpNetworkError), IpNetwork::from_str("localhost"));
+    }
+
+    #[test]
+    fn test_ip_network_display() {
+        assert_eq!("10.0.0.0/8", IpNetwork::from_str("10.0.0.0/8").unwrap().to_string());
+        assert_eq!(
+            "2001:db8::1/128",
+            IpNetwork::from_str("2001:0db8::0001").unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_ip_network_error_format() {
+        assert_eq!("The network is invalid", format!("{}", IpNetworkError));
+    }
+
+    #[test]
+    fn test_port_range() {
+        assert_eq!(PortRange { start: 20, end: 80 }, PortRange::new(20, 80));
+        assert_eq!(PortRange { start: 20, end: 80 }, PortRange::new(80, 20));
+        assert_eq!(PortRange { start: 443, end: 443 }, PortRange::single(443));
+        assert!(PortRange::single(443).is_single());
+        assert!(!PortRange::new(20, 80).is_single());
+        assert_eq!(20, PortRange::new(20, 80).start());
+        assert_eq!(80, PortRange::new(20, 80).end());
+    }
+
+    #[test]
+    fn test_port_range_from_str() {
+        assert_eq!(PortRange::single(443), PortRange::from_str("443").unwrap());
+        assert_eq!(PortRange::new(1000, 2000), PortRange::from_str("1000-2000").unwrap());
+        assert_eq!(PortRange::new(1000, 2000), PortRange::from_str("2000-1000").unwrap());
+
+        assert_eq!(Err(PortRangeError), PortRange::from_str(""));
+        assert_eq!(Err(PortRangeError), PortRange::from_str("1000-"));
+        assert_eq!(Err(PortRangeError), PortRange::from_str("65536"));
+        assert_eq!(Err(PortRangeError), PortRange::from_str("any"));
+    }
+
+    #[test]
+    fn test_port_range_error_format() {
+        assert_eq!("The port range is invalid", format!("{}", PortRangeError));
+    }
+
+    #[test]
+    fn test_protocol() {
+        assert!(Protocol::Tcp.has_ports());
+        assert!(Protocol::Udp.has_ports());
+        assert!(!Protocol::Icmp.has_ports());
+        assert!(!Protocol::IcmpV6.has_ports());
+
+        assert_eq!(None, Protocol::Tcp.family());
+        assert_eq!(Some(Family::V4), Protocol::Icmp.family());
+        assert_eq!(Some(Family::V6), Protocol::IcmpV6.family());
+    }
+
+    #[test]
+    fn test_firewall_rule_builder() {
+        let rule = FirewallRule::new(Direction::Output, Verdict::Accept)
+            .interface("eth0")
+            .excluded_interface("tun+")
+            .destination(IpNetwork::from_str("10.0.0.0/8").unwrap())
+            .protocol(Protocol::Tcp)
+            .source_ports(PortRange::single(1000))
+            .destination_ports(PortRange::new(20, 22))
+            .hop_limit(255)
+            .states(&[ConnectionState::New]);
+
+        assert_eq!(FirewallRule {
+            direction: Direction::Output,
+            family: None,
+            interface: Some(String::from("eth0")),
+            excluded_interface: Some(String::from("tun+")),
+            source: None,
+            destination: Some(IpNetwork::from_str("10.0.0.0/8").unwrap()),
+            protocol: Some(Protocol::Tcp),
+            source_ports: Some(PortRange::single(1000)),
+            destination_ports: Some(PortRange::new(20, 22)),
+            icmp_type: None,
+            hop_limit: Some(255),
+            states: vec![ConnectionState::New],
+            verdict: Verdict::Accept,
+        }, rule);
+    }
+
+    #[test]
+    fn test_firewall_rule_family() {
+        let rule = FirewallRule::new(Direction::Input, Verdict::Drop);
+        assert_eq!(None, rule.get_family());
+        assert!(rule.applies_to(Family::V4));
+        assert!(rule.applies_to(Family::V6));
+
+        let rule = FirewallRule::new(Direction::Input, Verdict::Drop).family(Family::V6);
+        assert_eq!(Some(Family::V6), rule.get_family());
+        assert!(!rule.applies_to(Family::V4));
+
+        let rule = FirewallRule::new(Direction::Input, Verdict::Drop)
+            .source(IpNetwork::from_str("10.0.0.0/8").unwrap());
+        assert_eq!(Some(Family::V4), rule.get_family());
+        assert!(!rule.applies_to(Family::V6));
+
+        let rule = FirewallRule::new(Direction::Output, Verdict::Drop)
+            .destination(IpNetwork::from_str("::1").unwrap());
+        assert_eq!(Some(Family::V6), rule.get_family());
+
+        let rule = FirewallRule::new(Direction::Output, Verdict::Accept).protocol(Protocol::Icmp);
+        assert_eq!(Some(Family::V4), rule.get_family());
+
+        let rule = FirewallRule::new(Direction::Input, Verdict::Accept)
+            .protocol(Protocol::IcmpV6)
+            .icmp_type(134);
+        assert_eq!(Some(134), rule.icmp_type);
+        assert_eq!(Some(Family::V6), rule.get_family());
+    }
+}
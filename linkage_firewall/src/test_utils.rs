@@ -12,3 +12,29 @@ macro_rules! expect_execute {
             .returning(|_| $returns);
     }};
 }
+
+
+/// Expects a save for the supplied identifier 1 time and returns the supplied ruleset.
+#[macro_export]
+macro_rules! expect_save {
+    ( $m:ident, $ruleset:expr ) => {{
+        $m.expect_save()
+            .times(1)
+            .returning(|| Ok(String::from($ruleset)));
+    }};
+}
+
+/// Expects a restore of the supplied ruleset for the supplied identifier 1 time. Returns `Ok(())` by
+/// default but that can be customized using another parameter.
+#[macro_export]
+macro_rules! expect_restore {
+    ( $m:ident, $ruleset:expr, $flush:expr ) => {
+        expect_restore!($m, $ruleset, $flush, Ok(()));
+    };
+    ( $m:ident, $ruleset:expr, $flush:expr, $returns:expr ) => {{
+        $m.expect_restore()
+            .times(1)
+            .with(eq(String::from($ruleset)), eq($flush))
+            .returning(|_, _| $returns);
+    }};
+}
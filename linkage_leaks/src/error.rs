@@ -2,9 +2,8 @@ use thiserror::Error;
 use reqwest::Error as ReqwestError;
 use std::io::Error as IoError;
 use serde_json::Error as SerdeJsonError;
-use std::any::Any;
-use std::sync::mpsc::RecvError;
 use std::net::AddrParseError;
+use tokio::task::JoinError;
 
 pub(crate) type LeakResult<T> = Result<T, LeakError>;
 
@@ -16,10 +15,20 @@ pub enum LeakError {
     IoError(#[from] IoError),
     #[error("cannot parse json: {0}")]
     SerdeJsonError(#[from] SerdeJsonError),
-    #[error("error while joining threads")]
-    JoiningThreadsError(Box<dyn Any + Send>),
-    #[error("recv error occurred: {0}")]
-    RecvError(#[from] RecvError),
     #[error("cannot parse address: {0}")]
     AddrParseError(#[from] AddrParseError),
+    #[error("unknown leak provider {0}")]
+    UnknownProvider(String),
+    #[error("neither the IPv4 nor the IPv6 site can be reached")]
+    NoConnectivity,
+    #[error("a request was aborted: {0}")]
+    JoinError(#[from] JoinError),
+    #[error("only {successes} of {requests} DNS leak test requests succeeded, at least \
+             {min_successes} are required: {reason}")]
+    TooFewDnsResults {
+        successes: u32,
+        requests: u32,
+        min_successes: u32,
+        reason: String,
+    },
 }
@@ -0,0 +1,220 @@
+//! The HTTP client that the leak tests use and how it's configured.
+
+use crate::error::{LeakError, LeakResult};
+use reqwest::{Certificate, Client};
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time;
+
+/// The seconds after which a request fails by default.
+const DEFAULT_TIMEOUT: u64 = 10;
+/// How often a failed request is retried by default.
+const DEFAULT_RETRIES: u32 = 2;
+/// The milliseconds before the first retry by default, which are doubled after every retry.
+const DEFAULT_RETRY_DELAY: u64 = 500;
+
+/// Configures the requests of the leak tests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeakCheckConfig {
+    /// The time after which a single request fails.
+    pub timeout: Duration,
+    /// How often a request is retried after it failed.
+    pub retries: u32,
+    /// The delay before the first retry, which is doubled after every retry.
+    pub retry_delay: Duration,
+    /// The `User-Agent` header of the requests.
+    pub user_agent: String,
+    /// The local address that the requests are sent from. Only sites that are reachable using its
+    /// address family can be reached then.
+    pub local_address: Option<IpAddr>,
+    /// PEM files with additional root certificates, e.g. of a proxy that intercepts TLS.
+    pub ca_certificates: Vec<PathBuf>,
+}
+
+impl Default for LeakCheckConfig {
+    fn default() -> LeakCheckConfig {
+        LeakCheckConfig {
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+            retries: DEFAULT_RETRIES,
+            retry_delay: Duration::from_millis(DEFAULT_RETRY_DELAY),
+            user_agent: format!("linkage/{}", env!("CARGO_PKG_VERSION")),
+            local_address: None,
+            ca_certificates: Vec::new(),
+        }
+    }
+}
+
+/// Sends the requests of the leak tests as configured. It's bound to the runtime it's used on
+/// first, so a new one is created for every test.
+#[derive(Clone)]
+pub(crate) struct HttpClient {
+    client: Client,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl HttpClient {
+    /// Returns a new instance of HttpClient that uses the supplied configuration.
+    pub fn new(config: &LeakCheckConfig) -> LeakResult<HttpClient> {
+        let mut builder = Client::builder()
+            .timeout(config.timeout)
+            .user_agent(&config.user_agent)
+            .local_address(config.local_address);
+        for path in &config.ca_certificates {
+            for certificate in Certificate::from_pem_bundle(&fs::read(path)?)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(HttpClient {
+            client: builder.build()?,
+            retries: config.retries,
+            retry_delay: config.retry_delay,
+        })
+    }
+
+    /// Returns the response body of a given url.
+    pub async fn get_body(&self, url: &str) -> LeakResult<String> {
+        self.get_with_retries(url, |_| true).await
+    }
+
+    /// Returns the response body of a given url, or `None` if the site can't be connected to, e.g.
+    /// because the system has no IPv6 connectivity. Connecting isn't retried for the same reason.
+    pub async fn get_optional_body(&self, url: &str) -> LeakResult<Option<String>> {
+        match self.get_with_retries(url, |e| !is_connect_error(e)).await {
+            Err(e) if is_connect_error(&e) => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    /// Requests the supplied url and retries errors that the supplied function accepts.
+    async fn get_with_retries<F>(&self, url: &str, retry: F) -> LeakResult<String>
+    where
+        F: Fn(&LeakError) -> bool,
+    {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            match self.get(url).await {
+                Err(e) if attempt < self.retries && retry(&e) => {
+                    time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Requests the supplied url once. The body of error responses is returned as well.
+    async fn get(&self, url: &str) -> LeakResult<String> {
+        Ok(self.client.get(url).send().await?.text().await?)
+    }
+}
+
+/// Returns whether the supplied error means that the site couldn't be connected to.
+fn is_connect_error(error: &LeakError) -> bool {
+    matches!(error, LeakError::RequestError(e) if e.is_connect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_on;
+    use crate::test_support::{MockLeakServer, MockResponses};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    /// The URL of a site that can't be connected to.
+    const UNREACHABLE_URL: &str = "http://127.0.0.1:0/";
+
+    /// Returns a configuration that retries the supplied amount of times without waiting long.
+    fn config(retries: u32) -> LeakCheckConfig {
+        LeakCheckConfig {
+            retries,
+            retry_delay: Duration::from_millis(10),
+            ..LeakCheckConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_get_body() -> LeakResult<()> {
+        let server = MockLeakServer::start(MockResponses::default())?;
+        let client = HttpClient::new(&LeakCheckConfig::default())?;
+        block_on(async {
+            let body = client.get_body(&server.url("/ipv4/json/")).await?;
+            assert!(body.contains(r#""ip":"1.1.1.1""#));
+            // The body of error responses is returned as well
+            assert_eq!("", client.get_body(&server.url("/unknown")).await?);
+            assert_eq!(None, client.get_optional_body(UNREACHABLE_URL).await?);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_get_body_retries() -> LeakResult<()> {
+        // Closes the first connection without a response and answers the second one
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let server = thread::spawn(move || {
+            for (i, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                if i == 1 {
+                    let response = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+                    stream.write_all(response.as_bytes()).unwrap();
+                }
+            }
+        });
+
+        assert_eq!("ok", block_on(HttpClient::new(&config(1))?.get_body(&url))?);
+        server.join().unwrap();
+
+        // Waits 10 and 20 milliseconds before retrying
+        let start = Instant::now();
+        let result = block_on(HttpClient::new(&config(2))?.get_body(UNREACHABLE_URL));
+        assert!(is_connect_error(&result.unwrap_err()));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // Sites that can't be connected to aren't retried if that's expected
+        let start = Instant::now();
+        let client = HttpClient::new(&LeakCheckConfig::default())?;
+        assert_eq!(None, block_on(client.get_optional_body(UNREACHABLE_URL))?);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_body_timeout() -> LeakResult<()> {
+        // Connections are accepted by the system, but never answered
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let client = HttpClient::new(&LeakCheckConfig {
+            timeout: Duration::from_millis(50),
+            ..config(0)
+        })?;
+
+        match block_on(client.get_body(&url)) {
+            Err(LeakError::RequestError(e)) => assert!(e.is_timeout()),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_client_new() {
+        let missing_certificate = LeakCheckConfig {
+            ca_certificates: vec![PathBuf::from("/nonexistent/linkage-ca.pem")],
+            ..LeakCheckConfig::default()
+        };
+        assert!(matches!(HttpClient::new(&missing_certificate), Err(LeakError::IoError(_))));
+    }
+}
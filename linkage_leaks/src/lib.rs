@@ -1,182 +1,312 @@
-//! Can check the current DNS servers as well as the outgoing ip address of the system.
+//! Can check the current DNS servers as well as the outgoing ip address of the system, and audit
+//! the resolver configuration.
 
 mod error;
+mod http;
+pub mod nonblocking;
+mod providers;
+mod report;
+mod resolver;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 
 use error::LeakResult;
+use http::HttpClient;
 use lazy_static::lazy_static;
 use random_string::{Charset, GenerationResult, RandomString};
 use serde::Deserialize;
-use serde_json;
-use std::io::Read;
+use std::future::Future;
 use std::net::IpAddr;
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::thread;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{self, Instant};
 
 pub use error::LeakError;
+pub use http::LeakCheckConfig;
+pub use providers::{
+    get_provider, EchoProvider, IpleakProvider, LeakFuture, LeakProvider, MullvadProvider,
+};
+pub use report::{LeakReport, LeakTestResults};
+pub use resolver::{
+    Nameserver, NameserverSource, ResolverAudit, ResolverFinding, TunnelInterface,
+};
 
-/// The site used for DNS leak checking. Contains a formatting parameter for a prefix.
-const LEAK_DETECT_DNS_SITE: &str = "https://{}.ipleak.net/dnsdetect/";
 /// A set of chars that can be used for generating prefixes for the DNS leak check.
 const LEAK_DETECT_DNS_PREFIX_CHARSET: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
 /// The length of the prefix for DNS leak detection.
 const LEAK_DETECT_DNS_PREFIX_LENGTH: i32 = 40;
-/// How many requests per thread will be executed to check for DNS leaks.
-const LEAK_DETECT_REQUESTS_PER_THREAD: u32 = 5;
-/// The site used for IPv4 leak checking.
-const LEAK_DETECT_IPV4_SITE: &str = "https://ipv4.ipleak.net/json/";
-/// The site used for IPv6 leak checking.
-const LEAK_DETECT_IPV6_SITE: &str = "https://ipv6.ipleak.net/json/";
-
-/// Returns the response body of a given url.
-fn get_body(url: &str) -> LeakResult<String> {
-    let mut response = reqwest::blocking::get(url)?;
-    let mut body = String::new();
-    response.read_to_string(&mut body)?;
-    Ok(body)
+/// How many requests of the DNS leak test run at the same time by default.
+const DNS_TEST_CONCURRENCY: usize = 10;
+/// The seconds after which the DNS leak test returns by default.
+const DNS_TEST_DEADLINE: u64 = 30;
+
+/// Runs the supplied future to completion on a new single-threaded runtime. This is how the
+/// blocking functions wrap the async ones, so it must not be called from within a runtime.
+pub(crate) fn block_on<T>(future: impl Future<Output = LeakResult<T>>) -> LeakResult<T> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(future)
+}
+
+/// Contains information about a public ip address. Providers that don't report a field leave it
+/// empty.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AddressInformation {
+    pub ip: IpAddr,
+    pub country_code: Option<String>,
+    pub region_code: Option<String>,
+    pub continent_code: Option<String>,
+    pub city_name: Option<String>,
 }
 
-/// Contains information about an ip address.
-#[derive(Deserialize, Debug)]
+impl AddressInformation {
+    /// Returns a new instance of AddressInformation without any information besides the address.
+    pub fn new(ip: IpAddr) -> AddressInformation {
+        AddressInformation {
+            ip,
+            country_code: None,
+            region_code: None,
+            continent_code: None,
+            city_name: None,
+        }
+    }
+}
+
+/// Contains information about the public IPv4 and IPv6 address of the system. An address is `None`
+/// if the system has no connectivity using that address family.
+#[derive(Debug, Clone, PartialEq)]
 pub struct IpInformation {
-    pub country_code: String,
-    pub region_code: String,
-    pub continent_code: String,
-    pub city_name: String,
-    pub ip: String,
-    pub ipv6: Option<String>,
+    pub ipv4: Option<AddressInformation>,
+    pub ipv6: Option<AddressInformation>,
+}
+
+impl IpInformation {
+    /// Returns a new instance of IpInformation with the supplied addresses. Fails if there is
+    /// neither an IPv4 nor an IPv6 address, since the system isn't connected at all then.
+    pub(crate) fn new(
+        ipv4: Option<AddressInformation>,
+        ipv6: Option<AddressInformation>,
+    ) -> LeakResult<IpInformation> {
+        if ipv4.is_none() && ipv6.is_none() {
+            return Err(LeakError::NoConnectivity);
+        }
+
+        Ok(IpInformation { ipv4, ipv6 })
+    }
 }
 
 /// Requests infos from a site that returns them in json format, parses them afterwards and then
-/// turns them into an instance of IpInformation.
+/// turns them into an instance of IpInformation. Uses ipleak.net.
 pub fn get_ip_information() -> LeakResult<IpInformation> {
-    let ipv4 = get_body(LEAK_DETECT_IPV4_SITE)?;
-    let ipv6 = get_body(LEAK_DETECT_IPV6_SITE)?;
-    let mut infos: IpInformation = serde_json::from_str(&ipv4)?;
-    let infos_ipv6: IpInformation = serde_json::from_str(&ipv6)?;
-    // TODO: Return two separate instances of the struct or similar, but save both IPv4 and IPv6
-    //       information.
-    infos.ipv6 = Some(infos_ipv6.ip);
-    Ok(infos)
+    block_on(nonblocking::ip_information())
 }
 
-/// Options related to the execution of the dns leak test.
-#[derive(Debug, PartialEq)]
-struct DnsTestConfig {
-    total: u32,
-    requests_per_thread: u32,
-    amount_of_threads: u32,
+/// Returns a list of all detected DNS servers. The supplied argument amount_of_requests determines
+/// how often the test will be run. Uses ipleak.net.
+pub fn dns_test(amount_of_requests: u32) -> LeakResult<Vec<IpAddr>> {
+    block_on(nonblocking::dns_test(amount_of_requests))
 }
 
-/// Returns the configuration that should be used to execute the dns leak test. This mainly consists
-/// of data related to the amount of threads and the requests that should be executed in each
-/// thread.
-fn get_dns_leak_test_config(mut amount_of_requests: u32) -> DnsTestConfig {
-    // Not mutable because it should be equal to the constant
-    let requests_per_thread: u32 = LEAK_DETECT_REQUESTS_PER_THREAD;
-
-    // If the amount of requests that should be executed is a multiple of requests_per_thread we can
-    // safely use the provided value
-    if amount_of_requests % requests_per_thread != 0 {
-        // If the amount is not a multiple of the value, we have to get the next number of the
-        // amount that's dividable by the requests per thread so we can have a clean amount of
-        // threads and as a result of that, we won't get any strange behaviour when using weird
-        // numbers as amount
-        amount_of_requests = {
-            let mut t: u32 = 0;
-            while t < amount_of_requests {
-                t += requests_per_thread;
-            }
-            t
-        };
+/// Options of the DNS leak test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsTestOptions {
+    /// How many requests are made.
+    pub requests: u32,
+    /// How many requests run at the same time.
+    pub concurrency: usize,
+    /// The time after which the test returns with the requests that finished so far.
+    pub deadline: Duration,
+    /// How many requests have to succeed for the result to be trusted.
+    pub min_successes: u32,
+}
+
+impl DnsTestOptions {
+    /// Returns a new instance of DnsTestOptions that makes the supplied amount of requests, of
+    /// which half have to succeed within the default deadline.
+    pub fn new(requests: u32) -> DnsTestOptions {
+        DnsTestOptions {
+            requests,
+            concurrency: DNS_TEST_CONCURRENCY,
+            deadline: Duration::from_secs(DNS_TEST_DEADLINE),
+            min_successes: requests.div_ceil(2),
+        }
     }
-    let amount_of_threads: u32 = amount_of_requests / requests_per_thread;
+}
 
-    return DnsTestConfig {
-        total: amount_of_requests,
-        requests_per_thread,
-        amount_of_threads,
-    };
+/// The result of the DNS leak test.
+#[derive(Debug, Default)]
+pub struct DnsTestResult {
+    /// The detected DNS servers without duplicates.
+    pub servers: Vec<IpAddr>,
+    /// How many requests were made.
+    pub requests: u32,
+    /// How many requests succeeded.
+    pub successes: u32,
+    /// The errors of the requests that failed. Requests that didn't finish before the deadline are
+    /// neither successes nor errors.
+    pub errors: Vec<LeakError>,
 }
 
-/// Returns a list of all detected DNS servers. The supplied argument amount_of_requests determines
-/// how often the test will be run.
-pub fn dns_test(amount_of_requests: u32) -> LeakResult<Vec<IpAddr>> {
-    let config = get_dns_leak_test_config(amount_of_requests);
-
-    // Make a channel to receive the ip addresses
-    let (tx, rx): (Sender<_>, Receiver<_>) = mpsc::channel::<String>();
-    let mut children = Vec::new();
-
-    for _ in 0..config.amount_of_threads {
-        let thread_tx = tx.clone();
-        let per_thread = config.requests_per_thread.clone();
-
-        // Spawn the threads and save their handles into a Vec
-        let child = thread::spawn(move || {
-            // Each thread should do a specific amount of requests per thread
-            for _ in 0..per_thread {
-                // TODO: Improve the error handling for this calls
-                let dns = get_dns().expect("cannot get dns server for dns leak detection");
-                // We use .except here since this will probably not happen because we calculate this
-                thread_tx
-                    .send(dns)
-                    .expect("cannot transmit data through created channel for dns leak detection");
-            }
-        });
-        children.push(child);
+impl DnsTestResult {
+    /// Returns the share of requests that succeeded, between 0 and 1.
+    pub fn success_ratio(&self) -> f64 {
+        f64::from(self.successes) / f64::from(self.requests.max(1))
     }
+}
+
+/// Requests a URL that the supplied function returns as often as the options determine and parses
+/// every response with the supplied function. Failed requests don't stop the test, but fewer
+/// successful requests than the minimum fail it.
+pub(crate) async fn run_dns_test<U>(
+    client: &HttpClient,
+    options: &DnsTestOptions,
+    request_url: U,
+    parse: fn(&str) -> LeakResult<Vec<IpAddr>>,
+) -> LeakResult<DnsTestResult>
+where
+    U: Fn() -> String,
+{
+    let deadline = Instant::now() + options.deadline;
+    let permits = Arc::new(Semaphore::new(options.concurrency.max(1)));
 
-    // Save the collected ip addresses into a vec
-    let mut ips: Vec<IpAddr> = Vec::new();
-    for _ in 0..config.total {
-        ips.push(rx.recv()?.parse()?);
+    let mut requests = JoinSet::new();
+    for _ in 0..options.requests {
+        let (client, permits, url) = (client.clone(), permits.clone(), request_url());
+        requests.spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("the semaphore is never closed");
+            parse(&client.get_body(&url).await?)
+        });
     }
 
-    // Wait for the threads to finish
-    for child in children {
-        child
-            .join()
-            .map_err(|e| LeakError::JoiningThreadsError(e))?;
+    let mut result = DnsTestResult {
+        requests: options.requests,
+        ..DnsTestResult::default()
+    };
+    // The requests that are still running after the deadline are aborted when the set is dropped
+    while let Ok(Some(request)) = time::timeout_at(deadline, requests.join_next()).await {
+        match request {
+            Ok(Ok(servers)) => {
+                result.successes += 1;
+                result.servers.extend(servers);
+            }
+            Ok(Err(e)) => result.errors.push(e),
+            Err(e) => result.errors.push(e.into()),
+        }
     }
 
     // Sort and deduplicate the ip addresses
-    ips.sort();
-    ips.dedup();
-
-    Ok(ips)
-}
+    result.servers.sort();
+    result.servers.dedup();
 
-/// Gets the DNS server from the site used for DNS leak detection.
-fn get_dns() -> LeakResult<String> {
-    let prefix = generate_dns_leak_prefix();
-    // TODO: Use constant
-    let request_url = format!("https://{}.ipleak.net/dnsdetect/", prefix);
-
-    let resp = reqwest::blocking::get(&request_url)?;
-    let body = resp.text()?;
+    if result.successes < options.min_successes {
+        return Err(LeakError::TooFewDnsResults {
+            successes: result.successes,
+            requests: result.requests,
+            min_successes: options.min_successes,
+            reason: match result.errors.first() {
+                Some(e) => e.to_string(),
+                None => String::from("the deadline elapsed"),
+            },
+        });
+    }
 
-    Ok(body.trim().to_owned())
+    Ok(result)
 }
 
 /// Returns a prefix that can be used for DNS leak detection.
-fn generate_dns_leak_prefix() -> String {
+pub(crate) fn generate_dns_leak_prefix() -> String {
     lazy_static! {
         static ref LETTERS: Vec<char> = Charset::from_str(LEAK_DETECT_DNS_PREFIX_CHARSET);
     }
-    let prefix: GenerationResult = RandomString::generate(LEAK_DETECT_DNS_PREFIX_LENGTH, &*LETTERS);
+    let prefix: GenerationResult = RandomString::generate(LEAK_DETECT_DNS_PREFIX_LENGTH, &LETTERS);
     prefix.to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::str::FromStr;
+    use crate::test_support::{MockLeakServer, MockResponses};
+    use std::net::TcpListener;
 
     #[test]
-    fn test_get_body() {
-        let body = get_body("https://am.i.mullvad.net/ip");
-        IpAddr::from_str(body.unwrap().as_str());
+    fn test_ip_information_new() -> LeakResult<()> {
+        let ipv6 = AddressInformation::new("2001:db8::1".parse()?);
+        assert_eq!(Some(ipv6.clone()), IpInformation::new(None, Some(ipv6))?.ipv6);
+        assert!(matches!(IpInformation::new(None, None), Err(LeakError::NoConnectivity)));
+
+        Ok(())
     }
+
+    /// Parses the response of the DNS site of the mock server.
+    fn parse_dns_servers(body: &str) -> LeakResult<Vec<IpAddr>> {
+        Ok(vec![body.parse()?])
+    }
+
+    #[test]
+    fn test_run_dns_test() -> LeakResult<()> {
+        let server = MockLeakServer::start(MockResponses {
+            dns_servers: vec!["9.9.9.9".parse()?, "8.8.8.8".parse()?],
+            failing_dns_requests: 5,
+            ..MockResponses::default()
+        })?;
+        let url = || server.url("/prefix/dnsdetect/");
+
+        let client = HttpClient::new(&LeakCheckConfig::default())?;
+        let result = block_on(run_dns_test(
+            &client, &DnsTestOptions::new(20), url, parse_dns_servers,
+        ))?;
+        assert_eq!(vec!["8.8.8.8".parse::<IpAddr>()?, "9.9.9.9".parse()?], result.servers);
+        assert_eq!((20, 15, 5), (result.requests, result.successes, result.errors.len()));
+        assert_eq!(0.75, result.success_ratio());
+
+        // None of the requests succeed
+        let options = DnsTestOptions::new(9);
+        let result = block_on(run_dns_test(
+            &client, &options, url, |_| Err(LeakError::NoConnectivity),
+        ));
+        assert!(matches!(result, Err(LeakError::TooFewDnsResults {
+            successes: 0, requests: 9, min_successes: 5, ..
+        })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_dns_test_deadline() -> LeakResult<()> {
+        // Connections are accepted by the system, but never answered
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let options = DnsTestOptions {
+            deadline: Duration::from_millis(50),
+            min_successes: 0,
+            ..DnsTestOptions::new(10)
+        };
+
+        let client = HttpClient::new(&LeakCheckConfig::default())?;
+        let start = std::time::Instant::now();
+        let result = block_on(run_dns_test(&client, &options, || url.clone(), parse_dns_servers))?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!((10, 0), (result.requests, result.successes));
+        // Requests that didn't finish aren't errors
+        assert!(result.errors.is_empty());
+
+        let options = DnsTestOptions {
+            min_successes: 5,
+            ..options
+        };
+        let result = block_on(run_dns_test(&client, &options, || url.clone(), parse_dns_servers));
+        assert_eq!(
+            "only 0 of 10 DNS leak test requests succeeded, at least 5 are required: the deadline \
+             elapsed",
+            result.unwrap_err().to_string()
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_generate_dns_leak_prefix() {
         // Running this test often ensures that we generate all available characters, but it will
@@ -189,68 +319,4 @@ mod tests {
                 .all(|x| x.is_ascii_digit() || x.is_ascii_lowercase()));
         }
     }
-
-    #[test]
-    fn test_get_dns_leak_test_config() {
-        assert_eq!(
-            DnsTestConfig {
-                total: 25,
-                requests_per_thread: 5,
-                amount_of_threads: 5,
-            },
-            get_dns_leak_test_config(25)
-        );
-
-        assert_eq!(
-            DnsTestConfig {
-                total: 100,
-                requests_per_thread: 5,
-                amount_of_threads: 20,
-            },
-            get_dns_leak_test_config(100)
-        );
-
-        assert_eq!(
-            DnsTestConfig {
-                total: 75,
-                requests_per_thread: 5,
-                amount_of_threads: 15,
-            },
-            get_dns_leak_test_config(74)
-        );
-        assert_eq!(
-            DnsTestConfig {
-                total: 80,
-                requests_per_thread: 5,
-                amount_of_threads: 16,
-            },
-            get_dns_leak_test_config(76)
-        );
-        assert_eq!(
-            DnsTestConfig {
-                total: 80,
-                requests_per_thread: 5,
-                amount_of_threads: 16,
-            },
-            get_dns_leak_test_config(79)
-        );
-
-        assert_eq!(
-            DnsTestConfig {
-                total: 0,
-                requests_per_thread: 5,
-                amount_of_threads: 0,
-            },
-            get_dns_leak_test_config(0)
-        );
-
-        assert_eq!(
-            DnsTestConfig {
-                total: 5,
-                requests_per_thread: 5,
-                amount_of_threads: 1,
-            },
-            get_dns_leak_test_config(1)
-        );
-    }
 }
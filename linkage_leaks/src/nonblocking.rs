@@ -0,0 +1,19 @@
+//! The async versions of the leak tests, which run on a tokio runtime. The blocking functions of
+//! the crate wrap them.
+
+use crate::error::LeakResult;
+use crate::{DnsTestOptions, IpInformation, IpleakProvider, LeakProvider};
+use std::net::IpAddr;
+
+/// Requests infos from a site that returns them in json format, parses them afterwards and then
+/// turns them into an instance of IpInformation. Uses ipleak.net.
+pub async fn ip_information() -> LeakResult<IpInformation> {
+    IpleakProvider::new().ip_information().await
+}
+
+/// Returns a list of all detected DNS servers. The supplied argument amount_of_requests determines
+/// how often the test will be run. Uses ipleak.net.
+pub async fn dns_test(amount_of_requests: u32) -> LeakResult<Vec<IpAddr>> {
+    let options = DnsTestOptions::new(amount_of_requests);
+    Ok(IpleakProvider::new().dns_test(&options).await?.servers)
+}
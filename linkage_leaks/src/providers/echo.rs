@@ -0,0 +1,79 @@
+//! Uses any URL that returns the IP address of the client as plain text.
+
+use super::{LeakFuture, LeakProvider};
+use crate::error::LeakResult;
+use crate::http::HttpClient;
+use crate::{AddressInformation, DnsTestOptions, DnsTestResult, IpInformation, LeakCheckConfig};
+
+/// Checks for IP leaks using a URL that returns the IP address as plain text, e.g.
+/// `https://am.i.mullvad.net/ip`. It can't detect the DNS servers and doesn't report where the
+/// address is located.
+pub struct EchoProvider {
+    url: String,
+    config: LeakCheckConfig,
+}
+
+impl EchoProvider {
+    /// Returns a new instance of EchoProvider that requests the supplied URL.
+    pub fn new(url: &str) -> EchoProvider {
+        EchoProvider {
+            url: url.to_string(),
+            config: LeakCheckConfig::default(),
+        }
+    }
+
+    /// Sets how the requests are sent.
+    pub fn config(mut self, config: LeakCheckConfig) -> EchoProvider {
+        self.config = config;
+        self
+    }
+}
+
+impl LeakProvider for EchoProvider {
+    /// Only the address that the URL returns is set, which is either an IPv4 or an IPv6 address
+    /// depending on the URL. The other one is `None`.
+    fn ip_information(&self) -> LeakFuture<'_, IpInformation> {
+        Box::pin(async move {
+            let client = HttpClient::new(&self.config)?;
+            parse_ip_information(&client.get_body(&self.url).await?)
+        })
+    }
+
+    fn dns_test<'a>(&'a self, _options: &'a DnsTestOptions) -> LeakFuture<'a, DnsTestResult> {
+        Box::pin(async { Ok(DnsTestResult::default()) })
+    }
+
+    fn supports_dns_test(&self) -> bool {
+        false
+    }
+}
+
+/// Turns the plain text response into an instance of IpInformation.
+fn parse_ip_information(body: &str) -> LeakResult<IpInformation> {
+    let address = AddressInformation::new(body.trim().parse()?);
+
+    IpInformation::new(
+        Some(address.clone()).filter(|a| a.ip.is_ipv4()),
+        Some(address).filter(|a| a.ip.is_ipv6()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_information() -> LeakResult<()> {
+        let infos = parse_ip_information("185.65.135.1\n")?;
+        assert_eq!(Some(AddressInformation::new("185.65.135.1".parse()?)), infos.ipv4);
+        assert_eq!(None, infos.ipv6);
+
+        let infos = parse_ip_information("2001:0db8::0001")?;
+        assert_eq!(None, infos.ipv4);
+        assert_eq!(Some(AddressInformation::new("2001:db8::1".parse()?)), infos.ipv6);
+
+        assert!(parse_ip_information("<html></html>").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! Uses the API of ipleak.net.
+
+use super::{LeakFuture, LeakProvider};
+use crate::error::LeakResult;
+use crate::http::HttpClient;
+use crate::{
+    generate_dns_leak_prefix, run_dns_test, AddressInformation, DnsTestOptions, DnsTestResult,
+    IpInformation, LeakCheckConfig,
+};
+use std::net::IpAddr;
+
+/// The site used for IPv4 leak checking.
+const IPV4_URL: &str = "https://ipv4.ipleak.net/json/";
+/// The site used for IPv6 leak checking.
+const IPV6_URL: &str = "https://ipv6.ipleak.net/json/";
+/// The site used for DNS leak checking.
+const DNS_URL: &str = "https://{prefix}.ipleak.net/dnsdetect/";
+/// The part of the DNS URL that is replaced with a random prefix for every request.
+const DNS_PREFIX_PLACEHOLDER: &str = "{prefix}";
+
+/// Checks for leaks using ipleak.net or a server with the same API.
+#[derive(Debug, Clone)]
+pub struct IpleakProvider {
+    ipv4_url: String,
+    ipv6_url: String,
+    dns_url: String,
+    config: LeakCheckConfig,
+}
+
+impl IpleakProvider {
+    /// Returns a new instance of IpleakProvider that uses ipleak.net.
+    pub fn new() -> IpleakProvider {
+        IpleakProvider::with_urls(IPV4_URL, IPV6_URL, DNS_URL)
+    }
+
+    /// Returns a new instance of IpleakProvider that uses the supplied URLs, e.g. of a local
+    /// server. `{prefix}` in the DNS URL is replaced with a random prefix for every request.
+    pub fn with_urls(ipv4_url: &str, ipv6_url: &str, dns_url: &str) -> IpleakProvider {
+        IpleakProvider {
+            ipv4_url: ipv4_url.to_string(),
+            ipv6_url: ipv6_url.to_string(),
+            dns_url: dns_url.to_string(),
+            config: LeakCheckConfig::default(),
+        }
+    }
+
+    /// Sets how the requests are sent.
+    pub fn config(mut self, config: LeakCheckConfig) -> IpleakProvider {
+        self.config = config;
+        self
+    }
+}
+
+impl Default for IpleakProvider {
+    fn default() -> IpleakProvider {
+        IpleakProvider::new()
+    }
+}
+
+impl LeakProvider for IpleakProvider {
+    /// An address is `None` if its site can't be reached. Both sites are requested at the same
+    /// time.
+    fn ip_information(&self) -> LeakFuture<'_, IpInformation> {
+        Box::pin(async move {
+            let client = HttpClient::new(&self.config)?;
+            let (ipv4, ipv6) = tokio::join!(
+                get_address_information(&client, &self.ipv4_url),
+                get_address_information(&client, &self.ipv6_url),
+            );
+            IpInformation::new(ipv4?, ipv6?)
+        })
+    }
+
+    /// Requests a random subdomain for every request, which returns the DNS server that resolved
+    /// it.
+    fn dns_test<'a>(&'a self, options: &'a DnsTestOptions) -> LeakFuture<'a, DnsTestResult> {
+        Box::pin(async move {
+            run_dns_test(
+                &HttpClient::new(&self.config)?,
+                options,
+                || self.dns_url.replace(DNS_PREFIX_PLACEHOLDER, &generate_dns_leak_prefix()),
+                parse_dns_server,
+            )
+            .await
+        })
+    }
+}
+
+/// Requests the supplied site of ipleak.net and parses the response. Returns `None` if the site
+/// can't be reached.
+async fn get_address_information(
+    client: &HttpClient,
+    url: &str,
+) -> LeakResult<Option<AddressInformation>> {
+    match client.get_optional_body(url).await? {
+        Some(body) => Ok(Some(serde_json::from_str(&body)?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the DNS server in a response of the DNS site.
+fn parse_dns_server(body: &str) -> LeakResult<Vec<IpAddr>> {
+    Ok(vec![body.trim().parse()?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LeakError;
+    use crate::block_on;
+    use crate::test_support::{MockLeakServer, MockResponses};
+
+    #[test]
+    fn test_ip_information() -> LeakResult<()> {
+        let server = MockLeakServer::start(MockResponses::default())?;
+        let infos = block_on(server.provider().ip_information())?;
+        let ipv4 = infos.ipv4.unwrap();
+        assert_eq!("1.1.1.1".parse::<IpAddr>()?, ipv4.ip);
+        assert_eq!(Some(String::from("DE")), ipv4.country_code);
+        assert_eq!(Some(String::from("Berlin")), ipv4.city_name);
+        assert_eq!("2001:db8::1".parse::<IpAddr>()?, infos.ipv6.unwrap().ip);
+
+        // A system without IPv6 connectivity
+        let server = MockLeakServer::start(MockResponses {
+            ipv6: None,
+            ..MockResponses::default()
+        })?;
+        let infos = block_on(server.provider().ip_information())?;
+        assert_eq!("1.1.1.1".parse::<IpAddr>()?, infos.ipv4.unwrap().ip);
+        assert_eq!(None, infos.ipv6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dns_test() -> LeakResult<()> {
+        let server = MockLeakServer::start(MockResponses {
+            dns_servers: vec!["9.9.9.9".parse()?, "2620:fe::fe".parse()?, "8.8.8.8".parse()?],
+            ..MockResponses::default()
+        })?;
+        let result = block_on(server.provider().dns_test(&DnsTestOptions::new(10)))?;
+        assert_eq!(
+            vec!["8.8.8.8".parse::<IpAddr>()?, "9.9.9.9".parse()?, "2620:fe::fe".parse()?],
+            result.servers
+        );
+        assert_eq!(10, result.successes);
+
+        // Every request uses another prefix
+        let mut requests = server.requests();
+        assert!(requests.iter().all(|r| r.ends_with("/dnsdetect/")));
+        requests.sort();
+        requests.dedup();
+        assert_eq!(10, requests.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dns_test_failing_requests() -> LeakResult<()> {
+        let server = MockLeakServer::start(MockResponses {
+            failing_dns_requests: 3,
+            ..MockResponses::default()
+        })?;
+        let result = block_on(server.provider().dns_test(&DnsTestOptions::new(10)))?;
+        assert_eq!(vec!["9.9.9.9".parse::<IpAddr>()?], result.servers);
+        assert_eq!(7, result.successes);
+        // The body of the 404 responses isn't an address
+        assert_eq!(3, result.errors.len());
+        assert!(matches!(result.errors[0], LeakError::AddrParseError(_)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! The services that can be used to check for leaks.
+
+mod echo;
+mod ipleak;
+mod mullvad;
+
+use crate::error::{LeakError, LeakResult};
+use crate::{DnsTestOptions, DnsTestResult, IpInformation, LeakCheckConfig};
+use std::future::Future;
+use std::pin::Pin;
+
+pub use echo::EchoProvider;
+pub use ipleak::IpleakProvider;
+pub use mullvad::MullvadProvider;
+
+/// The future that the methods of a LeakProvider return, so they can be used as a trait object.
+pub type LeakFuture<'a, T> = Pin<Box<dyn Future<Output = LeakResult<T>> + Send + 'a>>;
+
+/// A service that reports the public IP address and the DNS servers of the system.
+pub trait LeakProvider {
+    /// Returns information about the public IP address of the system.
+    fn ip_information(&self) -> LeakFuture<'_, IpInformation>;
+    /// Runs the DNS leak test, which returns the DNS servers that resolved the requests.
+    fn dns_test<'a>(&'a self, options: &'a DnsTestOptions) -> LeakFuture<'a, DnsTestResult>;
+    /// Returns whether the provider can detect the DNS servers. `dns_test` returns an empty result
+    /// without making any requests if it can't.
+    fn supports_dns_test(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the provider with the supplied name, which is `ipleak` or `mullvad`, that sends its
+/// requests as configured. An `http://` or `https://` URL returns an EchoProvider for it.
+pub fn get_provider(
+    name: &str,
+    config: LeakCheckConfig,
+) -> LeakResult<Box<dyn LeakProvider + Send + Sync>> {
+    match name {
+        "ipleak" => Ok(Box::new(IpleakProvider::new().config(config))),
+        "mullvad" => Ok(Box::new(MullvadProvider::new().config(config))),
+        url if url.starts_with("http://") || url.starts_with("https://") => {
+            Ok(Box::new(EchoProvider::new(url).config(config)))
+        }
+        _ => Err(LeakError::UnknownProvider(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_provider() {
+        assert!(get_provider("ipleak", LeakCheckConfig::default()).unwrap().supports_dns_test());
+        assert!(get_provider("mullvad", LeakCheckConfig::default()).unwrap().supports_dns_test());
+        assert!(!get_provider("https://am.i.mullvad.net/ip", LeakCheckConfig::default()).unwrap().supports_dns_test());
+        assert!(matches!(get_provider("ipleak.net", LeakCheckConfig::default()), Err(LeakError::UnknownProvider(_))));
+        assert!(matches!(get_provider("ftp://example.com", LeakCheckConfig::default()), Err(LeakError::UnknownProvider(_))));
+    }
+}
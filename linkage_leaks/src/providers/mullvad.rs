@@ -0,0 +1,147 @@
+//! Uses the API of am.i.mullvad.net.
+
+use super::{LeakFuture, LeakProvider};
+use crate::error::LeakResult;
+use crate::http::HttpClient;
+use crate::{
+    generate_dns_leak_prefix, run_dns_test, AddressInformation, DnsTestOptions, DnsTestResult,
+    IpInformation, LeakCheckConfig,
+};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// The site used for IPv4 leak checking.
+const IPV4_SITE: &str = "https://ipv4.am.i.mullvad.net/json";
+/// The site used for IPv6 leak checking.
+const IPV6_SITE: &str = "https://ipv6.am.i.mullvad.net/json";
+/// The domain whose random subdomains are used for DNS leak detection.
+const DNS_DOMAIN: &str = "dnsleak.am.i.mullvad.net";
+
+/// The response of the IP address API.
+#[derive(Deserialize, Debug)]
+struct IpResponse {
+    ip: IpAddr,
+    country: Option<String>,
+    city: Option<String>,
+}
+
+impl From<IpResponse> for AddressInformation {
+    fn from(response: IpResponse) -> AddressInformation {
+        AddressInformation {
+            country_code: response.country,
+            city_name: response.city,
+            ..AddressInformation::new(response.ip)
+        }
+    }
+}
+
+/// An entry of the response of the DNS leak API.
+#[derive(Deserialize, Debug)]
+struct DnsServerResponse {
+    ip: IpAddr,
+}
+
+/// Checks for leaks using am.i.mullvad.net. It reports the name of the country instead of a code
+/// and no region or continent.
+#[derive(Debug, Clone, Default)]
+pub struct MullvadProvider {
+    config: LeakCheckConfig,
+}
+
+impl MullvadProvider {
+    /// Returns a new instance of MullvadProvider.
+    pub fn new() -> MullvadProvider {
+        MullvadProvider::default()
+    }
+
+    /// Sets how the requests are sent.
+    pub fn config(mut self, config: LeakCheckConfig) -> MullvadProvider {
+        self.config = config;
+        self
+    }
+}
+
+impl LeakProvider for MullvadProvider {
+    /// An address is `None` if its site can't be reached. Both sites are requested at the same
+    /// time.
+    fn ip_information(&self) -> LeakFuture<'_, IpInformation> {
+        Box::pin(async move {
+            let client = HttpClient::new(&self.config)?;
+            let (ipv4, ipv6) = tokio::join!(
+                client.get_optional_body(IPV4_SITE),
+                client.get_optional_body(IPV6_SITE),
+            );
+            IpInformation::new(
+                parse_address_information(ipv4?.as_deref())?,
+                parse_address_information(ipv6?.as_deref())?,
+            )
+        })
+    }
+
+    /// Requests a random subdomain for every request, which returns all DNS servers that resolved
+    /// it.
+    fn dns_test<'a>(&'a self, options: &'a DnsTestOptions) -> LeakFuture<'a, DnsTestResult> {
+        Box::pin(async move {
+            run_dns_test(
+                &HttpClient::new(&self.config)?,
+                options,
+                || format!("https://{}.{}/", generate_dns_leak_prefix(), DNS_DOMAIN),
+                parse_dns_servers,
+            )
+            .await
+        })
+    }
+}
+
+/// Turns the response of the IPv4 or IPv6 site into an instance of AddressInformation. Returns
+/// `None` if the site couldn't be reached.
+fn parse_address_information(body: Option<&str>) -> LeakResult<Option<AddressInformation>> {
+    match body {
+        Some(body) => Ok(Some(serde_json::from_str::<IpResponse>(body)?.into())),
+        None => Ok(None),
+    }
+}
+
+/// Returns the DNS servers in a response of the DNS leak API.
+fn parse_dns_servers(body: &str) -> LeakResult<Vec<IpAddr>> {
+    let servers: Vec<DnsServerResponse> = serde_json::from_str(body)?;
+    Ok(servers.into_iter().map(|s| s.ip).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address_information() -> LeakResult<()> {
+        let infos = parse_address_information(Some(
+            r#"{"ip":"185.65.135.1","country":"Sweden","city":"Malmo","mullvad_exit_ip":true}"#,
+        ))?
+        .unwrap();
+        assert_eq!("185.65.135.1".parse::<IpAddr>()?, infos.ip);
+        assert_eq!(Some(String::from("Sweden")), infos.country_code);
+        assert_eq!(Some(String::from("Malmo")), infos.city_name);
+        assert_eq!(None, infos.region_code);
+
+        let infos = parse_address_information(Some(r#"{"ip":"2a03:1b20:1:f011::a01f"}"#))?;
+        assert_eq!("2a03:1b20:1:f011::a01f".parse::<IpAddr>()?, infos.unwrap().ip);
+
+        assert_eq!(None, parse_address_information(None)?);
+        assert!(parse_address_information(Some("{}")).is_err());
+        assert!(parse_address_information(Some(r#"{"ip":"Malmo"}"#)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dns_servers() -> LeakResult<()> {
+        let servers = parse_dns_servers(
+            r#"[{"ip":"193.138.218.74","hostname":"se-mma-dns-001"},{"ip":"2001:db8::1"}]"#,
+        )?;
+        assert_eq!(vec!["193.138.218.74".parse::<IpAddr>()?, "2001:db8::1".parse()?], servers);
+        assert!(parse_dns_servers("[]")?.is_empty());
+        assert!(parse_dns_servers(r#"[{"ip":"not an address"}]"#).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Evaluates whether the results of the leak tests while connected leak anything compared to the
+//! results from before connecting.
+
+use crate::error::LeakResult;
+use crate::{block_on, DnsTestOptions, DnsTestResult, IpInformation, LeakProvider};
+use std::fmt;
+use std::net::IpAddr;
+
+/// The length of the prefix that identifies the network of an IPv6 address. The interface
+/// identifier of temporary addresses changes regularly, but the network stays the same.
+const IPV6_NETWORK_PREFIX_LENGTH: u32 = 64;
+
+/// The results of the leak tests at one point in time.
+#[derive(Debug)]
+pub struct LeakTestResults {
+    pub ip_information: IpInformation,
+    /// `None` if the provider can't detect the DNS servers.
+    pub dns_test: Option<DnsTestResult>,
+}
+
+impl LeakTestResults {
+    /// Runs the leak tests using the supplied provider and DNS leak test options.
+    pub async fn run_async(
+        provider: &dyn LeakProvider,
+        dns_options: &DnsTestOptions,
+    ) -> LeakResult<LeakTestResults> {
+        Ok(LeakTestResults {
+            ip_information: provider.ip_information().await?,
+            dns_test: match provider.supports_dns_test() {
+                true => Some(provider.dns_test(dns_options).await?),
+                false => None,
+            },
+        })
+    }
+
+    /// Blocking version of `run_async`.
+    pub fn run(
+        provider: &dyn LeakProvider,
+        dns_options: &DnsTestOptions,
+    ) -> LeakResult<LeakTestResults> {
+        block_on(LeakTestResults::run_async(provider, dns_options))
+    }
+
+    /// Returns the detected DNS servers, which are empty if the provider can't detect them.
+    pub fn dns_servers(&self) -> &[IpAddr] {
+        self.dns_test.as_ref().map_or(&[], |t| t.servers.as_slice())
+    }
+}
+
+/// The leaks that were found by comparing the results of the leak tests while connected with the
+/// ones from before connecting. Every leak contains the addresses that were leaked.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LeakReport {
+    /// The public IPv4 address is the same as before connecting.
+    pub ipv4: Option<IpAddr>,
+    /// The public IPv6 address is in the same network as before connecting.
+    pub ipv6: Option<IpAddr>,
+    /// The DNS servers that were used before connecting and are still used.
+    pub dns_servers: Vec<IpAddr>,
+}
+
+impl LeakReport {
+    /// Compares the results of the leak tests while connected with the ones from before
+    /// connecting. An address family that is missing in either of the results can't leak, e.g. if
+    /// the system has no IPv6 connectivity or the firewall blocks it.
+    pub fn new(before: &LeakTestResults, after: &LeakTestResults) -> LeakReport {
+        let ipv4 = |r: &LeakTestResults| r.ip_information.ipv4.as_ref().map(|a| a.ip);
+        let ipv6 = |r: &LeakTestResults| r.ip_information.ipv6.as_ref().map(|a| a.ip);
+
+        LeakReport {
+            ipv4: ipv4(after).filter(|a| ipv4(before) == Some(*a)),
+            ipv6: ipv6(after).filter(|a| ipv6(before).is_some_and(|b| is_same_network(a, &b))),
+            dns_servers: after
+                .dns_servers()
+                .iter()
+                .filter(|a| before.dns_servers().contains(a))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Returns whether anything is leaked.
+    pub fn is_leaking(&self) -> bool {
+        self.ipv4.is_some() || self.ipv6.is_some() || !self.dns_servers.is_empty()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    /// Lists the leaks with the leaked addresses, e.g. `IPv4 leak (1.1.1.1), DNS leak (9.9.9.9)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut leaks = Vec::new();
+        if let Some(address) = self.ipv4 {
+            leaks.push(format!("IPv4 leak ({})", address));
+        }
+        if let Some(address) = self.ipv6 {
+            leaks.push(format!("IPv6 leak ({})", address));
+        }
+        if !self.dns_servers.is_empty() {
+            let addresses: Vec<String> = self.dns_servers.iter().map(IpAddr::to_string).collect();
+            leaks.push(format!("DNS leak ({})", addresses.join(", ")));
+        }
+
+        match leaks.is_empty() {
+            true => write!(f, "no leaks"),
+            false => write!(f, "{}", leaks.join(", ")),
+        }
+    }
+}
+
+/// Returns whether the supplied IPv6 addresses are in the same network.
+fn is_same_network(a: &IpAddr, b: &IpAddr) -> bool {
+    match (a, b) {
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = u128::MAX << (128 - IPV6_NETWORK_PREFIX_LENGTH);
+            u128::from(*a) & mask == u128::from(*b) & mask
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AddressInformation;
+
+    /// Returns LeakTestResults with the supplied addresses, an empty address is `None`.
+    fn results(ipv4: &str, ipv6: &str, dns_servers: &[&str]) -> LeakTestResults {
+        let address = |ip: &str| match ip {
+            "" => None,
+            ip => Some(AddressInformation::new(ip.parse().unwrap())),
+        };
+        LeakTestResults {
+            ip_information: IpInformation {
+                ipv4: address(ipv4),
+                ipv6: address(ipv6),
+            },
+            dns_test: Some(DnsTestResult {
+                servers: dns_servers.iter().map(|a| a.parse().unwrap()).collect(),
+                ..DnsTestResult::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_leak_report_new() {
+        let before = results("1.1.1.1", "2001:db8:1:1::1", &["9.9.9.9", "8.8.8.8"]);
+
+        let after = results("2.2.2.2", "2001:db8:2:1::1", &["10.8.0.1"]);
+        let report = LeakReport::new(&before, &after);
+        assert!(!report.is_leaking());
+        assert_eq!(LeakReport::default(), report);
+
+        assert_eq!(LeakReport {
+            ipv4: Some("1.1.1.1".parse().unwrap()),
+            ..LeakReport::default()
+        }, LeakReport::new(&before, &results("1.1.1.1", "2001:db8:2:1::1", &[])));
+
+        // Temporary IPv6 addresses change within the same network
+        assert_eq!(LeakReport {
+            ipv6: Some("2001:db8:1:1::2".parse().unwrap()),
+            ..LeakReport::default()
+        }, LeakReport::new(&before, &results("2.2.2.2", "2001:db8:1:1::2", &[])));
+
+        assert_eq!(LeakReport {
+            dns_servers: vec!["8.8.8.8".parse().unwrap()],
+            ..LeakReport::default()
+        }, LeakReport::new(&before, &results("2.2.2.2", "", &["10.8.0.1", "8.8.8.8"])));
+    }
+
+    #[test]
+    fn test_leak_report_missing_addresses() {
+        // Two missing addresses aren't the same address
+        let before = results("1.1.1.1", "", &[]);
+        assert!(!LeakReport::new(&before, &results("2.2.2.2", "", &[])).is_leaking());
+        // The IPv6 address can only be the VPN's, since there was none before
+        assert!(!LeakReport::new(&before, &results("2.2.2.2", "2001:db8::1", &[])).is_leaking());
+
+        let before = results("", "2001:db8::1", &[]);
+        assert!(!LeakReport::new(&before, &results("1.1.1.1", "", &[])).is_leaking());
+        assert!(LeakReport::new(&before, &results("", "2001:db8::1", &[])).is_leaking());
+    }
+
+    #[test]
+    fn test_leak_report_display() {
+        assert_eq!("no leaks", LeakReport::default().to_string());
+        assert_eq!(
+            "IPv4 leak (1.1.1.1), IPv6 leak (2001:db8::1), DNS leak (9.9.9.9, 8.8.8.8)",
+            LeakReport {
+                ipv4: Some("1.1.1.1".parse().unwrap()),
+                ipv6: Some("2001:db8::1".parse().unwrap()),
+                dns_servers: vec!["9.9.9.9".parse().unwrap(), "8.8.8.8".parse().unwrap()],
+            }
+            .to_string()
+        );
+    }
+}
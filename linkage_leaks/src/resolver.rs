@@ -0,0 +1,416 @@
+//! Audits the resolver configuration of the system without sending any DNS requests. This finds
+//! DNS servers that are configured outside of the tunnel even if they weren't used during the DNS
+//! leak test.
+
+use crate::error::LeakResult;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+
+/// The resolver configuration that is used by the C library.
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+/// The runtime directory of systemd-resolved. It contains `resolv.conf` with all upstream DNS
+/// servers and `netif/<index>` with the DNS servers of every link.
+const RESOLVED_RUNTIME_PATH: &str = "/run/systemd/resolve";
+/// The addresses of the stub resolvers of systemd-resolved.
+const RESOLVED_STUB_ADDRESSES: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(127, 0, 0, 53), Ipv4Addr::new(127, 0, 0, 54)];
+/// The port of DNS servers, which is used to look up the route to them.
+const DNS_PORT: u16 = 53;
+
+/// The interface of the tunnel, through which all DNS servers should be reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TunnelInterface {
+    pub name: String,
+    pub index: u32,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// Where a DNS server is configured.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NameserverSource {
+    /// `/etc/resolv.conf`, which is used directly without a local resolver.
+    ResolvConf,
+    /// The global configuration of systemd-resolved.
+    ResolvedGlobal,
+    /// The configuration of systemd-resolved for the link with the supplied index. Its servers are
+    /// always queried through that link.
+    ResolvedLink(u32),
+}
+
+impl fmt::Display for NameserverSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameserverSource::ResolvConf => write!(f, "{}", RESOLV_CONF_PATH),
+            NameserverSource::ResolvedGlobal => write!(f, "the global systemd-resolved settings"),
+            NameserverSource::ResolvedLink(index) => write!(f, "systemd-resolved link {}", index),
+        }
+    }
+}
+
+/// A DNS server of the resolver configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nameserver {
+    pub address: IpAddr,
+    /// The interface name or index after the `%` of a scoped IPv6 address.
+    pub scope: Option<String>,
+    pub source: NameserverSource,
+}
+
+/// A problem of the resolver configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolverFinding {
+    /// The DNS server is reached through another interface than the tunnel.
+    OutsideTunnel(Nameserver),
+    /// A local resolver other than systemd-resolved is used, so the servers it forwards the
+    /// requests to can't be checked.
+    UnknownLocalResolver(IpAddr),
+    /// The stub resolver of systemd-resolved is used, but its runtime configuration is missing.
+    ResolvedUnavailable,
+}
+
+impl fmt::Display for ResolverFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolverFinding::OutsideTunnel(nameserver) => write!(
+                f,
+                "the DNS server {} from {} is reached outside of the tunnel",
+                nameserver.address, nameserver.source
+            ),
+            ResolverFinding::UnknownLocalResolver(address) => {
+                write!(f, "the local DNS resolver {} can't be checked", address)
+            }
+            ResolverFinding::ResolvedUnavailable => {
+                write!(f, "systemd-resolved is used, but its configuration can't be read")
+            }
+        }
+    }
+}
+
+/// The result of auditing the resolver configuration.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResolverAudit {
+    /// `/etc/resolv.conf` points to the stub resolver of systemd-resolved.
+    pub stub_mode: bool,
+    /// The DNS servers that are used, which are the upstream servers of systemd-resolved in stub
+    /// mode.
+    pub nameservers: Vec<Nameserver>,
+    pub findings: Vec<ResolverFinding>,
+}
+
+impl ResolverAudit {
+    /// Audits the resolver configuration of the system for the supplied tunnel.
+    pub fn run(tunnel: &TunnelInterface) -> LeakResult<ResolverAudit> {
+        ResolverAudit::run_with_paths(
+            tunnel,
+            Path::new(RESOLV_CONF_PATH),
+            Path::new(RESOLVED_RUNTIME_PATH),
+        )
+    }
+
+    /// Audits the resolver configuration in the supplied `resolv.conf` and runtime directory of
+    /// systemd-resolved. Routes are looked up on the system without sending anything.
+    pub fn run_with_paths(
+        tunnel: &TunnelInterface,
+        resolv_conf_path: &Path,
+        resolved_path: &Path,
+    ) -> LeakResult<ResolverAudit> {
+        let mut audit = ResolverAudit::default();
+        for nameserver in parse_resolv_conf(&fs::read_to_string(resolv_conf_path)?) {
+            match nameserver.address {
+                IpAddr::V4(address) if RESOLVED_STUB_ADDRESSES.contains(&address) => {
+                    audit.stub_mode = true;
+                }
+                _ => audit.nameservers.push(Nameserver {
+                    source: NameserverSource::ResolvConf,
+                    ..nameserver
+                }),
+            }
+        }
+
+        if audit.stub_mode {
+            match read_resolved_nameservers(resolved_path)? {
+                Some(nameservers) => audit.nameservers.extend(nameservers),
+                None => audit.findings.push(ResolverFinding::ResolvedUnavailable),
+            }
+        }
+
+        let findings = evaluate(tunnel, &audit.nameservers, route_source_address);
+        audit.findings.extend(findings);
+        Ok(audit)
+    }
+
+    /// Returns whether a DNS server might be reached outside of the tunnel.
+    pub fn is_leaking(&self) -> bool {
+        !self.findings.is_empty()
+    }
+}
+
+/// Parses the `nameserver` lines of a `resolv.conf` file. Their source is `ResolvConf`.
+fn parse_resolv_conf(content: &str) -> Vec<Nameserver> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(address)) => {
+                    parse_nameserver(address, NameserverSource::ResolvConf)
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Parses an address like `10.8.0.1`, `fe80::1%eth0` or the `1.1.1.1:53#cloudflare-dns.com`
+/// format of systemd-resolved. Returns `None` if it's invalid.
+fn parse_nameserver(address: &str, source: NameserverSource) -> Option<Nameserver> {
+    // The server name is only used to verify the certificate for DNS over TLS
+    let address = address.split('#').next().unwrap_or_default();
+    let (address, scope) = match address.split_once('%') {
+        Some((address, scope)) => (address, Some(scope.to_string())),
+        None => (address, None),
+    };
+    let address = address
+        .parse()
+        .or_else(|_| address.parse::<SocketAddr>().map(|a| a.ip()))
+        .ok()?;
+
+    Some(Nameserver { address, scope, source })
+}
+
+/// Reads the upstream DNS servers of systemd-resolved. Servers that are configured for a link
+/// have the source `ResolvedLink`, all others are global. Returns `None` if systemd-resolved
+/// isn't running.
+fn read_resolved_nameservers(resolved_path: &Path) -> LeakResult<Option<Vec<Nameserver>>> {
+    let upstream = match fs::read_to_string(resolved_path.join("resolv.conf")) {
+        Ok(content) => parse_resolv_conf(&content),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut nameservers = read_link_nameservers(&resolved_path.join("netif"))?;
+    for nameserver in upstream {
+        if !nameservers.iter().any(|n| n.address == nameserver.address) {
+            nameservers.push(Nameserver {
+                source: NameserverSource::ResolvedGlobal,
+                ..nameserver
+            });
+        }
+    }
+
+    Ok(Some(nameservers))
+}
+
+/// Reads the `SERVERS=` lines of the link state files of systemd-resolved, which are named after
+/// the index of the link.
+fn read_link_nameservers(netif_path: &Path) -> LeakResult<Vec<Nameserver>> {
+    let entries = match fs::read_dir(netif_path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+
+    let mut nameservers = Vec::new();
+    for path in paths {
+        let index = match path.file_name().and_then(|n| n.to_str()).map(str::parse) {
+            Some(Ok(index)) => index,
+            _ => continue,
+        };
+        let content = fs::read_to_string(&path)?;
+        let servers = content.lines().filter_map(|line| line.strip_prefix("SERVERS="));
+        for address in servers.flat_map(str::split_whitespace) {
+            nameservers.extend(parse_nameserver(address, NameserverSource::ResolvedLink(index)));
+        }
+    }
+
+    Ok(nameservers)
+}
+
+/// Returns the local address that the system would use to reach the supplied DNS server, or
+/// `None` if there is no route to it. Connecting a UDP socket doesn't send anything.
+fn route_source_address(address: IpAddr) -> Option<IpAddr> {
+    let local_address: IpAddr = match address {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local_address, 0)).ok()?;
+    socket.connect((address, DNS_PORT)).ok()?;
+    socket.local_addr().ok().map(|a| a.ip())
+}
+
+/// Returns the findings for the supplied DNS servers. The supplied function returns the local
+/// address of the route to a server. Servers without a route can't be reached at all, so they
+/// don't leak.
+fn evaluate<R>(
+    tunnel: &TunnelInterface,
+    nameservers: &[Nameserver],
+    route_source_address: R,
+) -> Vec<ResolverFinding>
+where
+    R: Fn(IpAddr) -> Option<IpAddr>,
+{
+    let mut findings = Vec::new();
+    for nameserver in nameservers {
+        let through_tunnel = if let Some(scope) = &nameserver.scope {
+            *scope == tunnel.name || *scope == tunnel.index.to_string()
+        } else if let NameserverSource::ResolvedLink(index) = nameserver.source {
+            index == tunnel.index
+        } else if nameserver.address.is_loopback() {
+            findings.push(ResolverFinding::UnknownLocalResolver(nameserver.address));
+            continue;
+        } else {
+            match route_source_address(nameserver.address) {
+                Some(source) => tunnel.addresses.contains(&source),
+                None => true,
+            }
+        };
+
+        if !through_tunnel {
+            findings.push(ResolverFinding::OutsideTunnel(nameserver.clone()));
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Returns a tunnel with the supplied index and the address 10.8.0.2.
+    fn tunnel(index: u32) -> TunnelInterface {
+        TunnelInterface {
+            name: String::from("tun0"),
+            index,
+            addresses: vec!["10.8.0.2".parse().unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_parse_resolv_conf() -> LeakResult<()> {
+        let content = "# Generated by NetworkManager\nsearch lan\nnameserver 192.168.1.1\n\
+                       nameserver fe80::1%eth0\n; nameserver 8.8.8.8\nnameserver invalid\n\
+                       options edns0\n";
+        assert_eq!(vec![
+            Nameserver {
+                address: "192.168.1.1".parse()?,
+                scope: None,
+                source: NameserverSource::ResolvConf,
+            },
+            Nameserver {
+                address: "fe80::1".parse()?,
+                scope: Some(String::from("eth0")),
+                source: NameserverSource::ResolvConf,
+            },
+        ], parse_resolv_conf(content));
+
+        let nameserver =
+            parse_nameserver("1.1.1.1:853#cloudflare-dns.com", NameserverSource::ResolvedGlobal);
+        assert_eq!(Some("1.1.1.1".parse()?), nameserver.map(|n| n.address));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate() -> LeakResult<()> {
+        let nameservers: Vec<Nameserver> = vec![
+            ("10.8.0.1", None, NameserverSource::ResolvConf),
+            ("192.168.1.1", None, NameserverSource::ResolvConf),
+            ("fe80::1", Some("eth0"), NameserverSource::ResolvConf),
+            ("fe80::2", Some("7"), NameserverSource::ResolvConf),
+            ("127.0.0.1", None, NameserverSource::ResolvConf),
+            ("9.9.9.9", None, NameserverSource::ResolvedLink(7)),
+            ("8.8.8.8", None, NameserverSource::ResolvedLink(2)),
+            ("203.0.113.1", None, NameserverSource::ResolvedGlobal),
+        ]
+        .into_iter()
+        .map(|(address, scope, source)| Nameserver {
+            address: address.parse().unwrap(),
+            scope: scope.map(String::from),
+            source,
+        })
+        .collect();
+
+        // Only 10.8.0.1 is routed through the tunnel and there's no route to 203.0.113.1
+        let route = |address: IpAddr| match address.to_string().as_str() {
+            "10.8.0.1" => Some("10.8.0.2".parse().unwrap()),
+            "203.0.113.1" => None,
+            _ => Some("192.168.1.100".parse().unwrap()),
+        };
+        assert_eq!(vec![
+            ResolverFinding::OutsideTunnel(nameservers[1].clone()),
+            ResolverFinding::OutsideTunnel(nameservers[2].clone()),
+            ResolverFinding::UnknownLocalResolver("127.0.0.1".parse()?),
+            ResolverFinding::OutsideTunnel(nameservers[6].clone()),
+        ], evaluate(&tunnel(7), &nameservers, route));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolver_audit_run_with_paths() -> LeakResult<()> {
+        let directory = env::temp_dir().join(format!("linkage-resolver-test-{}", process::id()));
+        let resolv_conf_path = directory.join("resolv.conf");
+        let resolved_path = directory.join("resolve");
+        fs::create_dir_all(resolved_path.join("netif"))?;
+
+        // Stub mode without systemd-resolved running
+        fs::write(&resolv_conf_path, "nameserver 127.0.0.53\noptions edns0 trust-ad\n")?;
+        let audit = ResolverAudit::run_with_paths(
+            &tunnel(7), &resolv_conf_path, &directory.join("missing"),
+        )?;
+        assert!(audit.stub_mode);
+        assert_eq!(vec![ResolverFinding::ResolvedUnavailable], audit.findings);
+
+        // Only the tunnel has DNS servers
+        fs::write(resolved_path.join("resolv.conf"), "nameserver 10.8.0.1\n")?;
+        fs::write(resolved_path.join("netif/7"), "LLMNR=no\nSERVERS=10.8.0.1\nDOMAINS=~.\n")?;
+        fs::write(resolved_path.join("netif/2"), "LLMNR=yes\nMDNS=no\n")?;
+        let audit = ResolverAudit::run_with_paths(&tunnel(7), &resolv_conf_path, &resolved_path)?;
+        assert_eq!(vec![Nameserver {
+            address: "10.8.0.1".parse()?,
+            scope: None,
+            source: NameserverSource::ResolvedLink(7),
+        }], audit.nameservers);
+        assert!(!audit.is_leaking());
+
+        // The DNS server of another link is still used
+        fs::write(
+            resolved_path.join("resolv.conf"), "nameserver 10.8.0.1\nnameserver 192.168.1.1\n",
+        )?;
+        fs::write(resolved_path.join("netif/2"), "SERVERS=192.168.1.1\n")?;
+        let audit = ResolverAudit::run_with_paths(&tunnel(7), &resolv_conf_path, &resolved_path)?;
+        assert_eq!(vec![ResolverFinding::OutsideTunnel(Nameserver {
+            address: "192.168.1.1".parse()?,
+            scope: None,
+            source: NameserverSource::ResolvedLink(2),
+        })], audit.findings);
+        assert_eq!(
+            "the DNS server 192.168.1.1 from systemd-resolved link 2 is reached outside of the \
+             tunnel",
+            audit.findings[0].to_string()
+        );
+
+        // A local resolver without systemd-resolved
+        fs::write(&resolv_conf_path, "nameserver 127.0.0.1\n")?;
+        let audit = ResolverAudit::run_with_paths(&tunnel(7), &resolv_conf_path, &resolved_path)?;
+        assert!(!audit.stub_mode);
+        assert_eq!(
+            vec![ResolverFinding::UnknownLocalResolver("127.0.0.1".parse()?)],
+            audit.findings
+        );
+
+        fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
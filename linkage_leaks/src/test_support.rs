@@ -0,0 +1,204 @@
+//! A local HTTP server that emulates the API of ipleak.net, so the leak tests can be tested without
+//! a network connection. Only available in tests and with the `test-support` feature.
+
+use crate::error::LeakResult;
+use crate::IpleakProvider;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// The path of the IPv4 site.
+const IPV4_PATH: &str = "/ipv4/json/";
+/// The path of the IPv6 site.
+const IPV6_PATH: &str = "/ipv6/json/";
+/// The path of the DNS site. The prefix is part of the path instead of the host name, since there
+/// are no subdomains of a local address.
+const DNS_PATH: &str = "/{prefix}/dnsdetect/";
+/// How the paths of requests to the DNS site end.
+const DNS_PATH_SUFFIX: &str = "/dnsdetect/";
+/// A URL that can't be connected to, since nothing can listen on port 0.
+const UNREACHABLE_URL: &str = "http://127.0.0.1:0/";
+
+/// What the server responds with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockResponses {
+    /// The address returned by the IPv4 site.
+    pub ipv4: IpAddr,
+    /// The address returned by the IPv6 site. Providers that are created while this is `None` can't
+    /// reach the IPv6 site, like on a system without IPv6 connectivity.
+    pub ipv6: Option<IpAddr>,
+    /// The DNS servers returned by the DNS site, one per request in turn. The site responds with
+    /// `404 Not Found` if this is empty.
+    pub dns_servers: Vec<IpAddr>,
+    /// How many of the first requests to the DNS site fail with `404 Not Found`.
+    pub failing_dns_requests: u32,
+}
+
+impl Default for MockResponses {
+    fn default() -> MockResponses {
+        MockResponses {
+            ipv4: IpAddr::from([1, 1, 1, 1]),
+            ipv6: Some(IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1])),
+            dns_servers: vec![IpAddr::from([9, 9, 9, 9])],
+            failing_dns_requests: 0,
+        }
+    }
+}
+
+/// The state that is shared with the thread of the server.
+struct ServerState {
+    responses: Mutex<MockResponses>,
+    /// The paths of all requests, in order.
+    requests: Mutex<Vec<String>>,
+    running: AtomicBool,
+}
+
+/// Emulates ipleak.net on a random local port until it's dropped.
+pub struct MockLeakServer {
+    address: SocketAddr,
+    state: Arc<ServerState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockLeakServer {
+    /// Starts the server with the supplied responses.
+    pub fn start(responses: MockResponses) -> LeakResult<MockLeakServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(ServerState {
+            responses: Mutex::new(responses),
+            requests: Mutex::new(Vec::new()),
+            running: AtomicBool::new(true),
+        });
+
+        let thread_state = state.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !thread_state.running.load(Ordering::SeqCst) {
+                    break;
+                }
+                // A broken connection only fails the request of the client
+                if let Ok(stream) = stream {
+                    let _ = handle_connection(stream, &thread_state);
+                }
+            }
+        });
+
+        Ok(MockLeakServer {
+            address,
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the URL of the supplied path on the server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    /// Returns an IpleakProvider that uses the server. The IPv6 site can't be reached if the
+    /// current responses don't contain an IPv6 address.
+    pub fn provider(&self) -> IpleakProvider {
+        let ipv6_url = match self.state.responses.lock().unwrap().ipv6 {
+            Some(_) => self.url(IPV6_PATH),
+            None => String::from(UNREACHABLE_URL),
+        };
+        IpleakProvider::with_urls(&self.url(IPV4_PATH), &ipv6_url, &self.url(DNS_PATH))
+    }
+
+    /// Changes the responses of the following requests.
+    pub fn set_responses(&self, responses: MockResponses) {
+        *self.state.responses.lock().unwrap() = responses;
+    }
+
+    /// Returns the paths of all requests so far, in order.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockLeakServer {
+    fn drop(&mut self) {
+        self.state.running.store(false, Ordering::SeqCst);
+        // Wakes up the server, so it notices that it has to stop
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers a single request and closes the connection afterwards.
+fn handle_connection(mut stream: TcpStream, state: &ServerState) -> LeakResult<()> {
+    // Only GET requests without a body are sent, so the request ends with an empty line
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&chunk[..read]);
+    }
+
+    // The request line looks like `GET /ipv4/json/ HTTP/1.1`
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+    let body = get_response_body(&path, state);
+    state.requests.lock().unwrap().push(path);
+
+    let response = match body {
+        Some(body) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+        None => String::from(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ),
+    };
+    stream.write_all(response.as_bytes())?;
+    stream.shutdown(Shutdown::Both)?;
+
+    Ok(())
+}
+
+/// Returns the body of the response to the supplied path, or `None` if there is nothing to
+/// respond with.
+fn get_response_body(path: &str, state: &ServerState) -> Option<String> {
+    let responses = state.responses.lock().unwrap();
+    match path {
+        IPV4_PATH => Some(get_ip_information_body(responses.ipv4)),
+        IPV6_PATH => responses.ipv6.map(get_ip_information_body),
+        path if path.ends_with(DNS_PATH_SUFFIX) => {
+            // The DNS servers are returned in turn, so the requests before this one are counted
+            let previous = state
+                .requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.ends_with(DNS_PATH_SUFFIX))
+                .count();
+            let servers = &responses.dns_servers;
+            match previous < responses.failing_dns_requests as usize {
+                true => None,
+                false => servers.get(previous % servers.len().max(1)).map(IpAddr::to_string),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the body of the IPv4 and IPv6 sites for the supplied address.
+fn get_ip_information_body(ip: IpAddr) -> String {
+    serde_json::json!({
+        "country_code": "DE",
+        "region_code": "BE",
+        "continent_code": "EU",
+        "city_name": "Berlin",
+        "ip": ip.to_string(),
+    })
+    .to_string()
+}